@@ -1,8 +1,11 @@
+#[cfg(windows)]
 extern crate winapi;
+#[cfg(windows)]
 extern crate winres;
 
 fn main() {
-    if cfg!(target_os = "windows") {
+    #[cfg(windows)]
+    {
         let mut res = winres::WindowsResource::new();
         res.set_icon("kp_temp.ico");
         res.set_language(winapi::um::winnt::MAKELANGID(
@@ -14,7 +17,7 @@ fn main() {
 <trustInfo xmlns="urn:schemas-microsoft-com:asm.v3">
     <security>
         <requestedPrivileges>
-            <requestedExecutionLevel level="requireAdministrator" uiAccess="false" />
+            <requestedExecutionLevel level="asInvoker" uiAccess="false" />
         </requestedPrivileges>
     </security>
 </trustInfo>
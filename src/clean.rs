@@ -1,33 +1,49 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env::{var, var_os};
 use std::ffi::{OsStr, OsString};
-use std::fs::{File, Permissions, remove_dir_all, remove_file, set_permissions, symlink_metadata};
-use std::io::{Error, Write};
-use std::os::windows::ffi::OsStrExt;
-use std::path::{Path, PathBuf};
-use std::ptr::null;
+use std::fs::{File, OpenOptions, read_dir, symlink_metadata};
+use std::io::{Error, ErrorKind, Write};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
 use std::thread;
-use std::time::Duration;
-use std::usize::MAX as MAX_DEPTH;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use chrono::{DateTime, Local};
 use pretty_bytes::converter::convert;
-use walkdir::WalkDir;
-use winapi::um::winbase::{MOVEFILE_DELAY_UNTIL_REBOOT, MoveFileExW};
 
-use crate::globals::{KPTEMP_VERSION, LABEL_HANDLE, PROGRESS_HANDLE, TOTAL_STEP};
-use crate::gui::progress_bar::advance_progress_bar;
-use crate::gui::windows_helper::set_window_text;
+use crate::deferred::{append, Deferred, Outcome, settle, WHEN};
+use crate::globals::KPTEMP_VERSION;
 use crate::Ignore;
-use crate::privilege::adjust_privilege;
-use crate::utils::{data_recycle_bin, empty_recycle_bin, error_box, message_box, restart};
+use crate::privilege::{Privileges, SE_DEBUG, SE_RESTORE, SE_SHUTDOWN};
+use crate::profiles::{AccountKind, Profile, ProfileSource, SystemProfiles, UserScope};
+use crate::progress;
+use crate::quarantine::Quarantine;
+use crate::process::{close_applications, OpenFile, OpenFiles, ProcessProvider, running_applications, SystemProvider, Termination};
+use crate::rules::{
+    AgeBy, Entry, glob_file_rules, has_wildcards, Import, kept_rules, load_cleanerml, load_winapp2, name_matches, path_matches, profile_dirs, PROFILE_VARS, profile_rules,
+    Rule, rule_apps, Stamps, walk_all_rules, windows_old_rules,
+};
+use crate::secure::create_dir;
+use crate::safe_remove::{Disposal, Fingerprint, Kind, Policy};
+use crate::settings::Settings;
+use crate::shred::{Shred, SSD_WARNING};
+use crate::trash::{select, SystemTrash, TrashBackend, TrashItem, TrashPolicy, TrashVolume};
+use crate::utils::{confirm_box, error_box, message_box, restart};
+use crate::vfs::{self, Disk, Filesystem, Stat};
 
 // TODO Please Refactor ME
 
+// Where the profiles of a reference layout live, one directory per user
+#[cfg(windows)]
+const HOMES: &str = "Users";
+#[cfg(unix)]
+const HOMES: &str = "home";
+
 #[derive(PartialEq, Eq)]
 enum DeletionType {
     Clear,
-    Extension,
+    Files,
 }
 
 struct TempComponent {
@@ -36,9 +52,29 @@ struct TempComponent {
     len: u64,
     depth: usize,
     min_depth: usize,
-    extension: &'static OsStr,
+    files: String,
     deletion_type: DeletionType,
     need_reboot: bool,
+    links: Vec<PathBuf>,
+    scanned: HashMap<PathBuf, Fingerprint>,
+    changed: Vec<PathBuf>,
+    // Entries neither removed nor scheduled, with the reason
+    failed: Vec<(PathBuf, String)>,
+    excluded: Vec<PathBuf>,
+    // Directories that stay while their content goes
+    kept_dirs: Vec<PathBuf>,
+    min_age: Option<Duration>,
+    age_by: AgeBy,
+    shred: bool,
+    shredded: u64,
+    // Moved to a quarantine on another volume by a copy
+    copied: u64,
+    retried: u64,
+    // Files other processes held when the clean reached the component, the
+    // skipped ones have left `scanned`
+    open: Vec<OpenFile>,
+    owner: Option<String>,
+    rule: Option<String>,
 }
 
 impl TempComponent {
@@ -47,623 +83,2487 @@ impl TempComponent {
             path,
             size: 0,
             len: 0,
-            extension: "".as_ref(),
-            depth: MAX_DEPTH,
+            files: String::new(),
+            depth: usize::MAX,
             min_depth: 1,
             deletion_type: DeletionType::Clear,
             need_reboot: false,
+            links: Vec::new(),
+            scanned: HashMap::new(),
+            changed: Vec::new(),
+            failed: Vec::new(),
+            excluded: Vec::new(),
+            kept_dirs: Vec::new(),
+            min_age: None,
+            age_by: AgeBy::default(),
+            shred: false,
+            shredded: 0,
+            copied: 0,
+            retried: 0,
+            open: Vec::new(),
+            owner: None,
+            rule: None,
         }
     }
 
-    fn new_extension(path: PathBuf, extension: &'static OsStr) -> TempComponent {
+    fn new_files(path: PathBuf, files: String) -> TempComponent {
         TempComponent {
             path,
             size: 0,
             len: 0,
-            extension,
+            files,
             depth: 1,
             min_depth: 1,
-            deletion_type: DeletionType::Extension,
+            deletion_type: DeletionType::Files,
             need_reboot: false,
+            links: Vec::new(),
+            scanned: HashMap::new(),
+            changed: Vec::new(),
+            failed: Vec::new(),
+            excluded: Vec::new(),
+            kept_dirs: Vec::new(),
+            min_age: None,
+            age_by: AgeBy::default(),
+            shred: false,
+            shredded: 0,
+            copied: 0,
+            retried: 0,
+            open: Vec::new(),
+            owner: None,
+            rule: None,
         }
     }
-}
 
-fn remove_readonly(path: &Path) {
-    match symlink_metadata(path) {
-        Ok(metadata) => {
-            let mut permissions: Permissions = metadata.permissions();
-            permissions.set_readonly(false);
-            set_permissions(path, permissions).ignore();
+    fn from_rule(path: PathBuf, rule: &Rule) -> TempComponent {
+        let mut component = match rule.files {
+            Some(ref f) => TempComponent::new_files(path, f.clone()),
+            None => TempComponent::new_clear(path),
+        };
+
+        if rule.recursive {
+            component.depth = usize::MAX;
         }
-        _ => {}
+
+        component.excluded = rule.keep.iter().map(|k| component.path.join(k)).collect();
+        component.kept_dirs = rule.keep_dirs.iter().map(|k| component.path.join(k)).collect();
+        component.min_age = rule.min_age;
+        component.age_by = rule.age_by;
+        component.shred = rule.shred;
+        component.rule = Some(rule.describe());
+        component
     }
 }
 
-fn file_has_extension(path: &Path, ext_to_search: &OsStr) -> bool {
-    if path.is_file() {
-        return match path.extension() {
-            Some(e) => e == ext_to_search,
-            None => false,
-        };
+// Directories named by a wildcard are looked up, never through a link
+fn target_paths(fs: &dyn Filesystem, base: &Path, rule: &Rule) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = vec![base.to_path_buf()];
+
+    for component in rule.path.components() {
+        let name: &OsStr = component.as_os_str();
+
+        if !has_wildcards(name) {
+            paths.iter_mut().for_each(|p| p.push(component));
+            continue;
+        }
+
+        paths = paths.iter()
+            .filter_map(|p| fs.names(p).ok().map(|names| (p, names)))
+            .flat_map(|(p, names)| names.into_iter().map(move |n| (p, n)))
+            .filter(|(_, n)| name_matches(&name.to_string_lossy(), n))
+            .map(|(p, n)| p.join(n))
+            .filter(|p| is_real_dir(fs, p))
+            .collect();
+
+        paths.sort();
     }
 
-    return false;
+    paths
 }
 
-fn clear(path: &Path, component: &mut TempComponent) {
-    if component.deletion_type == DeletionType::Extension
-        && file_has_extension(&path, &component.extension) == false {
-        return;
+fn kind(fs: &dyn Filesystem, path: &Path) -> Option<Kind> {
+    fs.stat(path).ok().map(|s| s.kind)
+}
+
+fn is_link(fs: &dyn Filesystem, path: &Path) -> bool {
+    kind(fs, path) == Some(Kind::Link)
+}
+
+fn is_real_file(fs: &dyn Filesystem, path: &Path) -> bool {
+    kind(fs, path) == Some(Kind::File)
+}
+
+fn is_real_dir(fs: &dyn Filesystem, path: &Path) -> bool {
+    kind(fs, path) == Some(Kind::Dir)
+}
+
+// A path is contained when it lives under the root and no directory between
+// the root and the path is a link that could lead somewhere else
+fn is_contained(fs: &dyn Filesystem, path: &Path, root: &Path) -> bool {
+    if is_link(fs, root) {
+        return false;
     }
 
-    remove_readonly(path);
+    let relative: &Path = match path.strip_prefix(root) {
+        Ok(r) => r,
+        _ => return false,
+    };
+
+    let mut current: PathBuf = root.to_path_buf();
+    let mut components = relative.components().peekable();
+
+    while let Some(c) = components.next() {
+        current.push(c);
 
-    if path.is_dir() {
-        unsafe { set_window_text(LABEL_HANDLE, &format!("Remove {} ...", path.display())); }
-        let _ = remove_dir_all(path);
-    } else if path.is_file() {
-        let _ = remove_file(path);
+        if components.peek().is_some() && !is_real_dir(fs, &current) {
+            return false;
+        }
     }
+
+    true
 }
 
-fn get_len_and_size(path: &Path, component: &mut TempComponent) {
-    if path.is_dir() {
-        unsafe { set_window_text(LABEL_HANDLE, &format!("Indexing {} ...", path.display())); }
-    }
+#[cfg(windows)]
+fn same_path(a: &Path, b: &Path) -> bool {
+    a.to_string_lossy().eq_ignore_ascii_case(&b.to_string_lossy())
+}
 
-    if path.is_file() == false {
-        return;
+#[cfg(unix)]
+fn same_path(a: &Path, b: &Path) -> bool {
+    a == b
+}
+
+#[cfg(windows)]
+fn is_below(path: &Path, root: &Path) -> bool {
+    PathBuf::from(path.to_string_lossy().to_lowercase()).starts_with(root.to_string_lossy().to_lowercase())
+}
+
+#[cfg(unix)]
+fn is_below(path: &Path, root: &Path) -> bool {
+    path.starts_with(root)
+}
+
+// Newest time stamp `age_by` names, a directory is as new as its content
+fn newest(fs: &dyn Filesystem, path: &Path, age_by: AgeBy) -> Option<SystemTime> {
+    let stat: Stat = fs.stat(path).ok()?;
+
+    let stamps: Stamps = match stat.kind {
+        Kind::Dir => age_by.dirs,
+        _ => age_by.files,
+    };
+
+    // None chosen is as old as it gets, chosen ones all unknown is unknown
+    let own: Option<SystemTime> = match stamps == Stamps::default() {
+        true => Some(UNIX_EPOCH),
+        false => [
+            (stamps.accessed, stat.accessed),
+            (stamps.created, stat.created),
+            (stamps.changed, stat.changed),
+            (stamps.modified, stat.modified),
+        ].iter().filter_map(|(chosen, stamp)| stamp.filter(|_| *chosen)).max(),
+    };
+
+    if stat.kind != Kind::Dir {
+        return own;
     }
 
-    if component.deletion_type == DeletionType::Extension
-        && file_has_extension(&path, &component.extension) == false {
-        return;
+    let mut newest: SystemTime = own?;
+
+    for name in fs.names(path).ok()? {
+        newest = newest.max(self::newest(fs, &path.join(name), age_by)?);
     }
 
-    component.len += 1;
-    component.size += match path.metadata() {
-        Ok(metadata) => metadata.len(),
-        _ => 0,
+    Some(newest)
+}
+
+// Touched less than `min_age` ago, anything whose age is unknown counts as young
+fn is_young(fs: &dyn Filesystem, path: &Path, min_age: Option<Duration>, age_by: AgeBy) -> bool {
+    let min_age: Duration = match min_age {
+        Some(a) => a,
+        None => return false,
     };
+
+    match newest(fs, path, age_by) {
+        Some(newest) => SystemTime::now().duration_since(newest).map(|age| age < min_age).unwrap_or(true),
+        None => true,
+    }
+}
+
+fn file_matches(fs: &dyn Filesystem, path: &Path, pattern: &str) -> bool {
+    if is_real_file(fs, path) {
+        return match path.file_name() {
+            Some(n) => name_matches(pattern, n),
+            None => false,
+        };
+    }
+
+    false
 }
 
-fn remove_on_reboot(path: &Path, component: &mut TempComponent) {
-    if component.deletion_type == DeletionType::Extension
-        && file_has_extension(&path, &component.extension) == false {
+fn clear(path: &Path, component: &mut TempComponent, context: &mut CleanContext) {
+    if !is_contained(context.fs, path, &component.path) {
         return;
     }
 
-    if !path.is_file() && !path.is_dir() {
+    if component.deletion_type == DeletionType::Files
+        && !file_matches(context.fs, path, &component.files) {
         return;
     }
 
-    if path.is_dir() {
-        unsafe { set_window_text(LABEL_HANDLE, &format!("Remove on reboot {} ...", path.display())); }
+    if is_real_dir(context.fs, path) {
+        progress::status(&format!("Remove {} ...", path.display()));
     }
 
-    remove_readonly(path);
-    let existing_filename: Vec<u16> = path.as_os_str().encode_wide().collect();
+    let _ = remove_validated(path, component, context);
+}
 
-    let mut r_at_reboot = || {
-        let status: i32 = unsafe {
-            MoveFileExW(
-                existing_filename.as_ptr(),
-                null(),
-                MOVEFILE_DELAY_UNTIL_REBOOT,
-            )
-        };
+// Where and what happens to the files of every component during a clean
+struct CleanContext<'a> {
+    fs: &'a dyn Filesystem,
+    quarantine: Option<Quarantine>,
+    shred: Option<Shred>,
+    // What could only be scheduled, queued for the next start to verify
+    deferred: Vec<Deferred>,
+    retry: Retry,
+    // Spent on retries so far, over every component
+    waited: Duration,
+}
 
-        if status != 0 {
-            component.need_reboot = true;
-        }
-    };
+// How long a clean waits on files another process holds for a moment
+struct Retry {
+    attempts: u32,
+    backoff: Duration,
+    max_wait: Duration,
+}
 
-    if path.is_dir() {
-        match remove_dir_all(path) {
-            Ok(_) => {}
-            _ => r_at_reboot()
-        }
-    } else if path.is_file() {
-        match remove_file(path) {
-            Ok(_) => {}
-            _ => r_at_reboot()
-        }
-    }
+// A file is only deleted when it is still the one seen by get_len_and_size,
+// anything recreated or modified in between is skipped and reported
+struct ComponentPolicy<'a> {
+    fs: &'a dyn Filesystem,
+    len: &'a mut u64,
+    size: &'a mut u64,
+    links: &'a mut Vec<PathBuf>,
+    scanned: &'a HashMap<PathBuf, Fingerprint>,
+    changed: &'a mut Vec<PathBuf>,
+    excluded: &'a Vec<PathBuf>,
+    kept_dirs: &'a Vec<PathBuf>,
+    open: &'a Vec<OpenFile>,
+    min_age: Option<Duration>,
+    age_by: AgeBy,
+    shredded: &'a mut u64,
+    copied: &'a mut u64,
+    shred: Option<Shred>,
+    quarantine: Option<&'a mut Quarantine>,
 }
 
-fn walk<F>(component: &mut TempComponent, callback: F)
-    where F: Fn(&Path, &mut TempComponent) {
-    let walk = match component.min_depth {
-        0 => {
-            WalkDir::new(component.path.as_path())
-                .follow_links(false)
-                .max_depth(component.depth)
+impl<'a> Policy for ComponentPolicy<'a> {
+    fn accept(&mut self, path: &Path, current: &Fingerprint) -> bool {
+        if self.scanned.get(path) == Some(current) {
+            return true;
         }
-        _ => {
-            WalkDir::new(component.path.as_path())
-                .follow_links(false)
-                .min_depth(component.min_depth)
-                .max_depth(component.depth)
+
+        // Too young for the rule or not a regular file, never a candidate
+        if !self.scanned.contains_key(path) && (!is_real_file(self.fs, path) || is_young(self.fs, path, self.min_age, self.age_by)) {
+            return false;
         }
-    };
 
-    for entry in walk.into_iter().filter_map(|e| e.ok()) {
-        let path: &Path = entry.path();
-        callback(path, component);
-    }
+        // Skipped while another process holds it
+        if !self.scanned.contains_key(path) && self.open.iter().any(|o| (o.dev, o.id) == (current.dev, current.id)) {
+            return false;
+        }
 
-    advance_progress_bar(unsafe { PROGRESS_HANDLE }, 1);
-}
+        if !self.changed.iter().any(|c| c == path) {
+            self.changed.push(path.to_path_buf());
 
-fn get_walk_glob_file(
-    temp_components: &mut Vec<TempComponent>,
-    system_vars: &HashMap<&'static str, PathBuf>) -> () {
-    let config = [
-        ["system_drive", "tmp"],
-        ["system_root", "tmp"],
-        ["sys32", "tmp"],
-    ];
-
-    for row in config.iter() {
-        let key: &str = row[0];
-        let ext: &OsStr = OsStr::new(row[1]);
-        let path: &PathBuf = system_vars.get(key).unwrap();
-
-        if !(path.exists() && path.is_dir()) {
-            continue;
+            if let Some(expected) = self.scanned.get(path) {
+                *self.len -= 1;
+                *self.size -= expected.size;
+            }
         }
 
-        let mut temp_component = TempComponent::new_extension(path.to_path_buf(), ext);
-        walk(&mut temp_component, get_len_and_size);
+        false
+    }
 
-        if temp_component.len > 1 {
-            temp_components.push(temp_component);
+    fn disposal(&mut self, path: &Path, _current: &Fingerprint) -> Disposal {
+        if let Some(ref mut q) = self.quarantine {
+            return Disposal::Move(q.next_destination(path));
         }
-    }
-}
 
-fn get_glob_folders_clear(
-    temp_components: &mut Vec<TempComponent>,
-    users_profile_dirs: &Vec<PathBuf>) -> () {
-    let list = [
-        ["firefox_profil", "AppData\\Local\\Mozilla\\Firefox\\Profiles"],
-        ["waterfox_profil", "AppData\\Local\\Mozilla\\Waterfox\\Profiles"],
-        ["seamonkey_profil", "AppData\\Local\\Mozilla\\SeaMonkey\\Profiles"],
-        ["palemoon_profil", "AppData\\Local\\Mozilla\\Pale Moon\\Profiles"],
-        ["icedragon_profil", "AppData\\Local\\Comodo\\IceDragon\\Profiles"],
-    ];
-
-    let mut profiles: HashMap<String, Vec<PathBuf>> = HashMap::new();
-
-    for l in list.iter() {
-        let k = l[0].to_string();
-        let format_path = PathBuf::from(l[1]);
-        let mut v = Vec::new();
+        match self.shred {
+            Some(shred) => Disposal::Shred(shred),
+            None => Disposal::Delete,
+        }
+    }
 
-        for user_dir in users_profile_dirs {
-            let path: PathBuf = user_dir.join(&format_path);
-
-            if path.exists() && path.is_dir() {
-                for entry in WalkDir::new(path.as_path())
-                    .follow_links(false)
-                    .min_depth(1)
-                    .max_depth(1)
-                    .into_iter()
-                    .filter_map(|e| e.ok()) {
-                    let nested: PathBuf = entry.into_path();
-
-                    if nested.is_dir() {
-                        v.push(nested);
-                    }
+    fn disposed(&mut self, path: &Path, disposal: &Disposal, current: &Fingerprint) {
+        match disposal {
+            Disposal::Move(destination) => {
+                if let Some(ref mut q) = self.quarantine {
+                    q.record(path, destination, current.size).ignore();
                 }
             }
+            Disposal::Shred(_) => *self.shredded += 1,
+            Disposal::Delete => {}
         }
+    }
 
-        profiles.insert(k, v);
+    fn link_removed(&mut self, path: &Path) {
+        self.links.push(path.to_path_buf());
     }
 
-    let config = [
-        ["firefox_profil", "cache2"],
-        ["firefox_profil", "OfflineCache"],
-        ["firefox_profil", "jumpListCache"],
-        ["firefox_profil", "startupCache"],
-        ["waterfox_profil", "cache2"],
-        ["waterfox_profil", "OfflineCache"],
-        ["waterfox_profil", "jumpListCache"],
-        ["waterfox_profil", "startupCache"],
-        ["seamonkey_profil", ""],
-        ["palemoon_profil", ""],
-        ["icedragon_profil", "Cache"],
-        ["icedragon_profil", "OfflineCache"],
-        ["icedragon_profil", "mozilla-media-cache"],
-        ["icedragon_profil", "startupCache"],
-        ["icedragon_profil", "jumpListCache"],
-    ];
-
-    for c in config.iter() {
-        let var: String = c[0].to_string();
-        let format_path: PathBuf = PathBuf::from(c[1]);
-        match profiles.get(&var) {
-            Some(list) => {
-                for p in list.iter() {
-                    let path: PathBuf = p.join(&format_path);
-
-                    if check_path(&path, false, false) {
-                        let mut temp_component = TempComponent::new_clear(path);
-                        walk(&mut temp_component, get_len_and_size);
-
-                        if temp_component.len > 1 {
-                            temp_components.push(temp_component);
-                        }
-                    }
-                }
-            }
-            _ => continue,
-        };
+    fn copied(&mut self, _path: &Path) {
+        *self.copied += 1;
+    }
+
+    // Nested roots belong to their own component, young and kept directories
+    // stay, the walk still reaches the content of the kept ones
+    fn enter(&mut self, path: &Path) -> bool {
+        !self.excluded.iter().chain(self.kept_dirs.iter()).any(|e| path_matches(e, path))
+            && !is_young(self.fs, path, self.min_age, self.age_by)
     }
 }
 
-fn get_walk_all(
-    temp_components: &mut Vec<TempComponent>,
-    system_vars: &HashMap<&'static str, PathBuf>,
-    users_profile_dirs: &Vec<PathBuf>,
-) -> () {
-    let config = [
-        ["system_root", "Temp"],
-        ["system_root", "Prefetch"],
-        ["system_root", "SoftwareDistribution\\Download"],
-        ["system_root", "SoftwareDistribution\\DataStore\\Logs"],
-        ["system_root", "SoftwareDistribution\\DataStore.bak\\Logs"],
-        ["system_root", "Logs\\waasmedic"],
-        ["system_root", "Logs\\WindowsUpdate"],
-        ["all_user_profile", "Temp"],
-        ["users", "AppData\\Local\\Temp"],
-        ["users", "AppData\\Local\\Microsoft\\Windows\\Temporary Internet Files"],
-        ["users", "AppData\\Roaming\\Macromedia\\Flash Player\\#SharedObjects"],
-        ["users", "AppData\\Local\\Microsoft\\Windows\\WebCache"],
-        // ["users", "AppData\\Local\\Microsoft\\Windows\\AppCache"],
-        // ["users", "AppData\\Local\\Microsoft\\Windows\\Caches"],
-        ["users", "AppData\\Local\\Microsoft\\Windows\\INetCache\\IE"],
-        ["system_root", "ie7updates"],
-        ["system_root", "ie8updates"],
-        ["users", "AppData\\Local\\Google\\Chrome\\User Data\\Default\\Cache"],
-        ["users", "AppData\\Local\\Google\\Chrome\\User Data\\Default\\File System"],
-        ["users", "AppData\\Local\\Google\\Chrome\\UserData\\Default\\Local Storage"],
-        ["users", "AppData\\Local\\BraveSoftware\\Brave-Browser\\User Data\\Default\\Cache"],
-        ["users", "AppData\\LocalLow\\Sun\\Java\\Deployment\\cache"],
-        ["users", "AppData\\Local\\Opera Software\\Opera Stable\\Cache"],
-        ["users", "AppData\\Local\\Packages\\Microsoft.MicrosoftEdge_8wekyb3d8bbwe\\AC\\MicrosoftEdge\\Cache"],
-        ["users", "AppData\\Local\\Yandex\\YandexBrowser\\Default\\Cache"],
-        ["users", "AppData\\Local\\Yandex\\YandexBrowser\\User DataDefault\\Cache"],
-        ["users", "AppData\\Local\\Chromium\\User Data\\Default\\Cache"],
-        ["users", "AppData\\Local\\Chromium\\User Data\\Default\\ApplicationCache"],
-        ["users", "AppData\\Local\\Comodo\\Dragon\\User Data\\Default\\Cache"],
-        ["users", "AppData\\Local\\Comodo\\Dragon\\User DataDefault\\Cache"],
-        ["users", "AppData\\Local\\Baidu\\User Data\\Cache"],
-        ["users", "AppData\\Local\\Vivaldi\\User Data\\Default\\Application Cache"],
-        ["users", "AppData\\Local\\Vivaldi\\User Data\\Default\\Cache"],
-        ["users", "AppData\\Local\\Vivaldi\\User Data\\Default\\GPUCache"],
-        ["users", "AppData\\Local\\Vivaldi\\User Data\\Default\\Media Cache"],
-        ["system_drive", "\\Config.Msi"],
-    ];
-
-    for row in config.iter() {
-        let var: &str = row[0];
-        let format_path: PathBuf = PathBuf::from(row[1]);
-
-        let mut build_component = |path_of_component: PathBuf| -> () {
-            if check_path(&path_of_component, false, false) {
-                let mut temp_component = TempComponent::new_clear(path_of_component);
-                walk(&mut temp_component, get_len_and_size);
-
-                if temp_component.len > 1 {
-                    temp_components.push(temp_component);
-                }
-            }
-        };
+fn remove_validated(path: &Path, component: &mut TempComponent, context: &mut CleanContext) -> Result<(), Error> {
+    let fs: &dyn Filesystem = context.fs;
+    let shred: Option<Shred> = if component.shred { context.shred } else { None };
+    let quarantine: Option<&mut Quarantine> = context.quarantine.as_mut();
+    let min_age: Option<Duration> = component.min_age;
+    let age_by: AgeBy = component.age_by;
+    let TempComponent {
+        path: root, len, size, links, scanned, changed, excluded, kept_dirs, open, shredded, copied, ..
+    } = component;
+
+    let mut policy = ComponentPolicy {
+        fs, len, size, links, scanned, changed, excluded, kept_dirs, open, min_age, age_by, shredded, copied, shred,
+        quarantine,
+    };
+
+    fs.remove_contained(root, path, &mut policy)
+}
 
-        if var == "users" {
-            for user_dir in users_profile_dirs {
-                let path = user_dir.join(&format_path);
+fn get_len_and_size(fs: &dyn Filesystem, path: &Path, component: &mut TempComponent) {
+    if !is_contained(fs, path, &component.path) {
+        return;
+    }
 
-                build_component(path);
-            }
-        } else {
-            let sys_path: &PathBuf = system_vars.get(var).unwrap();
-            let path = sys_path.join(&format_path);
+    if is_real_dir(fs, path) {
+        progress::status(&format!("Indexing {} ...", path.display()));
+    }
 
-            build_component(path);
-        }
+    if !is_real_file(fs, path) {
+        return;
     }
 
-    let user_temp = match var("USERPROFILE") {
-        Ok(p) => Some(format!("{}\\AppData\\Local\\Temp", p)),
-        _ => None
+    if component.deletion_type == DeletionType::Files
+        && !file_matches(fs, path, &component.files) {
+        return;
+    }
+
+    if is_young(fs, path, component.min_age, component.age_by) {
+        return;
+    }
+
+    let fingerprint: Fingerprint = match fs.fingerprint(path) {
+        Ok(f) => f,
+        _ => return,
     };
 
-    let mut temp = var("TEMP").ok();
+    component.len += 1;
+    component.size += fingerprint.size;
+    component.scanned.insert(path.to_path_buf(), fingerprint);
+}
 
-    if user_temp.is_none() || temp.is_none() {
+fn remove_on_reboot(path: &Path, component: &mut TempComponent, context: &mut CleanContext) {
+    if !is_contained(context.fs, path, &component.path) {
         return;
     }
 
-    if temp == user_temp {
+    if component.deletion_type == DeletionType::Files
+        && !file_matches(context.fs, path, &component.files) {
         return;
     }
 
-    let temp = temp.get_or_insert("".to_string()).as_str();
+    let link: bool = is_link(context.fs, path);
 
-    if temp == "" {
+    if !link && !is_real_file(context.fs, path) && !is_real_dir(context.fs, path) {
         return;
     }
 
-    let path = PathBuf::from(temp);
+    if is_real_dir(context.fs, path) {
+        progress::status(&format!("Remove on reboot {} ...", path.display()));
+    }
+
+    let mut reason: Error = match remove_validated(path, component, context) {
+        Ok(_) => return,
+        Err(e) => e,
+    };
 
-    if check_path(&path, true, false) {
-        let mut temp_component = TempComponent::new_clear(path);
-        walk(&mut temp_component, get_len_and_size);
+    // Antivirus scanners and the search indexer let go of a file quickly
+    if is_transient(&reason) {
+        component.retried += 1;
 
-        if temp_component.len > 1 {
-            temp_components.push(temp_component);
-        }
+        reason = match retry(path, component, context, reason) {
+            Ok(_) => return,
+            Err(e) => e,
+        };
     }
 
-    advance_progress_bar(unsafe { PROGRESS_HANDLE }, 1);
-}
+    // Never schedule what changed since the scan, nor a directory kept for its
+    // age or for the open files it holds
+    if component.changed.iter().any(|c| c == path)
+        || (is_real_file(context.fs, path) && !component.scanned.contains_key(path))
+        || (is_real_dir(context.fs, path) && is_young(context.fs, path, component.min_age, component.age_by))
+        || component.kept_dirs.iter().any(|k| path_matches(k, path))
+        || component.open.iter().any(|o| o.path.starts_with(path) && !component.scanned.contains_key(&o.path)) {
+        return;
+    }
 
-fn windows_old(
-    temp_components: &mut Vec<TempComponent>,
-    system_vars: &HashMap<&'static str, PathBuf>,
-) {
-    let system_drive = system_vars.get("system_drive").unwrap();
-
-    let paths = [
-        PathBuf::from("\\Windows.old"),
-        PathBuf::from("\\Windows.old.000"),
-        PathBuf::from("\\Windows.old.001"),
-        PathBuf::from("\\Windows.old.002"),
-        PathBuf::from("\\Windows.old.003"),
-        PathBuf::from("\\Windows.old.004"),
-        PathBuf::from("\\Windows.old.005"),
-        PathBuf::from("\\$WINDOWS.~BT"),
-        PathBuf::from("\\$Windows.~WS"),
-    ];
-
-    for p in paths.iter() {
-        let path = &system_drive.clone().join(p);
-
-        if check_path(path, false, false) {
-            let mut temp_component = TempComponent::new_clear(path.clone());
-
-            walk(&mut temp_component, get_len_and_size);
-
-            if temp_component.len > 1 {
-                temp_component.min_depth = 0;
-                temp_components.push(temp_component);
-            }
+    // A directory only refused for what it still holds is no failure of its own
+    if !is_deferrable(&reason, path, &context.deferred) {
+        if reason.kind() != ErrorKind::DirectoryNotEmpty {
+            not_removed(path, component, &reason);
         }
+
+        return;
     }
-}
 
-fn get_users_dirs(system_vars: &HashMap<&'static str, PathBuf>) -> Vec<PathBuf> {
-    let mut users_dirs: Vec<PathBuf> = Vec::new();
-    let base_user_profile: &PathBuf = system_vars.get("base_user_profile").unwrap();
-    let system_root: &PathBuf = system_vars.get("system_root").unwrap();
+    let kind: Kind = match kind(context.fs, path) {
+        Some(k) => k,
+        None => return,
+    };
 
-    for entry in WalkDir::new(&base_user_profile)
-        .follow_links(false)
-        .min_depth(1)
-        .max_depth(1)
-        .into_iter()
-        .filter_map(|e| e.ok()) {
-        let p: &Path = entry.path();
+    if context.fs.stat(path).map(|s| s.readonly).unwrap_or(false) {
+        context.fs.clear_readonly(path).ignore();
+    }
 
-        if p.is_dir() {
-            users_dirs.push(p.to_path_buf());
-        }
+    // In quarantine mode a locked file is moved into the store at reboot,
+    // directories and links are still deleted
+    let destination: Option<PathBuf> = match context.quarantine {
+        Some(ref mut q) if kind == Kind::File => Some(q.next_destination(path)),
+        _ => None,
+    };
+
+    if let Err(e) = context.fs.schedule_on_reboot(path, destination.as_deref()) {
+        not_removed(path, component, &e);
+        return;
     }
 
-    let local_service_profile: PathBuf = system_root.join(PathBuf::from("ServiceProfiles\\LocalService"));
-    let network_service_profile: PathBuf = system_root.join(PathBuf::from("NetworkService\\LocalService"));
+    component.need_reboot = true;
 
-    users_dirs.push(local_service_profile);
-    users_dirs.push(network_service_profile);
+    if link {
+        component.links.push(path.to_path_buf());
+    }
 
-    users_dirs
-}
+    let fingerprint: Option<Fingerprint> = component.scanned.get(path).copied();
+    context.deferred.push(
+        Deferred::new(kind, fingerprint, destination.clone(), &component.path, &reason.to_string(), path)
+    );
 
-fn check_path(path: &PathBuf, check_temp: bool, panic: bool) -> bool {
-    if !path.is_absolute() || !path.has_root() || !path.exists() || !path.is_dir() {
-        if panic == true {
-            error_box(format!("Invalid Path {} ... Abandoned for Safety", path.display()).to_string());
-            restart();
-            panic!("Err");
-        }
+    if let (Some(q), Some(d)) = (context.quarantine.as_mut(), destination) {
+        q.record_pending(path, &d, fingerprint.map(|f| f.size).unwrap_or(0)).ignore();
+    }
+}
 
-        return false;
+// Left in place, so it leaves the counts
+fn not_removed(path: &Path, component: &mut TempComponent, error: &Error) {
+    if let Some(f) = component.scanned.get(path) {
+        component.len -= 1;
+        component.size -= f.size;
     }
 
-    if check_temp == true {
-        match path.file_name() {
-            Some(f) => {
-                if f != "Temp" && f != "Tmp" && f != "temp" && f != "tmp" {
-                    if panic == true {
-                        error_box(format!("Invalid Path {} ... Abandoned for Safety", path.display()).to_string());
-                        restart();
-                        panic!("Err");
-                    }
+    component.failed.push((path.to_path_buf(), error.to_string()));
+}
 
-                    return false;
-                }
-            }
-            _ => {
-                if panic == true {
-                    error_box(format!("Invalid Path {} ... Abandoned for Safety", path.display()).to_string());
-                    restart();
-                    panic!("Err");
-                }
+// Files of the component other processes hold. Unlinking them on Linux frees
+// nothing until they are closed and may break the process, skipped ones leave
+// the counts. They are matched on their device and inode, /proc names the
+// canonical path which need not be the one the component reaches them by, and
+// are recorded under the latter.
+fn find_open(component: &mut TempComponent, open: &[OpenFile], mode: OpenFiles) {
+    let mut paths: HashMap<(u64, u64), PathBuf> = component.scanned.iter()
+        .map(|(p, f)| ((f.dev, f.id), p.clone()))
+        .collect();
+
+    for o in &component.open {
+        paths.insert((o.dev, o.id), o.path.clone());
+    }
+
+    for file in open.iter().filter(|f| !f.deleted) {
+        let path: PathBuf = match paths.get(&(file.dev, file.id)) {
+            Some(p) => p.clone(),
+            None => continue,
+        };
 
-                return false;
+        if mode == OpenFiles::Skip {
+            if let Some(f) = component.scanned.remove(&path) {
+                component.len -= 1;
+                component.size -= f.size;
             }
         }
-    }
 
-    return true;
+        component.open.push(OpenFile { path, ..file.clone() });
+    }
 }
 
-fn get_system_vars() -> HashMap<&'static str, PathBuf> {
-    let mut system_vars = HashMap::new();
+// Tried again while the error stays a sharing one, until the attempts or the
+// time allowed to the whole clean run out. The last error is returned.
+fn retry(path: &Path, component: &mut TempComponent, context: &mut CleanContext, error: Error) -> Result<(), Error> {
+    let mut delay: Duration = context.retry.backoff;
+    let mut error: Error = error;
 
-    let system_drive_string: String = var("SYSTEMDRIVE")
-        .unwrap_or(
-            "C:".to_string()
-        );
+    for _ in 0..context.retry.attempts {
+        if context.waited + delay > context.retry.max_wait {
+            break;
+        }
 
-    let system_drive: PathBuf = PathBuf::from(
-        var_os("SYSTEMDRIVE")
-            .unwrap_or(
-                OsString::from("C:")
-            )
-    );
+        thread::sleep(delay);
+        context.waited += delay;
+        delay *= 2;
 
-    let all_user_profile: PathBuf = PathBuf::from(
-        var_os("ALLUSERSPROFILE")
-            .unwrap_or(
-                OsString::from(format!("{}\\ProgramData", system_drive_string))
-            )
-    );
+        match remove_validated(path, component, context) {
+            Ok(_) => return Ok(()),
+            Err(e) if is_transient(&e) => error = e,
+            Err(e) => return Err(e),
+        }
+    }
 
-    let system_root: PathBuf = PathBuf::from(
-        var_os("SYSTEMROOT")
-            .unwrap_or(
-                OsString::from(
-                    format!("{}\\Windows", &system_drive_string)
-                )
-            )
-    );
+    Err(error)
+}
 
-    let sys32: PathBuf = system_root.join(PathBuf::from("System32"));
+#[cfg(windows)]
+fn is_transient(error: &Error) -> bool {
+    matches!(error.raw_os_error(), Some(32) | Some(33))
+}
 
-    let username: String = var("USERNAME")
-        .unwrap_or(
-            "Default".to_string()
-        );
+#[cfg(unix)]
+fn is_transient(error: &Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::EBUSY) | Some(libc::ETXTBSY))
+}
 
-    let user_profile_string: String = var("USERPROFILE")
-        .unwrap_or(
-            format!("{}\\Users\\{}", system_drive_string, username)
-        );
+// MoveFileEx takes any entry, what the reboot cannot do it refuses itself
+#[cfg(windows)]
+fn is_deferrable(_error: &Error, _path: &Path, _deferred: &[Deferred]) -> bool {
+    true
+}
 
-    let user_profile: PathBuf = PathBuf::from(
-        var_os("USERPROFILE")
-            .unwrap_or(
-                OsString::from(&user_profile_string)
-            )
+// Only what a process holds is gone at the next start, a directory waits for
+// the entries of it already deferred
+#[cfg(unix)]
+fn is_deferrable(error: &Error, path: &Path, deferred: &[Deferred]) -> bool {
+    is_transient(error)
+        || (error.kind() == ErrorKind::DirectoryNotEmpty && deferred.iter().any(|d| d.path.starts_with(path)))
+}
+
+fn walk<F>(fs: &dyn Filesystem, component: &mut TempComponent, mut callback: F)
+    where F: FnMut(&Path, &mut TempComponent) {
+    let root: PathBuf = component.path.clone();
+    let depths: (usize, usize) = (component.min_depth, component.depth);
+    let excluded: Vec<PathBuf> = component.excluded.clone();
+
+    vfs::walk(
+        fs,
+        &root,
+        depths,
+        &|p| !excluded.iter().any(|x| path_matches(x, p)),
+        &mut |p| callback(p, component),
     );
 
-    let mut spt_user_profile: Vec<&str> = user_profile_string.split("\\").collect();
-    spt_user_profile.truncate(spt_user_profile.len() - 1);
+    progress::advance(1);
+}
 
-    let base_user_profile: PathBuf = PathBuf::from(spt_user_profile.join("\\"));
+// Both passes over a component: what can go now, then what is left for reboot
+fn clean_component(component: &mut TempComponent, context: &mut CleanContext) {
+    let fs: &dyn Filesystem = context.fs;
 
-    check_path(&system_root, false, true);
-    check_path(&sys32, false, true);
-    check_path(&base_user_profile, false, true);
-    check_path(&all_user_profile, false, true);
-    check_path(&user_profile, false, true);
+    walk(fs, component, |p, c| clear(p, c, context));
 
-    system_vars.insert("system_drive", system_drive);
-    system_vars.insert("system_root", system_root);
-    system_vars.insert("sys32", sys32);
-    system_vars.insert("base_user_profile", base_user_profile);
-    system_vars.insert("all_user_profile", all_user_profile);
-    system_vars.insert("user_profile", user_profile);
+    // The content of a directory is scheduled before it, a reboot cannot
+    // remove a directory that still holds something
+    let mut left: Vec<PathBuf> = Vec::new();
+    walk(fs, component, |p, _| left.push(p.to_path_buf()));
 
-    system_vars
+    for path in left.iter().rev() {
+        remove_on_reboot(path, component, context);
+    }
 }
 
-pub fn clean(old: bool) -> Result<(), Error> {
-    adjust_privilege("SeRestorePrivilege");
+// Value of a per-profile variable. `cache` is the XDG_CACHE_HOME of the user
+// running the clean, for that user only: the others keep the default one.
+fn profile_var(profile: &Profile, var: &str, cache: Option<&PathBuf>) -> Option<PathBuf> {
+    match var {
+        "users" => Some(profile.path.clone()),
+        "user_temp" => profile.temp.clone(),
+        "xdg_cache" => Some(cache.cloned().unwrap_or(profile.path.join(".cache"))),
+        _ => None,
+    }
+}
 
-    let system_vars: HashMap<&'static str, PathBuf> = get_system_vars();
-    let users_profile_dirs: Vec<PathBuf> = get_users_dirs(&system_vars);
-    let mut temp_components: Vec<TempComponent> = Vec::new();
-    let local: DateTime<Local> = Local::now();
-    let user_profile: PathBuf = PathBuf::from(var("USERPROFILE").unwrap_or("C:\\".to_string()));
-    let local_datetime = local.format("%a %b %e %T %Y");
-    let report: PathBuf = user_profile.join(format!(
-        "Desktop\\KpTemp_{}.txt", local.format("%Y-%m-%d_%H-%M-%S").to_string()
-    ));
+// Every browser profile directory found under the user profiles, by variable
+fn browser_profiles<'a>(fs: &dyn Filesystem, users_profiles: &'a [Profile]) -> HashMap<String, Vec<(PathBuf, &'a Profile)>> {
+    let mut profiles: HashMap<String, Vec<(PathBuf, &Profile)>> = HashMap::new();
 
-    let nbr_row = 41 + 3;
+    for l in profile_dirs().iter() {
+        let k = l[0].to_string();
+        let format_path = PathBuf::from(l[1]);
+        let mut v = Vec::new();
 
-    get_walk_all(&mut temp_components, &system_vars, &users_profile_dirs);
-    get_walk_glob_file(&mut temp_components, &system_vars);
-    get_glob_folders_clear(&mut temp_components, &users_profile_dirs);
+        for profile in users_profiles {
+            let path: PathBuf = profile.path.join(&format_path);
 
-    if old == true {
-        windows_old(&mut temp_components, &system_vars);
-    }
+            for name in fs.names(&path).unwrap_or_default() {
+                let nested: PathBuf = path.join(name);
 
-    let missing = (nbr_row - temp_components.len() as u64) * 2;
+                if is_real_dir(fs, &nested) {
+                    v.push((nested, profile));
+                }
+            }
+        }
 
-    for _ in 0..missing {
-        thread::sleep(Duration::from_millis(50));
-        advance_progress_bar(unsafe { PROGRESS_HANDLE }, 1);
+        profiles.insert(k, v);
     }
 
-    let mut total_len = 0;
-    let mut total_size = 0;
+    profiles
+}
 
-    for temp_component in &temp_components {
-        total_size += temp_component.size;
-        total_len += temp_component.len;
+// Every directory a variable resolves to, with the profile owning it. A
+// variable missing on this system points nowhere.
+fn var_bases<'a>(
+    var: &str,
+    system_vars: &HashMap<&'static str, PathBuf>,
+    users_profiles: &'a [Profile],
+    browsers: &HashMap<String, Vec<(PathBuf, &'a Profile)>>,
+) -> Vec<(PathBuf, Option<&'a Profile>)> {
+    if PROFILE_VARS.contains(&var) {
+        let user_profile: Option<&PathBuf> = system_vars.get("user_profile");
+
+        return users_profiles.iter()
+            .filter_map(|p| {
+                let current: bool = user_profile.map(|u| same_path(u, &p.path)).unwrap_or(false);
+                let cache: Option<&PathBuf> = system_vars.get("xdg_cache_home").filter(|_| current);
+
+                profile_var(p, var, cache).map(|base| (base, Some(p)))
+            })
+            .collect();
     }
 
-    let mut output = File::create(report)?;
-
-    output.write_all(format!("KpTemp v{} by kernel-panik\r\n", KPTEMP_VERSION).as_bytes())?;
-    output.write_all(format!("Date: {}\r\n\r\n", local_datetime.to_string()).as_bytes())?;
+    if let Some(list) = browsers.get(var) {
+        return list.iter().map(|(p, owner)| (p.clone(), Some(*owner))).collect();
+    }
 
-    if 0 == temp_components.len() {
-        output.write_all("No records found\n".as_bytes())?;
+    match system_vars.get(var) {
+        Some(base) => vec![(base.clone(), None)],
+        None => Vec::new(),
     }
+}
 
-    for mut temp_component in temp_components {
-        walk(&mut temp_component, clear);
-        walk(&mut temp_component, remove_on_reboot);
+// Every directory a rule points at, with the profile owning it
+fn rule_targets<'a>(
+    fs: &dyn Filesystem,
+    rule: &Rule,
+    system_vars: &HashMap<&'static str, PathBuf>,
+    users_profiles: &'a [Profile],
+    browsers: &HashMap<String, Vec<(PathBuf, &'a Profile)>>,
+) -> Vec<(PathBuf, Option<&'a Profile>)> {
+    var_bases(&rule.var, system_vars, users_profiles, browsers).into_iter()
+        .flat_map(|(base, owner)| target_paths(fs, &base, rule).into_iter().map(move |t| (t, owner)))
+        .collect()
+}
 
-        output.write_all(format!(
-            "{} : {} files => {} deleted\r\n",
-            temp_component.path.display(),
-            temp_component.len,
-            convert(temp_component.size as f64)
-        ).as_bytes())?;
-    }
+// The temp directory of a profile is cleared as a whole, it must really be one.
+// TEMP comes from the registry of the user, who could point it anywhere: only
+// a directory the profile owns is accepted.
+#[cfg(windows)]
+fn check_user_temp(fs: &dyn Filesystem, path: &Path, profile: &Profile) -> bool {
+    let temp: bool = path.file_name().map(|f| f == "Temp" || f == "Tmp" || f == "temp" || f == "tmp").unwrap_or(false);
 
-    let (r_len, r_size) = unsafe { data_recycle_bin() };
+    temp && is_target_dir(fs, path) && fs.owner(path).map(|sid| sid.eq_ignore_ascii_case(&profile.id)).unwrap_or(false)
+}
 
-    total_len += r_len as u64;
-    total_size += r_size as u64;
+// Never the shared temp directories, only one owned by the profile itself
+#[cfg(unix)]
+fn check_user_temp(fs: &dyn Filesystem, path: &Path, profile: &Profile) -> bool {
+    if !is_target_dir(fs, path) || path == Path::new("/tmp") || path == Path::new("/var/tmp") {
+        return false;
+    }
 
-    output.write_all(format!(
-        "Recycle Bin : {} files => {} deleted\r\n\r\n",
-        r_len,
-        convert(r_size as f64)
-    ).as_bytes())?;
+    fs.owner(path).map(|uid| uid == profile.id).unwrap_or(false)
+}
 
-    output.write_all(format!(
-        "Total : {} files => {} deleted\r\n",
-        total_len,
-        convert(total_size as f64)
-    ).as_bytes())?;
+// A target is an absolute path to a directory, never a link to one
+fn is_target_dir(fs: &dyn Filesystem, path: &Path) -> bool {
+    path.is_absolute() && path.has_root() && is_real_dir(fs, path)
+}
 
-    advance_progress_bar(unsafe { PROGRESS_HANDLE }, 1);
-    unsafe { set_window_text(LABEL_HANDLE, "Clear recycle bin"); }
-    empty_recycle_bin();
-    advance_progress_bar(unsafe { PROGRESS_HANDLE }, TOTAL_STEP);
-    message_box(format!("Success {} files and {} deleted", total_len, convert(total_size as f64)));
+fn is_safe_target(fs: &dyn Filesystem, rule: &Rule, path: &Path, owner: Option<&Profile>) -> bool {
+    match owner {
+        Some(profile) if rule.var == "user_temp" => check_user_temp(fs, path, profile),
+        _ => is_target_dir(fs, path),
+    }
+}
 
-    Ok(())
+// Every target of a rule on the given system, and whether a clean would accept it
+pub fn resolve_targets(
+    rule: &Rule,
+    system_vars: &HashMap<&'static str, PathBuf>,
+    users_profiles: &[Profile],
+) -> Vec<(PathBuf, bool)> {
+    let browsers: HashMap<String, Vec<(PathBuf, &Profile)>> = browser_profiles(&Disk, users_profiles);
+
+    rule_targets(&Disk, rule, system_vars, users_profiles, &browsers).into_iter()
+        .map(|(path, owner)| {
+            let safe: bool = is_safe_target(&Disk, rule, &path, owner);
+            (path, safe)
+        })
+        .collect()
+}
+
+fn get_components(
+    fs: &dyn Filesystem,
+    temp_components: &mut Vec<TempComponent>,
+    rules: &[Rule],
+    system_vars: &HashMap<&'static str, PathBuf>,
+    users_profiles: &[Profile],
+    browsers: &HashMap<String, Vec<(PathBuf, &Profile)>>,
+) {
+    for rule in rules.iter() {
+        for (path, owner) in rule_targets(fs, rule, system_vars, users_profiles, browsers) {
+            if is_safe_target(fs, rule, &path, owner) {
+                let mut temp_component = TempComponent::from_rule(path, rule);
+                temp_component.owner = owner.map(|p| p.name.clone());
+                temp_components.push(temp_component);
+            }
+        }
+    }
+}
+
+// A component clearing a root leaves nothing for another one on the same root
+// or, when it ignores age, on files below it
+fn covers(a: &TempComponent, b: &TempComponent) -> bool {
+    match a.deletion_type {
+        DeletionType::Clear => b.path.starts_with(&a.path)
+            && ((b.deletion_type == DeletionType::Files && a.min_age.is_none()) || same_path(&a.path, &b.path)),
+        DeletionType::Files => same_path(&a.path, &b.path) && a.files == b.files,
+    }
+}
+
+// Duplicates are cleaned once, a root cleared by one component and nested in
+// another is left to its own rule, the targets of skipped rules to nobody.
+// Components are indexed afterwards and the empty ones dropped.
+fn index_components(
+    fs: &dyn Filesystem,
+    components: Vec<TempComponent>,
+    protected: &[PathBuf],
+) -> Vec<TempComponent> {
+    let mut unique: Vec<TempComponent> = Vec::new();
+
+    for component in components {
+        if unique.iter().any(|u| covers(u, &component)) {
+            continue;
+        }
+
+        unique.retain(|u| !covers(&component, u));
+        unique.push(component);
+    }
+
+    let roots: Vec<PathBuf> = unique.iter()
+        .filter(|c| c.deletion_type == DeletionType::Clear)
+        .map(|c| c.path.clone())
+        .chain(protected.iter().cloned())
+        .collect();
+
+    for component in unique.iter_mut() {
+        let nested: Vec<PathBuf> = roots.iter()
+            .filter(|r| r.starts_with(&component.path) && !same_path(r, &component.path))
+            .cloned()
+            .collect();
+
+        component.excluded.extend(nested);
+
+        walk(fs, component, |p, c| get_len_and_size(fs, p, c));
+    }
+
+    unique.into_iter().filter(|c| c.len > 1).collect()
+}
+
+// Rules of the imported entries whose application is installed, what could
+// not be imported is noted for the report
+fn imported_rules(
+    fs: &dyn Filesystem,
+    path: &Path,
+    import: Result<Import, Error>,
+    system_vars: &HashMap<&'static str, PathBuf>,
+    users_profiles: &[Profile],
+    notes: &mut Vec<String>,
+) -> Vec<Rule> {
+    let import: Import = match import {
+        Ok(i) => i,
+        Err(e) => {
+            notes.push(format!("{} : not imported, {}", path.display(), e));
+            return Vec::new();
+        }
+    };
+
+    let installed: Vec<&Entry> = import.entries.iter()
+        .filter(|e| {
+            e.detect.is_empty() || e.detect.iter()
+                .flat_map(|d| rule_targets(fs, d, system_vars, users_profiles, &HashMap::new()))
+                .any(|(p, _)| fs.stat(&p).is_ok())
+        })
+        .collect();
+
+    let rules: Vec<Rule> = installed.iter().flat_map(|e| e.rules.iter().cloned()).collect();
+
+    notes.push(format!(
+        "{} : {} of {} entries installed, {} rules imported",
+        path.display(), installed.len(), import.entries.len(), rules.len()
+    ));
+
+    for (reason, entries) in &import.ignored {
+        notes.push(format!("    {} ({} entries)", reason, entries.len()));
+    }
+
+    rules
+}
+
+fn confirm_close(names: &[String], termination: Termination) -> bool {
+    match termination {
+        Termination::Graceful => confirm_box(format!(
+            "These applications use files to clean:\n{}\n\n\
+            Ask them to close?\n\
+            Answer No to skip the files they use.",
+            names.join("\n")
+        )),
+        Termination::Forced => confirm_box(format!(
+            "These applications did not close:\n{}\n\n\
+            Force them to close? Unsaved work will be lost.\n\
+            Answer No to skip the files they use.",
+            names.join("\n")
+        )),
+    }
+}
+
+// Keeps the rules whose applications are closed, the others are set aside
+// with the applications still holding them
+fn without_running(
+    rules: Vec<Rule>,
+    running: &[String],
+    skipped: &mut Vec<(Rule, String)>,
+) -> Vec<Rule> {
+    let mut kept: Vec<Rule> = Vec::new();
+
+    for rule in rules {
+        let blocked_by: Vec<String> = rule.blocked_by(running);
+
+        if blocked_by.is_empty() {
+            kept.push(rule);
+        } else {
+            skipped.push((rule, format!("{} still running", blocked_by.join(", "))));
+        }
+    }
+
+    kept
+}
+
+// Without elevation only the rules inside the profile of the current user are
+// kept, system locations are reported instead of failing one file at a time
+fn within_privileges(
+    rules: Vec<Rule>,
+    privileges: &Privileges,
+    skipped: &mut Vec<(Rule, String)>,
+) -> Vec<Rule> {
+    if privileges.elevated {
+        return rules;
+    }
+
+    let mut kept: Vec<Rule> = Vec::new();
+
+    for rule in rules {
+        if rule.is_per_user() {
+            kept.push(rule);
+        } else {
+            skipped.push((rule, "requires administrator rights".to_string()));
+        }
+    }
+
+    kept
+}
+
+// Profiles known to the OS and inside the configured scope, without elevation
+// only the one of the current user. The current user stands alone when the
+// list cannot be read.
+pub fn get_profiles(
+    system_vars: &HashMap<&'static str, PathBuf>,
+    privileges: &Privileges,
+    scope: &UserScope,
+) -> Vec<Profile> {
+    select_profiles(&SystemProfiles::new(), system_vars, privileges, scope)
+}
+
+// The profiles of `source` the scope reaches, only the current one without
+// elevation and never one without its directory
+fn select_profiles(
+    source: &dyn ProfileSource,
+    system_vars: &HashMap<&'static str, PathBuf>,
+    privileges: &Privileges,
+    scope: &UserScope,
+) -> Vec<Profile> {
+    // Left out when it is not a directory, then no profile is the current one
+    let user_profile: Option<&PathBuf> = system_vars.get("user_profile");
+    let is_current = |p: &Profile| user_profile.map(|u| same_path(&p.path, u)).unwrap_or(false);
+    let username: String = var("USERNAME").or(var("USER")).unwrap_or("Default".to_string());
+
+    let profiles: Vec<Profile> = match source.profiles() {
+        Ok(p) => p,
+        Err(_) => user_profile.into_iter()
+            .map(|u| Profile {
+                id: username.clone(),
+                name: username.clone(),
+                path: u.clone(),
+                temp: var_os("TEMP").or(var_os("TMPDIR")).map(PathBuf::from),
+                kind: AccountKind::User,
+            })
+            .collect(),
+    };
+
+    profiles.into_iter()
+        .filter(|p| privileges.elevated || is_current(p))
+        .filter(|p| scope.includes(p, is_current(p)))
+        .filter(|p| is_real_dir(&Disk, &p.path))
+        .collect()
+}
+
+fn check_path(path: &Path, panic: bool) -> bool {
+    if !path.is_absolute() || !path.has_root() || !path.exists() || !path.is_dir() {
+        if panic {
+            error_box(format!("Invalid Path {} ... Abandoned for Safety", path.display()).to_string());
+            restart();
+            panic!("Err");
+        }
+
+        return false;
+    }
+
+    true
+}
+
+// Variables that must name a directory, the others may be missing
+#[cfg(windows)]
+const CHECKED_VARS: [&str; 5] = ["system_root", "sys32", "all_user_profile", "program_files", "user_profile"];
+#[cfg(unix)]
+const CHECKED_VARS: [&str; 1] = ["user_profile"];
+
+// The variables of this system as the environment gives them, unchecked: for
+// what only needs their names or moves them below another root
+#[cfg(windows)]
+pub fn read_system_vars() -> HashMap<&'static str, PathBuf> {
+    let mut system_vars = HashMap::new();
+
+    let system_drive_string: String = var("SYSTEMDRIVE")
+        .unwrap_or(
+            "C:".to_string()
+        );
+
+    let system_drive: PathBuf = PathBuf::from(
+        var_os("SYSTEMDRIVE")
+            .unwrap_or(
+                OsString::from("C:")
+            )
+    );
+
+    let all_user_profile: PathBuf = PathBuf::from(
+        var_os("ALLUSERSPROFILE")
+            .unwrap_or(
+                OsString::from(format!("{}\\ProgramData", system_drive_string))
+            )
+    );
+
+    let system_root: PathBuf = PathBuf::from(
+        var_os("SYSTEMROOT")
+            .unwrap_or(
+                OsString::from(
+                    format!("{}\\Windows", &system_drive_string)
+                )
+            )
+    );
+
+    let sys32: PathBuf = system_root.join(PathBuf::from("System32"));
+
+    let program_files: PathBuf = PathBuf::from(
+        var_os("ProgramFiles")
+            .unwrap_or(
+                OsString::from(format!("{}\\Program Files", system_drive_string))
+            )
+    );
+
+    let username: String = var("USERNAME")
+        .unwrap_or(
+            "Default".to_string()
+        );
+
+    let user_profile_string: String = var("USERPROFILE")
+        .unwrap_or(
+            format!("{}\\Users\\{}", system_drive_string, username)
+        );
+
+    let user_profile: PathBuf = PathBuf::from(
+        var_os("USERPROFILE")
+            .unwrap_or(
+                OsString::from(&user_profile_string)
+            )
+    );
+
+    system_vars.insert("system_drive", system_drive);
+    system_vars.insert("system_root", system_root);
+    system_vars.insert("sys32", sys32);
+    system_vars.insert("all_user_profile", all_user_profile);
+    system_vars.insert("program_files", program_files);
+    system_vars.insert("user_profile", user_profile);
+
+    system_vars
+}
+
+#[cfg(unix)]
+pub fn read_system_vars() -> HashMap<&'static str, PathBuf> {
+    let mut system_vars = HashMap::new();
+
+    let user_profile: PathBuf = PathBuf::from(var_os("HOME").unwrap_or(OsString::from("/root")));
+
+    system_vars.insert("root", PathBuf::from("/"));
+    system_vars.insert("user_profile", user_profile);
+
+    if let Some(cache) = var_os("XDG_CACHE_HOME").map(PathBuf::from).filter(|c| c.is_absolute()) {
+        system_vars.insert("xdg_cache_home", cache);
+    }
+
+    system_vars
+}
+
+// The variables of this system, a clean is abandoned when one of them is not a
+// directory
+pub fn get_system_vars() -> HashMap<&'static str, PathBuf> {
+    let system_vars: HashMap<&'static str, PathBuf> = read_system_vars();
+
+    for name in CHECKED_VARS.iter() {
+        if let Some(path) = system_vars.get(name) {
+            check_path(path, true);
+        }
+    }
+
+    system_vars
+}
+
+// The same variables for what only looks at the system: the ones that are not
+// a directory are left out with a note
+pub fn probe_system_vars(notes: &mut Vec<String>) -> HashMap<&'static str, PathBuf> {
+    let mut system_vars: HashMap<&'static str, PathBuf> = read_system_vars();
+
+    system_vars.retain(|name, path| {
+        let valid: bool = !CHECKED_VARS.contains(name) || check_path(path, false);
+
+        if !valid {
+            notes.push(format!("%{}% : {} is not a directory, left out", name, path.display()));
+        }
+
+        valid
+    });
+
+    system_vars
+}
+
+// The same path with `root` in place of the root of this system
+fn reroot(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.components().filter(|c| matches!(c, Component::Normal(_))).collect::<PathBuf>())
+}
+
+#[cfg(windows)]
+fn reference_profile(path: PathBuf) -> Option<Profile> {
+    let name: String = path.file_name()?.to_string_lossy().to_string();
+    let temp: Option<PathBuf> = Some(path.join("AppData\\Local\\Temp"));
+
+    Some(Profile { id: name.clone(), name, path, temp, kind: AccountKind::User })
+}
+
+#[cfg(unix)]
+fn reference_profile(path: PathBuf) -> Option<Profile> {
+    let name: String = path.file_name()?.to_string_lossy().to_string();
+    let id: String = symlink_metadata(&path).ok()?.uid().to_string();
+
+    Some(Profile { id, name, path, temp: None, kind: AccountKind::User })
+}
+
+// The variables of this system moved below `root`, and one profile per
+// directory of its homes
+pub fn reference_layout(root: &Path) -> (HashMap<&'static str, PathBuf>, Vec<Profile>) {
+    let mut system_vars: HashMap<&'static str, PathBuf> = read_system_vars().into_iter()
+        .map(|(k, v)| (k, reroot(root, &v)))
+        .collect();
+
+    let mut profiles: Vec<Profile> = read_dir(root.join(HOMES)).into_iter()
+        .flat_map(|entries| entries.filter_map(|e| e.ok()))
+        .map(|e| e.path())
+        .filter(|p| symlink_metadata(p).map(|m| m.is_dir()).unwrap_or(false))
+        .filter_map(reference_profile)
+        .collect();
+
+    profiles.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if let Some(first) = profiles.first() {
+        system_vars.insert("user_profile", first.path.clone());
+    }
+
+    (system_vars, profiles)
+}
+
+// Empties every bin with something in it. The report tells a trash that could
+// not be read from an empty one.
+fn purge_all(trash: &dyn TrashBackend, volumes: &[TrashVolume]) -> (u64, u64, String) {
+    let mut len: u64 = 0;
+    let mut size: u64 = 0;
+    let mut lines: String = String::new();
+
+    for volume in volumes.iter().filter(|v| v.len > 0) {
+        match trash.purge(volume) {
+            Ok(_) => {
+                len += volume.len;
+                size += volume.size;
+                lines.push_str(&format!(
+                    "    {} : {} files => {} deleted\r\n", volume.location.display(), volume.len, convert(volume.size as f64)
+                ));
+            }
+            Err(e) => lines.push_str(&format!(
+                "    {} : {} files => {} left, {}\r\n", volume.location.display(), volume.len, convert(volume.size as f64), e
+            )),
+        }
+    }
+
+    (len, size, format!("Recycle Bin : {} files => {} deleted\r\n{}\r\n", len, convert(size as f64), lines))
+}
+
+// Files and bytes per volume
+fn tally(items: &[TrashItem]) -> BTreeMap<PathBuf, (u64, u64)> {
+    let mut totals: BTreeMap<PathBuf, (u64, u64)> = BTreeMap::new();
+
+    for item in items {
+        let total = totals.entry(item.volume.clone()).or_default();
+        *total = (total.0 + 1, total.1 + item.size);
+    }
+
+    totals
+}
+
+// Only what the policy selects goes, across every volume so that a size budget
+// applies to the whole trash. An item that could not be removed is kept.
+fn purge_selected(trash: &dyn TrashBackend, volumes: &[TrashVolume], policy: TrashPolicy) -> (u64, u64, String) {
+    let mut items: Vec<TrashItem> = Vec::new();
+    let mut lines: String = String::new();
+
+    for volume in volumes.iter().filter(|v| v.len > 0) {
+        match trash.items(volume) {
+            Ok(i) => items.extend(i),
+            Err(e) => lines.push_str(&format!("    {} : unavailable, {}\r\n", volume.location.display(), e)),
+        }
+    }
+
+    let (selected, mut retained) = select(items, policy, Local::now().naive_local());
+    let mut purged: Vec<TrashItem> = Vec::new();
+
+    for item in selected {
+        match trash.remove(&item) {
+            Ok(_) => purged.push(item),
+            Err(_) => retained.push(item),
+        }
+    }
+
+    let deleted: BTreeMap<PathBuf, (u64, u64)> = tally(&purged);
+    let kept: BTreeMap<PathBuf, (u64, u64)> = tally(&retained);
+    let volumes: BTreeSet<&PathBuf> = deleted.keys().chain(kept.keys()).collect();
+
+    for volume in volumes {
+        let (d_len, d_size) = deleted.get(volume).copied().unwrap_or_default();
+        let (k_len, k_size) = kept.get(volume).copied().unwrap_or_default();
+
+        lines.push_str(&format!(
+            "    {} : {} files => {} deleted, {} files => {} kept\r\n",
+            volume.display(), d_len, convert(d_size as f64), k_len, convert(k_size as f64)
+        ));
+    }
+
+    let len: u64 = purged.len() as u64;
+    let size: u64 = purged.iter().map(|i| i.size).sum();
+    let kept: u64 = retained.iter().map(|i| i.size).sum();
+
+    (len, size, format!(
+        "Recycle Bin : {} files => {} deleted, {} files => {} kept\r\n{}\r\n",
+        len, convert(size as f64), retained.len(), convert(kept as f64), lines
+    ))
+}
+
+fn empty_trash(trash: &dyn TrashBackend, policy: TrashPolicy) -> (u64, u64, String) {
+    if policy == TrashPolicy::Keep {
+        return (0, 0, "Recycle Bin : left alone\r\n\r\n".to_string());
+    }
+
+    let volumes: Vec<TrashVolume> = match trash.query() {
+        Ok(v) => v,
+        Err(e) => return (0, 0, format!("Recycle Bin : unavailable, {}\r\n\r\n", e)),
+    };
+
+    match policy {
+        TrashPolicy::All => purge_all(trash, &volumes),
+        _ => purge_selected(trash, &volumes, policy),
+    }
+}
+
+// Deleted files under the targets that processes still hold open, by this
+// clean or an earlier one: why the free space did not grow as much
+fn held_space(provider: &dyn ProcessProvider, roots: &[PathBuf]) -> String {
+    let mut files: Vec<OpenFile> = provider.open_files().unwrap_or_default()
+        .into_iter()
+        .filter(|f| f.deleted && roots.iter().any(|r| f.path.starts_with(r)))
+        .collect();
+
+    if files.is_empty() {
+        return String::new();
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut ids: BTreeSet<(u64, u64)> = BTreeSet::new();
+    let total: u64 = files.iter().filter(|f| ids.insert((f.dev, f.id))).map(|f| f.size).sum();
+
+    let mut content: String = "\r\nHeld by open deleted files :\r\n".to_string();
+
+    for f in &files {
+        content.push_str(&format!("    {} : {} held by {} ({})\r\n", f.path.display(), convert(f.size as f64), f.process, f.pid));
+    }
+
+    content.push_str(&format!("{} freed once these processes close them\r\n", convert(total as f64)));
+
+    content
+}
+
+fn user_breakdown(user: &str, rules: &BTreeMap<String, (u64, u64)>, action: &str) -> String {
+    let len: u64 = rules.values().map(|r| r.0).sum();
+    let size: u64 = rules.values().map(|r| r.1).sum();
+    let mut text: String = format!("{} : {} files => {} {}\r\n", user, len, convert(size as f64), action);
+
+    for (rule, (len, size)) in rules {
+        text.push_str(&format!("    {} : {} files => {}\r\n", rule, len, convert(*size as f64)));
+    }
+
+    text
+}
+
+// What a scan found, cleaned afterwards by `execute`
+pub struct Plan {
+    privileges: Privileges,
+    profiles: Vec<Profile>,
+    components: Vec<TempComponent>,
+    skipped: Vec<(Rule, String)>,
+    notes: Vec<String>,
+}
+
+impl Plan {
+    // Root, number of files and size of every component
+    pub fn targets(&self) -> Vec<(PathBuf, u64, u64)> {
+        self.components.iter().map(|c| (c.path.clone(), c.len, c.size)).collect()
+    }
+
+    pub fn skipped(&self) -> Vec<(String, String)> {
+        self.skipped.iter().map(|(r, reason)| (r.describe(), reason.clone())).collect()
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    pub fn add_notes(&mut self, notes: Vec<String>) {
+        self.notes.extend(notes);
+    }
+
+    // Narrows the plan to some of its own roots, anything else is left alone
+    pub fn retain(&mut self, roots: &[PathBuf]) {
+        self.components.retain(|c| roots.iter().any(|r| r == &c.path));
+    }
+}
+
+pub struct Summary {
+    pub len: u64,
+    pub size: u64,
+    pub action: &'static str,
+    pub report: PathBuf,
+    // Some files wait for the next start of the system
+    pub reboot: bool,
+}
+
+// Decides whether applications are asked to close, then whether the ones
+// refusing are forced to
+pub type Confirm<'a> = &'a mut dyn FnMut(&[String], Termination) -> bool;
+
+// Finds what the rules allow to clean. With `confirm` the applications of the
+// profiles in scope holding a target are asked to close once it agrees, and it
+// decides whether the ones that refuse are forced to. Without it they are left
+// running. Rules they hold are skipped.
+pub fn scan(old: bool, settings: &Settings, system_vars: &HashMap<&'static str, PathBuf>, confirm: Option<Confirm>) -> Plan {
+    let privileges: Privileges = Privileges::acquire(&[SE_RESTORE, SE_DEBUG, SE_SHUTDOWN]);
+
+    let users_profiles: Vec<Profile> = get_profiles(system_vars, &privileges, &settings.user_scope);
+
+    let nbr_row: u64 = 41 + 3;
+
+    progress::status("Close applications ...");
+
+    let owners: Vec<String> = users_profiles.iter().map(|p| p.name.clone()).collect();
+
+    let plan: Plan = plan(old, settings, privileges, system_vars, users_profiles, &Disk, |apps| match confirm {
+        Some(c) => close_applications(&SystemProvider::new(), apps, &owners, Duration::from_secs(settings.close_timeout), c),
+        None => running_applications(&SystemProvider::new(), apps, &owners),
+    });
+
+    let missing = nbr_row.saturating_sub(plan.components.len() as u64) * 2;
+
+    for _ in 0..missing {
+        thread::sleep(Duration::from_millis(50));
+        progress::advance(1);
+    }
+
+    plan
+}
+
+// What an elevated clean would find on a system laid out by `system_vars` and
+// `users_profiles` and indexed on `fs`, with every application closed
+pub fn dry_run(
+    old: bool,
+    settings: &Settings,
+    system_vars: &HashMap<&'static str, PathBuf>,
+    users_profiles: Vec<Profile>,
+    fs: &dyn Filesystem,
+) -> Plan {
+    let mut privileges: Privileges = Privileges::current();
+    privileges.elevated = true;
+
+    plan(old, settings, privileges, system_vars, users_profiles, fs, |_| Vec::new())
+}
+
+// The rules resolved on one layout, its files indexed on `fs`. `running` gets
+// the applications the rules need closed and answers the ones still running.
+fn plan<F>(
+    old: bool,
+    settings: &Settings,
+    privileges: Privileges,
+    system_vars: &HashMap<&'static str, PathBuf>,
+    users_profiles: Vec<Profile>,
+    fs: &dyn Filesystem,
+    running: F,
+) -> Plan
+    where F: FnOnce(&[String]) -> Vec<String> {
+    let mut skipped: Vec<(Rule, String)> = Vec::new();
+    let walk_all: Vec<Rule> = within_privileges(walk_all_rules(system_vars), &privileges, &mut skipped);
+    let glob_files: Vec<Rule> = within_privileges(glob_file_rules(), &privileges, &mut skipped);
+    let browser_rules: Vec<Rule> = within_privileges(profile_rules(), &privileges, &mut skipped);
+
+    let mut notes: Vec<String> = Vec::new();
+    let mut imported: Vec<Rule> = Vec::new();
+
+    if let Some(ref path) = settings.winapp2 {
+        imported.extend(imported_rules(fs, path, load_winapp2(path), system_vars, &users_profiles, &mut notes));
+    }
+
+    if let Some(ref path) = settings.cleanerml {
+        imported.extend(imported_rules(fs, path, load_cleanerml(path), system_vars, &users_profiles, &mut notes));
+    }
+
+    let imported: Vec<Rule> = within_privileges(imported, &privileges, &mut skipped);
+
+    let old_rules: Vec<Rule> = match old {
+        true => within_privileges(windows_old_rules(), &privileges, &mut skipped),
+        false => Vec::new(),
+    };
+
+    let mut apps: Vec<String> = rule_apps(&walk_all);
+    apps.extend(rule_apps(&browser_rules));
+    apps.extend(rule_apps(&imported));
+
+    let running: Vec<String> = running(&apps);
+
+    let walk_all: Vec<Rule> = without_running(walk_all, &running, &mut skipped);
+    let browser_rules: Vec<Rule> = without_running(browser_rules, &running, &mut skipped);
+    let imported: Vec<Rule> = without_running(imported, &running, &mut skipped);
+
+    progress::status("Start clean ...");
+
+    let browsers: HashMap<String, Vec<(PathBuf, &Profile)>> = browser_profiles(fs, &users_profiles);
+    let mut temp_components: Vec<TempComponent> = Vec::new();
+
+    get_components(fs, &mut temp_components, &walk_all, system_vars, &users_profiles, &browsers);
+    get_components(fs, &mut temp_components, &glob_files, system_vars, &users_profiles, &browsers);
+    get_components(fs, &mut temp_components, &browser_rules, system_vars, &users_profiles, &browsers);
+    get_components(fs, &mut temp_components, &imported, system_vars, &users_profiles, &browsers);
+
+    if old {
+        let first: usize = temp_components.len();
+        get_components(fs, &mut temp_components, &old_rules, system_vars, &users_profiles, &browsers);
+
+        // windows.old goes away as a whole, its root included
+        for temp_component in temp_components[first..].iter_mut() {
+            temp_component.min_depth = 0;
+        }
+    }
+
+    // Targets of skipped rules and what the system declares kept are never touched
+    let kept: Vec<Rule> = kept_rules(system_vars);
+    let protected: Vec<PathBuf> = skipped.iter()
+        .map(|(rule, _)| rule)
+        .chain(kept.iter())
+        .flat_map(|rule| rule_targets(fs, rule, system_vars, &users_profiles, &browsers))
+        .map(|(path, _)| path)
+        .collect();
+
+    let temp_components: Vec<TempComponent> = index_components(fs, temp_components, &protected);
+
+    Plan { privileges, profiles: users_profiles, components: temp_components, skipped, notes }
+}
+
+// Every target of every rule on this system, whatever the privileges, the
+// applications running or the settings of the clean
+pub fn target_roots(settings: &Settings) -> Vec<PathBuf> {
+    let mut notes: Vec<String> = Vec::new();
+    let system_vars: HashMap<&'static str, PathBuf> = probe_system_vars(&mut notes);
+    let users_profiles: Vec<Profile> = get_profiles(&system_vars, &Privileges::current(), &settings.user_scope);
+    let browsers: HashMap<String, Vec<(PathBuf, &Profile)>> = browser_profiles(&Disk, &users_profiles);
+
+    let mut rules: Vec<Rule> = walk_all_rules(&system_vars);
+    rules.extend(glob_file_rules());
+    rules.extend(profile_rules());
+    rules.extend(windows_old_rules());
+
+    if let Some(ref p) = settings.winapp2 {
+        rules.extend(imported_rules(&Disk, p, load_winapp2(p), &system_vars, &users_profiles, &mut notes));
+    }
+
+    if let Some(ref p) = settings.cleanerml {
+        rules.extend(imported_rules(&Disk, p, load_cleanerml(p), &system_vars, &users_profiles, &mut notes));
+    }
+
+    rules.iter()
+        .flat_map(|rule| rule_targets(&Disk, rule, &system_vars, &users_profiles, &browsers))
+        .map(|(path, _)| path)
+        .collect()
+}
+
+// Cleans what the plan holds and writes the report on the desktop
+pub fn execute(plan: Plan, settings: &Settings) -> Result<Summary, Error> {
+    let Plan { privileges, profiles, components: temp_components, skipped, mut notes } = plan;
+    let local: DateTime<Local> = Local::now();
+    let user_profile: PathBuf = PathBuf::from(var_os("USERPROFILE").or(var_os("HOME")).unwrap_or(OsString::from("C:\\")));
+    let local_datetime = local.format("%a %b %e %T %Y");
+    let report_dir: PathBuf = match is_real_dir(&Disk, &user_profile.join("Desktop")) {
+        true => user_profile.join("Desktop"),
+        false => user_profile.clone(),
+    };
+    let report_stem: String = format!("KpTemp_{}", local.format("%Y-%m-%d_%H-%M-%S"));
+    let report: PathBuf = report_dir.join(format!("{}.txt", report_stem));
+
+    let mut total_len = 0;
+    let mut total_size = 0;
+    let mut reboot: bool = false;
+
+    // Only a clean finishes what the last one deferred, a scan changes nothing
+    progress::status("Check deferred deletions ...");
+    let settled: Vec<(Deferred, Outcome)> = match settle(&settings.deferred_queue) {
+        Ok(s) => s,
+        Err(e) => {
+            notes.push(format!("{} : {}", settings.deferred_queue.display(), e));
+            Vec::new()
+        }
+    };
+
+    let mut context = CleanContext {
+        fs: &Disk,
+        quarantine: match settings.quarantine {
+            true => Some(Quarantine::create(&settings.quarantine_dir)?),
+            false => None,
+        },
+        shred: settings.shred_options(),
+        deferred: Vec::new(),
+        retry: Retry {
+            attempts: settings.retry_attempts,
+            backoff: Duration::from_millis(settings.retry_backoff),
+            max_wait: Duration::from_millis(settings.retry_max_wait),
+        },
+        waited: Duration::ZERO,
+    };
+
+    let action: &str = match context.quarantine {
+        Some(_) => "moved to quarantine",
+        None => "deleted",
+    };
+
+    let mut total_shredded = 0;
+    let mut total_retried = 0;
+    let mut per_user: BTreeMap<String, BTreeMap<String, (u64, u64)>> = BTreeMap::new();
+
+    let mut output = File::create(&report)?;
+
+    output.write_all(format!("KpTemp v{} by kernel-panik\r\n", KPTEMP_VERSION).as_bytes())?;
+    output.write_all(format!("Date: {}\r\n", local_datetime).as_bytes())?;
+    output.write_all(format!("Privileges: {}\r\n", privileges.describe()).as_bytes())?;
+
+    for profile in &profiles {
+        output.write_all(format!("Profile: {} => {}\r\n", profile.describe(), profile.path.display()).as_bytes())?;
+    }
+
+    if let Some(ref q) = context.quarantine {
+        output.write_all(format!("Quarantine: {}\r\n", q.run_dir().display()).as_bytes())?;
+    }
+
+    output.write_all("\r\n".as_bytes())?;
+
+    if !settled.is_empty() {
+        output.write_all("Deferred by the last clean :\r\n".as_bytes())?;
+    }
+
+    for (item, outcome) in &settled {
+        output.write_all(format!(
+            "    {} : {} (queued {}, {})\r\n",
+            item.path.display(),
+            outcome.describe(),
+            item.queued,
+            item.reason
+        ).as_bytes())?;
+    }
+
+    if !settled.is_empty() {
+        output.write_all("\r\n".as_bytes())?;
+    }
+
+    if temp_components.is_empty() {
+        output.write_all("No records found\n".as_bytes())?;
+    }
+
+    let provider = SystemProvider::new();
+    let roots: Vec<PathBuf> = temp_components.iter().map(|c| c.path.clone()).collect();
+    let open: Vec<OpenFile> = match settings.open_files {
+        OpenFiles::Ignore => Vec::new(),
+        _ => provider.open_files().unwrap_or_default(),
+    };
+
+    for mut temp_component in temp_components {
+        find_open(&mut temp_component, &open, settings.open_files);
+        clean_component(&mut temp_component, &mut context);
+        reboot |= temp_component.need_reboot;
+
+        total_size += temp_component.size;
+        total_len += temp_component.len;
+        total_shredded += temp_component.shredded;
+        total_retried += temp_component.retried;
+
+        let counts = per_user
+            .entry(temp_component.owner.clone().unwrap_or("System".to_string()))
+            .or_default()
+            .entry(temp_component.rule.clone().unwrap_or(temp_component.path.display().to_string()))
+            .or_insert((0, 0));
+
+        counts.0 += temp_component.len;
+        counts.1 += temp_component.size;
+
+        output.write_all(format!(
+            "{} : {} files => {} {}\r\n",
+            temp_component.path.display(),
+            temp_component.len,
+            convert(temp_component.size as f64),
+            action
+        ).as_bytes())?;
+
+        // Shredding happens on removal, what waits for the reboot is only
+        // deleted then and a quarantine keeps its files whole
+        if temp_component.shred && context.shred.is_some() && context.quarantine.is_none() {
+            let deferred: usize = context.deferred.iter()
+                .filter(|d| d.kind == Kind::File && same_path(&d.root, &temp_component.path))
+                .count();
+
+            output.write_all(format!(
+                "    {} shredded, {} left to the reboot unshredded\r\n",
+                temp_component.shredded,
+                deferred
+            ).as_bytes())?;
+        }
+
+        if temp_component.copied > 0 {
+            output.write_all(format!(
+                "    {} files copied to the quarantine from another volume\r\n",
+                temp_component.copied
+            ).as_bytes())?;
+        }
+
+        if temp_component.retried > 0 {
+            output.write_all(format!("    {} files needed retries\r\n", temp_component.retried).as_bytes())?;
+        }
+
+        for file in &temp_component.open {
+            output.write_all(format!(
+                "    {} : open by {} ({}), {}\r\n",
+                file.path.display(),
+                file.process,
+                file.pid,
+                match temp_component.scanned.contains_key(&file.path) {
+                    true => "deleted anyway",
+                    false => "skipped",
+                }
+            ).as_bytes())?;
+        }
+
+        for link in &temp_component.links {
+            output.write_all(format!(
+                "    {} : link removed, target left untouched\r\n",
+                link.display()
+            ).as_bytes())?;
+        }
+
+        for changed in &temp_component.changed {
+            output.write_all(format!(
+                "    {} : changed since the scan, skipped\r\n",
+                changed.display()
+            ).as_bytes())?;
+        }
+
+        for (failed, reason) in &temp_component.failed {
+            output.write_all(format!("    {} : not removed, {}\r\n", failed.display(), reason).as_bytes())?;
+        }
+    }
+
+    if settings.open_files != OpenFiles::Ignore {
+        output.write_all(held_space(&provider, &roots).as_bytes())?;
+    }
+
+    for item in &context.deferred {
+        output.write_all(format!("{} : deferred {}, {}\r\n", item.path.display(), WHEN, item.reason).as_bytes())?;
+    }
+
+    if let Err(e) = append(&settings.deferred_queue, &context.deferred) {
+        output.write_all(format!("{} : {}\r\n", settings.deferred_queue.display(), e).as_bytes())?;
+    }
+
+    for (rule, reason) in &skipped {
+        output.write_all(format!("{} : skipped, {}\r\n", rule.describe(), reason).as_bytes())?;
+    }
+
+    for note in &notes {
+        output.write_all(format!("{}\r\n", note).as_bytes())?;
+    }
+
+    if !per_user.is_empty() {
+        output.write_all("\r\nPer user :\r\n".as_bytes())?;
+    }
+
+    for (user, rules) in &per_user {
+        output.write_all(user_breakdown(user, rules, action).as_bytes())?;
+    }
+
+    // What was cleaned from every other profile goes to the reports directory,
+    // never into the profile: its owner could have planted a link there
+    let others: Vec<&Profile> = profiles.iter()
+        .filter(|p| p.kind == AccountKind::User && !same_path(&p.path, &user_profile))
+        .filter(|p| per_user.contains_key(&p.name))
+        .collect();
+
+    if !others.is_empty() {
+        if let Err(e) = create_dir(&settings.reports_dir) {
+            output.write_all(format!("{} : {}\r\n", settings.reports_dir.display(), e).as_bytes())?;
+        }
+    }
+
+    for profile in others {
+        let content: String = format!(
+            "KpTemp v{} by kernel-panik\r\nDate: {}\r\n\r\n{}",
+            KPTEMP_VERSION,
+            local_datetime,
+            user_breakdown(&profile.name, &per_user[&profile.name], action)
+        );
+
+        let name: String = format!("{}_{}.txt", report_stem, profile.name.replace(['/', '\\', ':'], "_"));
+
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(settings.reports_dir.join(name))
+            .and_then(|mut f| f.write_all(content.as_bytes()))
+            .ignore();
+    }
+
+    progress::status("Clear recycle bin");
+    let (r_len, r_size, recycle_bin) = empty_trash(&SystemTrash::new(), settings.recycle_bin);
+
+    output.write_all(recycle_bin.as_bytes())?;
+
+    // The recycle bin is emptied whatever the clean does with the targets
+    let total: String = match context.quarantine {
+        Some(_) if r_len > 0 => format!(
+            "{} files => {} {}, {} files => {} deleted from the recycle bin",
+            total_len,
+            convert(total_size as f64),
+            action,
+            r_len,
+            convert(r_size as f64)
+        ),
+        Some(_) => format!("{} files => {} {}", total_len, convert(total_size as f64), action),
+        None => {
+            total_len += r_len;
+            total_size += r_size;
+
+            format!("{} files => {} {}", total_len, convert(total_size as f64), action)
+        }
+    };
+
+    output.write_all(format!("Total : {}\r\n", total).as_bytes())?;
+
+    if total_retried > 0 {
+        output.write_all(format!(
+            "\r\nRetried : {} files held by another process, {} ms waited\r\n",
+            total_retried,
+            context.waited.as_millis()
+        ).as_bytes())?;
+    }
+
+    if total_shredded > 0 {
+        output.write_all(format!("\r\nShredded : {} files\r\n{}\r\n", total_shredded, SSD_WARNING).as_bytes())?;
+    }
+
+    progress::advance(1);
+    progress::finish();
+
+    Ok(Summary { len: total_len, size: total_size, action, report, reboot })
+}
+
+pub fn clean(old: bool, settings: &Settings) -> Result<(), Error> {
+    let plan: Plan = scan(old, settings, &get_system_vars(), Some(&mut confirm_close));
+    let summary: Summary = execute(plan, settings)?;
+
+    message_box(format!("Success {} files and {} {}", summary.len, convert(summary.size as f64), summary.action));
+
+    Ok(())
+}
+
+// What a clean would do with one path, from the mildest to the strongest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Keep,
+    Protected,
+    Delete,
+    Reboot,
+}
+
+impl Action {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Action::Keep => "keep",
+            Action::Protected => "protected",
+            Action::Delete => "delete",
+            Action::Reboot => "delete at reboot",
+        }
+    }
+}
+
+// A rule whose target holds the path, the value its variable took and why it
+// would or would not remove the path
+pub struct Reason {
+    pub rule: String,
+    pub var: String,
+    pub base: PathBuf,
+    pub owner: Option<String>,
+    pub target: PathBuf,
+    pub action: Action,
+    pub why: String,
+}
+
+pub struct Explanation {
+    pub reasons: Vec<Reason>,
+    pub notes: Vec<String>,
+    pub action: Action,
+}
+
+// Another process holds the file open without sharing it, removing it waits
+// for the next reboot
+#[cfg(windows)]
+fn is_locked(path: &Path) -> bool {
+    use std::fs::OpenOptions;
+    use std::os::windows::fs::OpenOptionsExt;
+
+    match OpenOptions::new().read(true).share_mode(0).open(path) {
+        Err(e) => matches!(e.raw_os_error(), Some(32) | Some(33)),
+        Ok(_) => false,
+    }
+}
+
+// An open file is unlinked all the same
+#[cfg(unix)]
+fn is_locked(_path: &Path) -> bool {
+    false
+}
+
+// The directories between `root` excluded and `path` included
+fn below<'a>(path: &'a Path, root: &'a Path) -> impl Iterator<Item = &'a Path> {
+    path.ancestors().take_while(move |a| is_below(a, root) && !same_path(a, root))
+}
+
+// The same checks as a clean, in the same order: the target, what other rules
+// hold inside it, the rule itself, then the file
+fn judge(
+    path: &Path,
+    rule: &Rule,
+    target: &Path,
+    owner: Option<&Profile>,
+    whole: bool,
+    held: &[(PathBuf, String, Action)],
+    settings: &Settings,
+) -> (Action, String) {
+    if !is_safe_target(&Disk, rule, target, owner) {
+        return (Action::Protected, "target refused by the safety checks".to_string());
+    }
+
+    if same_path(path, target) && !whole {
+        return (Action::Keep, "the target itself stays, only its content goes".to_string());
+    }
+
+    let holder: Option<&(PathBuf, String, Action)> = held.iter()
+        .find(|(root, _, _)| is_below(root, target) && !same_path(root, target) && is_below(path, root));
+
+    if let Some((root, why, action)) = holder {
+        return (*action, format!("inside {}, {}", root.display(), why));
+    }
+
+    let kept: Option<&PathBuf> = rule.keep.iter()
+        .find(|k| below(path, target).any(|a| path_matches(&target.join(k), a)));
+
+    if let Some(k) = kept {
+        return (Action::Protected, format!("kept by the rule, {}", k.display()));
+    }
+
+    let existing: &Path = path.ancestors().find(|a| symlink_metadata(a).is_ok()).unwrap_or(path);
+
+    if is_below(existing, target) && !is_contained(&Disk, existing, target) {
+        return (Action::Protected, "reached through a link, never followed".to_string());
+    }
+
+    if let Some(ref pattern) = rule.files {
+        let depth: usize = below(path, target).count();
+
+        if is_real_dir(&Disk, path) {
+            return (Action::Keep, format!("a folder, only the {} files inside go", pattern));
+        }
+
+        if depth > 1 && !rule.recursive {
+            return (Action::Keep, "below the first level, not searched".to_string());
+        }
+
+        if !path.file_name().map(|n| name_matches(pattern, n)).unwrap_or(false) {
+            return (Action::Keep, format!("does not match {}", pattern));
+        }
+    }
+
+    if rule.keep_dirs.iter().any(|k| path_matches(&target.join(k), path)) {
+        return (Action::Protected, format!("kept by the rule, {} stays, its content goes", path.display()));
+    }
+
+    // What lies in a young directory still goes on its own
+    if symlink_metadata(path).is_ok() && is_young(&Disk, path, rule.min_age, rule.age_by) {
+        return (Action::Keep, "changed too recently for the rule".to_string());
+    }
+
+    let removal: &str = match (settings.quarantine, rule.shred && settings.shred_options().is_some()) {
+        (true, _) => "moved to quarantine",
+        (false, true) => "shredded",
+        (false, false) => "removed",
+    };
+
+    if is_link(&Disk, path) {
+        return (Action::Delete, "link removed, its target left untouched".to_string());
+    }
+
+    if is_real_file(&Disk, path) && is_locked(path) {
+        return (Action::Reboot, format!("in use, {} at the next reboot", removal));
+    }
+
+    (Action::Delete, removal.to_string())
+}
+
+// Why a clean would remove a path or leave it, rule by rule. Nothing is
+// changed: applications are only looked for and privileges only checked. With
+// `root` the rules are resolved on the system mounted there, as an elevated
+// clean with every application closed would see it.
+pub fn explain(path: &Path, root: Option<&Path>, old: bool, settings: &Settings) -> Explanation {
+    let mut privileges: Privileges = Privileges::current();
+    privileges.elevated |= root.is_some();
+
+    let mut notes: Vec<String> = Vec::new();
+
+    let (system_vars, users_profiles) = match root {
+        Some(r) => reference_layout(r),
+        None => {
+            let system_vars: HashMap<&'static str, PathBuf> = probe_system_vars(&mut notes);
+            let users_profiles: Vec<Profile> = get_profiles(&system_vars, &privileges, &settings.user_scope);
+            (system_vars, users_profiles)
+        }
+    };
+
+    let mut rules: Vec<Rule> = walk_all_rules(&system_vars);
+    rules.extend(glob_file_rules());
+    rules.extend(profile_rules());
+
+    if let Some(ref p) = settings.winapp2 {
+        rules.extend(imported_rules(&Disk, p, load_winapp2(p), &system_vars, &users_profiles, &mut notes));
+    }
+
+    if let Some(ref p) = settings.cleanerml {
+        rules.extend(imported_rules(&Disk, p, load_cleanerml(p), &system_vars, &users_profiles, &mut notes));
+    }
+
+    // windows.old goes away as a whole, its root included
+    let first_old: usize = rules.len();
+
+    if old {
+        rules.extend(windows_old_rules());
+    }
+
+    let running: Vec<String> = match root {
+        Some(_) => Vec::new(),
+        None => {
+            let owners: Vec<String> = users_profiles.iter().map(|p| p.name.clone()).collect();
+            running_applications(&SystemProvider::new(), &rule_apps(&rules), &owners)
+        }
+    };
+
+    let browsers: HashMap<String, Vec<(PathBuf, &Profile)>> = browser_profiles(&Disk, &users_profiles);
+    let mut skipped: Vec<(Rule, String)> = Vec::new();
+    let mut active: Vec<(bool, Rule)> = Vec::new();
+
+    for (i, rule) in rules.into_iter().enumerate() {
+        let allowed: Vec<Rule> = within_privileges(vec![rule], &privileges, &mut skipped);
+        active.extend(without_running(allowed, &running, &mut skipped).into_iter().map(|r| (i >= first_old, r)));
+    }
+
+    // What other rules hold inside a target: the targets of skipped and kept
+    // rules are protected, nested roots cleared by their own rule
+    let kept: Vec<Rule> = kept_rules(&system_vars);
+    let protected: Vec<(&Rule, String)> = skipped.iter()
+        .map(|(r, why)| (r, format!("rule skipped, {}", why)))
+        .chain(kept.iter().map(|r| (r, "kept by the system".to_string())))
+        .collect();
+
+    let mut held: Vec<(PathBuf, String, Action)> = Vec::new();
+
+    for (rule, why) in &protected {
+        for (target, _) in rule_targets(&Disk, rule, &system_vars, &users_profiles, &browsers) {
+            held.push((target, format!("{} : {}", rule.describe(), why), Action::Protected));
+        }
+    }
+
+    for (_, rule) in active.iter().filter(|(_, r)| r.files.is_none()) {
+        for (target, owner) in rule_targets(&Disk, rule, &system_vars, &users_profiles, &browsers) {
+            if is_safe_target(&Disk, rule, &target, owner) {
+                held.push((target, format!("left to {}", rule.describe()), Action::Keep));
+            }
+        }
+    }
+
+    let mut reasons: Vec<Reason> = Vec::new();
+
+    let candidates = active.iter().map(|(whole, r)| (r, *whole, None))
+        .chain(protected.iter().map(|(r, why)| (*r, false, Some(why))));
+
+    for (rule, whole, skip) in candidates {
+        for (base, owner) in var_bases(&rule.var, &system_vars, &users_profiles, &browsers) {
+            for target in target_paths(&Disk, &base, rule).into_iter().filter(|t| is_below(path, t)) {
+                let (action, why) = match skip {
+                    Some(why) => (Action::Protected, why.clone()),
+                    None => judge(path, rule, &target, owner, whole, &held, settings),
+                };
+
+                reasons.push(Reason {
+                    rule: rule.describe(),
+                    var: rule.var.clone(),
+                    base: base.clone(),
+                    owner: owner.map(|p| p.name.clone()),
+                    target,
+                    action,
+                    why,
+                });
+            }
+        }
+    }
+
+    if symlink_metadata(path).is_err() {
+        notes.push(format!("{} does not exist, only its name was checked", path.display()));
+    }
+
+    let action: Action = reasons.iter().map(|r| r.action).max().unwrap_or(Action::Keep);
+
+    Explanation { reasons, notes, action }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use crate::privilege::Privileges;
+    use crate::process::{OpenFile, OpenFiles};
+    use crate::quarantine::Quarantine;
+    use crate::profiles::{AccountKind, Profile, UserScope};
+    use crate::profiles::mock::FixedProfiles;
+    use crate::rules::{AgeBy, glob_file_rules};
+    use crate::settings::Settings;
+    use crate::testing::TempRoot;
+    use crate::vfs::{Disk, Filesystem};
+    use crate::vfs::faults::{Fault, FaultyFs};
+    use crate::vfs::memory::MemoryFs;
+
+    use super::{
+        clean_component, CleanContext, dry_run, find_open, get_components, get_len_and_size, index_components, is_young, Retry,
+        select_profiles, TempComponent, walk,
+    };
+
+    const HOUR: Duration = Duration::from_secs(3600);
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    fn context(fs: &dyn Filesystem) -> CleanContext<'_> {
+        CleanContext {
+            fs,
+            quarantine: None,
+            shred: None,
+            deferred: Vec::new(),
+            retry: Retry { attempts: 0, backoff: Duration::ZERO, max_wait: Duration::ZERO },
+            waited: Duration::ZERO,
+        }
+    }
+
+    // Indexed like a scan does before the clean
+    fn component(fs: &dyn Filesystem, root: &str, min_age: Option<Duration>) -> TempComponent {
+        let mut component: TempComponent = TempComponent::new_clear(PathBuf::from(root));
+        component.min_age = min_age;
+
+        walk(fs, &mut component, |p, c| get_len_and_size(fs, p, c));
+        component
+    }
+
+    #[test]
+    fn files_are_as_young_as_their_newest_stamp() {
+        let fs: MemoryFs = MemoryFs::new()
+            .file("/t/read", 1, 30 * DAY)
+            .accessed("/t/read", HOUR)
+            .file("/t/old", 1, 30 * DAY);
+
+        assert!(is_young(&fs, Path::new("/t/read"), Some(DAY), AgeBy::default()));
+        assert!(!is_young(&fs, Path::new("/t/read"), Some(DAY), AgeBy::parse("m").unwrap()));
+        assert!(!is_young(&fs, Path::new("/t/old"), Some(DAY), AgeBy::default()));
+        assert!(!is_young(&fs, Path::new("/t/read"), None, AgeBy::default()));
+        assert!(is_young(&fs, Path::new("/t/missing"), Some(DAY), AgeBy::default()));
+    }
+
+    #[test]
+    fn directories_are_as_young_as_their_content() {
+        let fs: MemoryFs = MemoryFs::new()
+            .file("/t/d/old", 1, 30 * DAY)
+            .aged("/t/d", 30 * DAY)
+            .file("/t/e/sub/new", 1, HOUR)
+            .aged("/t/e/sub", 30 * DAY)
+            .aged("/t/e", 30 * DAY)
+            .accessed("/t/d", HOUR);
+
+        // Reading a directory does not make it young, unless `A` asks for it
+        assert!(!is_young(&fs, Path::new("/t/d"), Some(DAY), AgeBy::default()));
+        assert!(is_young(&fs, Path::new("/t/d"), Some(DAY), AgeBy::parse("amA").unwrap()));
+
+        assert!(is_young(&fs, Path::new("/t/e"), Some(DAY), AgeBy::default()));
+
+        // Only the directories named by capitals are judged on their own
+        // stamps, their files by the small letters
+        assert!(!is_young(&fs, Path::new("/t/d"), Some(DAY), AgeBy::parse("m").unwrap()));
+        assert!(is_young(&fs, Path::new("/t/e"), Some(DAY), AgeBy::parse("m").unwrap()));
+    }
+
+    #[test]
+    fn aged_clean_keeps_young_entries() {
+        let fs: MemoryFs = MemoryFs::new()
+            .file("/t/old/a", 10, 30 * DAY)
+            .aged("/t/old", 30 * DAY)
+            .file("/t/mixed/b", 20, 30 * DAY)
+            .file("/t/mixed/c", 40, HOUR)
+            .aged("/t/mixed", 30 * DAY)
+            .file("/t/d", 80, 30 * DAY)
+            .accessed("/t/d", HOUR);
+
+        let mut component: TempComponent = component(&fs, "/t", Some(DAY));
+        let mut context: CleanContext = context(&fs);
+
+        assert_eq!((component.len, component.size), (2, 30));
+
+        clean_component(&mut component, &mut context);
+
+        assert!(!fs.exists(Path::new("/t/old")));
+        assert!(!fs.exists(Path::new("/t/mixed/b")));
+        assert!(fs.exists(Path::new("/t/mixed/c")));
+        assert!(fs.exists(Path::new("/t/d")));
+    }
+
+    #[test]
+    fn kept_directories_stay_while_their_content_goes() {
+        let fs: MemoryFs = MemoryFs::new()
+            .file("/t/keep/a", 1, 30 * DAY)
+            .file("/t/keep/sub/b", 1, 30 * DAY)
+            .aged("/t/keep/sub", 30 * DAY)
+            .aged("/t/keep", 30 * DAY)
+            .file("/t/other/c", 1, 30 * DAY)
+            .aged("/t/other", 30 * DAY);
+
+        let mut component: TempComponent = component(&fs, "/t", Some(DAY));
+        component.kept_dirs = vec![PathBuf::from("/t/kee*")];
+
+        let mut context: CleanContext = context(&fs);
+        clean_component(&mut component, &mut context);
+
+        assert!(fs.exists(Path::new("/t/keep")));
+        assert!(!fs.exists(Path::new("/t/keep/a")));
+        assert!(!fs.exists(Path::new("/t/keep/sub")));
+        assert!(!fs.exists(Path::new("/t/other")));
+        assert!(fs.scheduled().is_empty());
+    }
+
+    fn scheduled(fs: &MemoryFs) -> Vec<PathBuf> {
+        fs.scheduled().into_iter().map(|(p, _)| p).collect()
+    }
+
+    #[test]
+    fn locked_files_wait_for_the_reboot() {
+        let fs: FaultyFs<MemoryFs> = FaultyFs::new(
+            MemoryFs::new()
+                .file("/t/d/locked", 10, DAY)
+                .file("/t/d/free", 20, DAY)
+                .file("/t/other", 40, DAY)
+                .link("/t/link"),
+        ).fail("/t/d/locked", Fault::SharingViolation);
+
+        let mut component: TempComponent = component(&fs, "/t", None);
+        let mut context: CleanContext = context(&fs);
+        clean_component(&mut component, &mut context);
+
+        assert!(!fs.inner().exists(Path::new("/t/d/free")));
+        assert!(!fs.inner().exists(Path::new("/t/other")));
+        assert!(!fs.inner().exists(Path::new("/t/link")));
+        assert_eq!(component.links, vec![PathBuf::from("/t/link")]);
+
+        // Counted as removed, the file first so that its directory is empty
+        // by the time the reboot reaches it
+        assert!(component.need_reboot);
+        assert_eq!((component.len, component.size), (3, 70));
+        assert_eq!(scheduled(fs.inner()), vec![PathBuf::from("/t/d/locked"), PathBuf::from("/t/d")]);
+        assert_eq!(context.deferred.len(), 2);
+
+        fs.inner().reboot();
+        assert!(!fs.inner().exists(Path::new("/t/d")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn read_only_files_are_scheduled_once_cleared() {
+        let fs: FaultyFs<MemoryFs> = FaultyFs::new(MemoryFs::new().file("/t/a", 10, DAY).readonly("/t/a"))
+            .fail("/t/a", Fault::ReadOnly);
+
+        let mut component: TempComponent = component(&fs, "/t", None);
+        let mut context: CleanContext = context(&fs);
+        clean_component(&mut component, &mut context);
+
+        // Refused by the clean and again before the attribute is cleared
+        assert_eq!(fs.failures(), vec![(PathBuf::from("/t/a"), Fault::ReadOnly); 2]);
+        assert_eq!(scheduled(fs.inner()), vec![PathBuf::from("/t/a")]);
+        assert_eq!((component.len, component.size), (1, 10));
+    }
+
+    // Nothing but a busy entry waits for the reboot on Linux
+    #[cfg(unix)]
+    #[test]
+    fn refused_files_are_not_deferred() {
+        let fs: FaultyFs<MemoryFs> = FaultyFs::new(
+            MemoryFs::new().file("/t/d/a", 10, DAY).readonly("/t/d/a").file("/t/b", 20, DAY),
+        ).fail("/t/d/a", Fault::ReadOnly);
+
+        let mut component: TempComponent = component(&fs, "/t", None);
+        let mut context: CleanContext = context(&fs);
+        clean_component(&mut component, &mut context);
+
+        assert!(fs.inner().exists(Path::new("/t/d/a")));
+        assert!(!fs.inner().exists(Path::new("/t/b")));
+        assert!(scheduled(fs.inner()).is_empty());
+        assert!(context.deferred.is_empty());
+        assert!(!component.need_reboot);
+        assert_eq!((component.len, component.size), (1, 20));
+
+        // Its directory stays for it and is not reported on its own
+        let failed: Vec<&Path> = component.failed.iter().map(|(p, _)| p.as_path()).collect();
+        assert_eq!(failed, vec![Path::new("/t/d/a")]);
+    }
+
+    #[test]
+    fn denied_entries_leave_the_counts() {
+        let fs: FaultyFs<MemoryFs> = FaultyFs::new(
+            MemoryFs::new()
+                .file("/t/denied", 10, DAY)
+                .file("/t/free", 20, DAY)
+                .dir("/t/empty"),
+        ).fail("/t/denied", Fault::AccessDenied);
+
+        let mut component: TempComponent = component(&fs, "/t", None);
+        assert_eq!((component.len, component.size), (2, 30));
+
+        let mut context: CleanContext = context(&fs);
+        clean_component(&mut component, &mut context);
+
+        assert!(fs.inner().exists(Path::new("/t/denied")));
+        assert!(!fs.inner().exists(Path::new("/t/empty")));
+        assert!(scheduled(fs.inner()).is_empty());
+        assert_eq!((component.len, component.size), (1, 20));
+        assert_eq!(component.failed.len(), 1);
+        assert_eq!(component.failed[0].0, PathBuf::from("/t/denied"));
+    }
+
+    #[test]
+    fn open_files_are_matched_on_their_inode() {
+        let fs: MemoryFs = MemoryFs::new().file("/t/held", 10, DAY).file("/t/free", 20, DAY);
+        let mut component: TempComponent = component(&fs, "/t", None);
+
+        let held = |path: &str, file: &str| {
+            let id: u64 = fs.fingerprint(Path::new(file)).unwrap().id;
+            OpenFile { pid: 10, process: "app".to_string(), path: path.into(), deleted: false, dev: 0, id, size: 10 }
+        };
+
+        // /proc gives the canonical path, and a path alone may name another
+        // file of another device
+        let open: Vec<OpenFile> = vec![
+            held("/private/t/held", "/t/held"),
+            OpenFile { dev: 1, ..held("/t/free", "/t/free") },
+        ];
+        find_open(&mut component, &open, OpenFiles::Skip);
+
+        let paths: Vec<&Path> = component.open.iter().map(|o| o.path.as_path()).collect();
+        assert_eq!(paths, vec![Path::new("/t/held")]);
+        assert_eq!((component.len, component.size), (1, 20));
+
+        let mut context: CleanContext = context(&fs);
+        clean_component(&mut component, &mut context);
+
+        assert!(fs.exists(Path::new("/t/held")));
+        assert!(!fs.exists(Path::new("/t/free")));
+    }
+
+    #[test]
+    fn quarantine_on_another_volume_takes_copies() {
+        let store: TempRoot = TempRoot::new();
+        let quarantine: Quarantine = Quarantine::create(store.path()).unwrap();
+        let run: PathBuf = quarantine.run_dir().to_path_buf();
+
+        let fs: MemoryFs = MemoryFs::new()
+            .file("/t/a", 10, DAY)
+            .file("/t/b", 20, DAY)
+            .mount(&run.to_string_lossy());
+
+        let mut component: TempComponent = component(&fs, "/t", None);
+        let mut context: CleanContext = context(&fs);
+        context.quarantine = Some(quarantine);
+
+        clean_component(&mut component, &mut context);
+
+        assert!(!fs.exists(Path::new("/t/a")) && !fs.exists(Path::new("/t/b")));
+        assert!(fs.exists(&run.join("00000001")) && fs.exists(&run.join("00000002")));
+        assert_eq!(component.copied, 2);
+        assert_eq!((component.len, component.size), (2, 30));
+    }
+
+    #[test]
+    fn files_changed_since_the_scan_leave_the_counts() {
+        let fs: MemoryFs = MemoryFs::new().file("/t/a", 10, DAY).file("/t/b", 20, DAY);
+        let mut component: TempComponent = component(&fs, "/t", None);
+
+        fs.rewrite("/t/b", 25);
+
+        let mut context: CleanContext = context(&fs);
+        clean_component(&mut component, &mut context);
+
+        assert!(!fs.exists(Path::new("/t/a")));
+        assert!(fs.exists(Path::new("/t/b")));
+        assert_eq!(component.changed, vec![PathBuf::from("/t/b")]);
+        assert_eq!((component.len, component.size), (1, 10));
+        assert!(fs.scheduled().is_empty());
+    }
+
+    #[test]
+    fn transient_faults_are_retried() {
+        let fs: FaultyFs<MemoryFs> = FaultyFs::new(MemoryFs::new().file("/t/a", 10, DAY).file("/t/b", 20, DAY))
+            .fail_times("/t/a", Fault::InUse, 2)
+            .fail_times("/t/b", Fault::SharingViolation, 4);
+
+        let mut component: TempComponent = component(&fs, "/t", None);
+        let mut context: CleanContext = context(&fs);
+        context.retry = Retry { attempts: 2, backoff: Duration::ZERO, max_wait: Duration::ZERO };
+
+        clean_component(&mut component, &mut context);
+
+        // The first one goes on the second retry, the other outlasts them and
+        // waits for the reboot
+        assert!(!fs.inner().exists(Path::new("/t/a")));
+        assert_eq!(scheduled(fs.inner()), vec![PathBuf::from("/t/b")]);
+        assert_eq!(component.retried, 2);
+        assert_eq!(fs.failures().len(), 6);
+        assert_eq!((component.len, component.size), (2, 30));
+    }
+
+    #[test]
+    fn retries_stop_at_the_time_allowed() {
+        let fs: FaultyFs<MemoryFs> = FaultyFs::new(MemoryFs::new().file("/t/a", 10, DAY))
+            .fail("/t/a", Fault::SharingViolation);
+
+        let mut component: TempComponent = component(&fs, "/t", None);
+        let mut context: CleanContext = context(&fs);
+        context.retry = Retry { attempts: 5, backoff: Duration::from_millis(10), max_wait: Duration::from_millis(25) };
+
+        clean_component(&mut component, &mut context);
+
+        // 10 ms, then 20 more would go over
+        assert_eq!(context.waited, Duration::from_millis(10));
+        assert_eq!(scheduled(fs.inner()), vec![PathBuf::from("/t/a")]);
+    }
+
+    // The package rules on a fake root: package caches lose their packages
+    // only and logs their rotations only, the temp directories follow the
+    // host's tmpfiles.d
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn package_targets_on_a_fake_root() {
+        let root: TempRoot = TempRoot::new();
+        let files: [(&str, u32); 7] = [
+            ("var/cache/apt/archives/a.deb", 0),
+            ("var/cache/apt/archives/b.deb", 0),
+            ("var/cache/apt/archives/lock", 0),
+            ("var/log/syslog.1", 0),
+            ("var/log/apt/term.log.1", 0),
+            ("var/log/syslog.2.gz", 0),
+            ("var/log/apt/history.log.1.gz", 0),
+        ];
+
+        for (file, days) in files.iter() {
+            root.file(file, "x");
+            root.aged(file, DAY * *days);
+        }
+
+        root.file("var/log/syslog", "x");
+
+        let system_vars: HashMap<&'static str, PathBuf> = vec![("root", root.path().to_path_buf())].into_iter().collect();
+        let mut components: Vec<TempComponent> = Vec::new();
+
+        get_components(&Disk, &mut components, &glob_file_rules(), &system_vars, &[], &HashMap::new());
+
+        let mut counts: Vec<(PathBuf, String, u64)> = index_components(&Disk, components, &[]).iter()
+            .map(|c| (c.path.strip_prefix(root.path()).unwrap().to_path_buf(), c.files.clone(), c.len))
+            .collect();
+        counts.sort();
+
+        let expected = |path: &str, files: &str, len: u64| (Path::new(path).to_path_buf(), files.to_string(), len);
+
+        assert_eq!(counts, vec![
+            expected("var/cache/apt/archives", "*.deb", 2),
+            expected("var/log", "*.1", 2),
+            expected("var/log", "*.gz", 2),
+        ]);
+    }
+
+    // Nothing of the layout is on this system, every lookup goes to the memory
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn dry_run_on_a_layout_in_memory() {
+        let fs: MemoryFs = MemoryFs::new()
+            .file("/nonexistent-layout/tmp/old", 100, DAY * 20)
+            .file("/nonexistent-layout/tmp/older", 200, DAY * 30)
+            .file("/nonexistent-layout/tmp/young", 7, HOUR)
+            .file("/nonexistent-layout/var/cache/apt/archives/a.deb", 50, DAY)
+            .file("/nonexistent-layout/var/cache/apt/archives/b.deb", 60, DAY)
+            .file("/nonexistent-layout/home/u/.cache/mozilla/firefox/abc.default/cache2/a", 10, DAY)
+            .file("/nonexistent-layout/home/u/.cache/mozilla/firefox/abc.default/cache2/b", 20, DAY)
+            .link("/nonexistent-layout/home/u/.cache/mozilla/firefox/elsewhere")
+            .file("/nonexistent-layout/home/u/tmp/x", 3, DAY)
+            .file("/nonexistent-layout/home/u/tmp/y", 4, DAY)
+            .owned("/nonexistent-layout/home/u/tmp", "1000")
+            .file("/nonexistent-layout/home/v/tmp/x", 5, DAY)
+            .file("/nonexistent-layout/home/v/tmp/y", 6, DAY)
+            .owned("/nonexistent-layout/home/v/tmp", "1000");
+
+        let profile = |id: &str, name: &str| Profile {
+            id: id.to_string(),
+            name: name.to_string(),
+            path: PathBuf::from(format!("/nonexistent-layout/home/{}", name)),
+            temp: Some(PathBuf::from(format!("/nonexistent-layout/home/{}/tmp", name))),
+            kind: AccountKind::User,
+        };
+
+        let system_vars: HashMap<&'static str, PathBuf> = vec![
+            ("root", PathBuf::from("/nonexistent-layout")),
+            ("user_profile", PathBuf::from("/nonexistent-layout/home/u")),
+            ("xdg_cache_home", PathBuf::from("/nonexistent-layout/home/u/xdg")),
+        ].into_iter().collect();
+
+        let users_profiles: Vec<Profile> = vec![profile("1000", "u"), profile("1001", "v")];
+        let mut targets: Vec<(PathBuf, u64, u64)> = dry_run(false, &Settings::default(), &system_vars, users_profiles, &fs).targets();
+        targets.sort();
+
+        let expected = |path: &str, len: u64, size: u64| (PathBuf::from(path), len, size);
+
+        // The temp directory of v belongs to u, a link is no browser profile
+        assert_eq!(targets, vec![
+            expected("/nonexistent-layout/home/u/.cache/mozilla/firefox/abc.default/cache2", 2, 30),
+            expected("/nonexistent-layout/home/u/tmp", 2, 7),
+            expected("/nonexistent-layout/tmp", 2, 300),
+            expected("/nonexistent-layout/var/cache/apt/archives", 2, 110),
+        ]);
+    }
+
+    #[test]
+    fn profiles_within_privileges_and_scope() {
+        let root: TempRoot = TempRoot::new();
+
+        for home in ["me", "other", "svc"] {
+            root.file(&format!("{}/file", home), "");
+        }
+
+        let home = |name: &str| root.path().join(name).to_string_lossy().to_string();
+        let source: FixedProfiles = FixedProfiles::new()
+            .with("1000", "me", &home("me"), AccountKind::User)
+            .with("1001", "other", &home("other"), AccountKind::User)
+            .with("1002", "gone", &home("gone"), AccountKind::User)
+            .with("112", "svc", &home("svc"), AccountKind::Service);
+
+        let mut system_vars: HashMap<&'static str, PathBuf> = HashMap::new();
+        system_vars.insert("user_profile", root.path().join("me"));
+
+        let select = |elevated: bool, scope: &str| -> Vec<String> {
+            let privileges: Privileges = Privileges { elevated, granted: Vec::new(), missing: Vec::new() };
+
+            select_profiles(&source, &system_vars, &privileges, &UserScope::parse(scope)).into_iter()
+                .map(|p: Profile| p.name)
+                .collect()
+        };
+
+        // Without elevation only the current profile, whatever the scope
+        assert_eq!(select(false, "interactive,services"), vec!["me"]);
+        assert_eq!(select(true, "current"), vec!["me"]);
+
+        // A profile whose directory is gone is never reached
+        assert_eq!(select(true, "interactive"), vec!["me", "other"]);
+        assert_eq!(select(true, "services"), vec!["svc"]);
+        assert_eq!(select(true, "current, OTHER"), vec!["me", "other"]);
+        assert!(select(true, "").is_empty());
+
+        // Without a valid profile of its own, the caller is none of them
+        system_vars.remove("user_profile");
+
+        let privileges: Privileges = Privileges { elevated: false, granted: Vec::new(), missing: Vec::new() };
+        assert!(select_profiles(&source, &system_vars, &privileges, &UserScope::parse("current")).is_empty());
+    }
 }
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::io::Error;
+use std::path::{absolute, Path, PathBuf};
+
+use pretty_bytes::converter::convert;
+
+use crate::clean::{clean, explain, Explanation, get_system_vars, reference_layout, target_roots};
+use crate::deferred::{cancel, Deferred, load as load_deferred, WHEN};
+use crate::lint::{Finding, lint, lint_reference};
+use crate::privilege::{Privileges, SE_RESTORE};
+use crate::quarantine::{list_runs, purge, restore};
+use crate::rules::{
+    glob_file_rules, Import, load_cleanerml, load_winapp2, profile_rules, Rule, walk_all_rules, windows_old_rules,
+};
+use crate::settings::Settings;
+use crate::snapshot::{export, load, replay, Replay, Snapshot};
+use crate::worker::{request, serve};
+
+const USAGE: &str = "Usage:\n\
+    kp_temp clean [--old]\n\
+    kp_temp quarantine list\n\
+    kp_temp quarantine restore <run> [original path ...]\n\
+    kp_temp quarantine purge <days>\n\
+    kp_temp deferred list\n\
+    kp_temp deferred cancel [path ...]\n\
+    kp_temp winapp2 <file>\n\
+    kp_temp cleanerml <file or directory>\n\
+    kp_temp lint [--reference <directory>]\n\
+    kp_temp explain [--old] [--root <directory>] <path>\n\
+    kp_temp snapshot export [--old] <file>\n\
+    kp_temp snapshot replay [--old] <file>\n\
+    kp_temp worker [--once <pid>]\n\
+    kp_temp remote status\n\
+    kp_temp remote scan [--old]\n\
+    kp_temp remote clean [--old] [--quarantine] [root ...]";
+
+// Command line entry point, returns the process exit code
+pub fn run_command(args: &[String]) -> i32 {
+    let settings: Settings = Settings::load();
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+    match args.as_slice() {
+        ["clean"] => clean_now(false, &settings),
+        ["clean", "--old"] => clean_now(true, &settings),
+        ["quarantine", "list"] => quarantine_list(&settings),
+        ["quarantine", "restore", run, paths @ ..] => quarantine_restore(&settings, run, paths),
+        ["quarantine", "purge", days] => quarantine_purge(&settings, days),
+        ["deferred", "list"] => deferred_list(&settings),
+        ["deferred", "cancel", paths @ ..] => deferred_cancel(&settings, paths),
+        ["winapp2", file] => preview(Path::new(file), load_winapp2(Path::new(file))),
+        ["cleanerml", path] => preview(Path::new(path), load_cleanerml(Path::new(path))),
+        ["lint"] => lint_rules(&settings, None),
+        ["lint", "--reference", root] => lint_rules(&settings, Some(Path::new(root))),
+        ["explain", "--old", rest @ ..] => explain_path(&settings, true, rest),
+        ["explain", rest @ ..] => explain_path(&settings, false, rest),
+        ["snapshot", "export", "--old", file] => snapshot_export(&settings, true, file),
+        ["snapshot", "export", file] => snapshot_export(&settings, false, file),
+        ["snapshot", "replay", "--old", file] => snapshot_replay(&settings, true, file),
+        ["snapshot", "replay", file] => snapshot_replay(&settings, false, file),
+        ["worker"] => worker(None),
+        ["worker", "--once", pid] => match pid.parse::<u32>() {
+            Ok(pid) => worker(Some(pid)),
+            Err(_) => {
+                eprintln!("{}", USAGE);
+                2
+            }
+        },
+        ["remote", "status"] => remote(&["status"]),
+        ["remote", "scan", "--old"] => remote(&["scan", "1"]),
+        ["remote", "scan"] => remote(&["scan", "0"]),
+        ["remote", "clean", rest @ ..] => remote_clean(rest),
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    }
+}
+
+fn clean_now(old: bool, settings: &Settings) -> i32 {
+    match clean(old, settings) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("clean : {}", e);
+            1
+        }
+    }
+}
+
+fn quarantine_list(settings: &Settings) -> i32 {
+    let runs = match list_runs(&settings.quarantine_dir) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{} : {}", settings.quarantine_dir.display(), e);
+            return 1;
+        }
+    };
+
+    if runs.is_empty() {
+        println!("No quarantine run in {}", settings.quarantine_dir.display());
+    }
+
+    for run in runs {
+        println!("{} : {} files => {}", run.name, run.entries.len(), convert(run.size() as f64));
+    }
+
+    0
+}
+
+fn quarantine_restore(settings: &Settings, run: &str, paths: &[&str]) -> i32 {
+    let only: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+
+    let roots: Vec<PathBuf> = target_roots(settings);
+
+    // Files go back to their owners, which takes more than being an administrator
+    let _: Privileges = Privileges::acquire(&[SE_RESTORE]);
+
+    let results = match restore(&settings.quarantine_dir, run, &only, &roots) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{} : {}", run, e);
+            return 1;
+        }
+    };
+
+    let mut code: i32 = 0;
+
+    for (path, status) in results {
+        match status {
+            Ok(_) => println!("{} : restored", path.display()),
+            Err(e) => {
+                eprintln!("{} : {}", path.display(), e);
+                code = 1;
+            }
+        }
+    }
+
+    code
+}
+
+fn quarantine_purge(settings: &Settings, days: &str) -> i32 {
+    let days: u32 = match days.parse() {
+        Ok(d) => d,
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    match purge(&settings.quarantine_dir, days) {
+        Ok(purged) => {
+            for run in purged {
+                println!("{} : {} files => {} purged", run.name, run.entries.len(), convert(run.size() as f64));
+            }
+
+            0
+        }
+        Err(e) => {
+            eprintln!("{} : {}", settings.quarantine_dir.display(), e);
+            1
+        }
+    }
+}
+
+fn deferred_list(settings: &Settings) -> i32 {
+    let items: Vec<Deferred> = match load_deferred(&settings.deferred_queue) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("{} : {}", settings.deferred_queue.display(), e);
+            return 1;
+        }
+    };
+
+    for item in &items {
+        println!("{} : {} queued {}, {}", item.path.display(), convert(item.size() as f64), item.queued, item.reason);
+    }
+
+    println!("{} entries deferred {}", items.len(), WHEN);
+
+    0
+}
+
+// The system forgets them too, they are left where they are
+fn deferred_cancel(settings: &Settings, paths: &[&str]) -> i32 {
+    let only: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+
+    match cancel(&settings.deferred_queue, &only) {
+        Ok(cancelled) => {
+            for item in cancelled {
+                println!("{} : cancelled", item.path.display());
+            }
+
+            0
+        }
+        Err(e) => {
+            eprintln!("{} : {}", settings.deferred_queue.display(), e);
+            1
+        }
+    }
+}
+
+// What an imported rule database would add to a clean, and what it holds that
+// cannot be
+fn preview(file: &Path, import: Result<Import, Error>) -> i32 {
+    let import: Import = match import {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("{} : {}", file.display(), e);
+            return 1;
+        }
+    };
+
+    for entry in &import.entries {
+        println!("{} :", entry.name);
+
+        for rule in &entry.rules {
+            println!("    {}", rule.describe());
+        }
+
+        for detect in &entry.detect {
+            println!("    when {} exists", detect.describe());
+        }
+    }
+
+    println!("\n{} entries imported", import.entries.len());
+
+    for (reason, entries) in &import.ignored {
+        println!("\n{} ({} entries) :\n    {}", reason, entries.len(), entries.join("\n    "));
+    }
+
+    0
+}
+
+// The built-in rules and the ones imported by the settings, checked together
+// so that an import repeating a built-in target is caught
+fn lint_rules(settings: &Settings, reference: Option<&Path>) -> i32 {
+    let system_vars: HashMap<&'static str, PathBuf> = match reference {
+        Some(root) => reference_layout(root).0,
+        None => get_system_vars(),
+    };
+
+    let mut rules: Vec<Rule> = walk_all_rules(&system_vars);
+    rules.extend(glob_file_rules());
+    rules.extend(profile_rules());
+    rules.extend(windows_old_rules());
+
+    let imports = vec![
+        settings.winapp2.as_ref().map(|p| (p, load_winapp2(p))),
+        settings.cleanerml.as_ref().map(|p| (p, load_cleanerml(p))),
+    ];
+
+    for (path, import) in imports.into_iter().flatten() {
+        match import {
+            Ok(i) => rules.extend(i.entries.into_iter().flat_map(|e| e.rules)),
+            Err(e) => {
+                eprintln!("{} : {}", path.display(), e);
+                return 1;
+            }
+        }
+    }
+
+    let mut findings: Vec<Finding> = lint(&rules);
+
+    if let Some(root) = reference {
+        findings.extend(lint_reference(&rules, root));
+    }
+
+    for f in &findings {
+        println!("{} : {}", f.rule, f.problem);
+    }
+
+    println!("{} rules checked, {} findings", rules.len(), findings.len());
+
+    match findings.is_empty() {
+        true => 0,
+        false => 1,
+    }
+}
+
+// Every rule reaching the path, the variables it took and the verdict of each
+fn explain_path(settings: &Settings, old: bool, args: &[&str]) -> i32 {
+    let (root, path): (Option<&Path>, &str) = match args {
+        ["--root", root, path] => (Some(Path::new(root)), path),
+        [path] => (None, path),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    // Relative to the current directory, links are left for the rules to see
+    let path: PathBuf = match absolute(path) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{} : {}", path, e);
+            return 1;
+        }
+    };
+
+    let explanation: Explanation = explain(&path, root, old, settings);
+
+    println!("{} :", path.display());
+
+    if explanation.reasons.is_empty() {
+        println!("    no rule reaches it");
+    }
+
+    for reason in &explanation.reasons {
+        let owner: String = reason.owner.as_ref().map(|o| format!(" ({})", o)).unwrap_or_default();
+
+        println!("    {} : {}, {}", reason.rule, reason.action.describe(), reason.why);
+        println!("        %{}% = {}{}, target {}", reason.var, reason.base.display(), owner, reason.target.display());
+    }
+
+    for note in &explanation.notes {
+        println!("{}", note);
+    }
+
+    println!("Action : {}", explanation.action.describe());
+
+    0
+}
+
+fn snapshot_export(settings: &Settings, old: bool, file: &str) -> i32 {
+    match export(Path::new(file), old, settings) {
+        Ok(len) => {
+            println!("{} : {} entries", file, len);
+            0
+        }
+        Err(e) => {
+            eprintln!("{} : {}", file, e);
+            1
+        }
+    }
+}
+
+// What a clean would do on the exported system, found on a rebuilt copy of its
+// tree. Paths are shown as they were there.
+fn snapshot_replay(settings: &Settings, old: bool, file: &str) -> i32 {
+    let snapshot: Snapshot = match load(Path::new(file)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{} : {}", file, e);
+            return 1;
+        }
+    };
+
+    let replay: Replay = match replay(&snapshot, old, settings) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{} : {}", file, e);
+            return 1;
+        }
+    };
+
+    for (root, len, size) in replay.plan.targets() {
+        println!("{} : {} files => {}", replay.original(&root).display(), len, convert(size as f64));
+    }
+
+    for (rule, reason) in replay.plan.skipped() {
+        println!("{} : skipped, {}", rule, reason);
+    }
+
+    for note in replay.plan.notes() {
+        println!("{}", note);
+    }
+
+    println!("Dry run on the {} snapshot of {}, nothing deleted", snapshot.os, snapshot.date);
+
+    0
+}
+
+fn worker(launcher: Option<u32>) -> i32 {
+    match serve(launcher) {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("worker : {}", e);
+            1
+        }
+    }
+}
+
+fn remote_clean(args: &[&str]) -> i32 {
+    let (old, args): (&str, &[&str]) = match args {
+        ["--old", rest @ ..] => ("1", rest),
+        _ => ("0", args),
+    };
+
+    let (quarantine, roots): (&str, &[&str]) = match args {
+        ["--quarantine", rest @ ..] => ("1", rest),
+        _ => ("0", args),
+    };
+
+    remote(&[&["clean", old, quarantine], roots].concat())
+}
+
+fn remote(fields: &[&str]) -> i32 {
+    let answer: Vec<Vec<String>> = match request(fields) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("worker : {}", e);
+            return 1;
+        }
+    };
+
+    let mut code: i32 = 0;
+
+    for line in answer {
+        let fields: Vec<&str> = line.iter().map(|f| f.as_str()).collect();
+
+        match fields.as_slice() {
+            ["status", state @ ..] => println!("Worker : {}", state.join(" ")),
+            ["target", root, len, size] => println!(
+                "{} : {} files => {}", root, len, convert(size.parse().unwrap_or(0.0))
+            ),
+            ["skipped", rule, reason] => println!("{} : skipped, {}", rule, reason),
+            ["note", note] => println!("{}", note),
+            ["done", len, size, action, reboot, report] => {
+                println!("Success {} files and {} {}\nReport : {}", len, convert(size.parse().unwrap_or(0.0)), action, report);
+
+                if *reboot == "1" {
+                    println!("Restart the computer to finish the clean");
+                }
+            }
+            ["error", message] => {
+                eprintln!("worker : {}", message);
+                code = 1;
+            }
+            _ => eprintln!("worker : unexpected answer {}", line.join(" ")),
+        }
+    }
+
+    code
+}
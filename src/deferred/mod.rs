@@ -0,0 +1,298 @@
+use std::fs::{OpenOptions, read_to_string, remove_file, symlink_metadata, write};
+use std::io::{ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use crate::safe_remove::{Disposal, Fingerprint, Kind, Policy, remove_contained};
+use crate::secure::{check_owner, create_dir};
+
+#[cfg(unix)]
+pub use self::unix::WHEN;
+#[cfg(windows)]
+pub use self::windows::WHEN;
+#[cfg(unix)]
+use self::unix::{forget, pending};
+#[cfg(windows)]
+use self::windows::{forget, pending};
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// An entry the clean could not remove, one line of the queue:
+// `queued <TAB> kind <TAB> dev:id:size:modified <TAB> destination <TAB> root <TAB> reason <TAB> path`
+// with `-` for a missing fingerprint or destination
+#[derive(Clone, Debug)]
+pub struct Deferred {
+    pub queued: String,
+    pub kind: Kind,
+    pub fingerprint: Option<Fingerprint>,
+    // Quarantine store it is moved to instead of being deleted
+    pub destination: Option<PathBuf>,
+    // Root of its component, nothing outside of it is ever touched
+    pub root: PathBuf,
+    pub reason: String,
+    pub path: PathBuf,
+}
+
+impl Deferred {
+    pub fn new(
+        kind: Kind,
+        fingerprint: Option<Fingerprint>,
+        destination: Option<PathBuf>,
+        root: &Path,
+        reason: &str,
+        path: &Path,
+    ) -> Deferred {
+        Deferred {
+            queued: Local::now().format(DATE_FORMAT).to_string(),
+            kind,
+            fingerprint,
+            destination,
+            root: root.to_path_buf(),
+            reason: reason.replace(['\t', '\r', '\n'], " "),
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.fingerprint.map(|f| f.size).unwrap_or(0)
+    }
+
+    fn to_line(&self) -> String {
+        let kind: &str = match self.kind {
+            Kind::File => "file",
+            Kind::Dir => "dir",
+            Kind::Link => "link",
+        };
+
+        let fingerprint: String = match self.fingerprint {
+            Some(f) => format!("{}:{}:{}:{}", f.dev, f.id, f.size, f.modified),
+            None => "-".to_string(),
+        };
+
+        let destination: String = match self.destination {
+            Some(ref d) => d.display().to_string(),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\r\n",
+            self.queued, kind, fingerprint, destination, self.root.display(), self.reason, self.path.display()
+        )
+    }
+
+    fn parse(line: &str) -> Option<Deferred> {
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).splitn(7, '\t').collect();
+
+        let [queued, kind, fingerprint, destination, root, reason, path] = fields.as_slice() else {
+            return None;
+        };
+
+        let kind: Kind = match *kind {
+            "file" => Kind::File,
+            "dir" => Kind::Dir,
+            "link" => Kind::Link,
+            _ => return None,
+        };
+
+        let fingerprint: Option<Fingerprint> = match fingerprint.split(':').collect::<Vec<&str>>().as_slice() {
+            [dev, id, size, modified] => Some(Fingerprint {
+                dev: dev.parse().ok()?,
+                id: id.parse().ok()?,
+                size: size.parse().ok()?,
+                modified: modified.parse().ok()?,
+            }),
+            _ => None,
+        };
+
+        Some(Deferred {
+            queued: queued.to_string(),
+            kind,
+            fingerprint,
+            destination: Some(destination).filter(|d| **d != "-").map(PathBuf::from),
+            root: PathBuf::from(root),
+            reason: reason.to_string(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+// What became of a queued entry, found at the next start
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    // The system has not had its reboot yet
+    Pending,
+    // Gone before this start
+    Gone,
+    // Removed by this start
+    Removed,
+    Moved,
+    // Still there, with the reason
+    Left(String),
+    // Neither at its path nor in the quarantine
+    Lost,
+}
+
+impl Outcome {
+    pub fn describe(&self) -> String {
+        match self {
+            Outcome::Pending => "still waiting for the reboot".to_string(),
+            Outcome::Gone => "removed at reboot".to_string(),
+            Outcome::Removed => "removed at this start".to_string(),
+            Outcome::Moved => "moved to quarantine".to_string(),
+            Outcome::Left(reason) => format!("still present, {}", reason),
+            Outcome::Lost => "missing from the quarantine".to_string(),
+        }
+    }
+}
+
+// The queue names what an elevated clean removes, whoever else could write it
+// would choose that. It and its directory must belong to the administrators.
+fn check_queue(queue: &Path) -> Result<()> {
+    if let Some(parent) = queue.parent().filter(|p| !p.as_os_str().is_empty()) {
+        check_owner(parent)?;
+    }
+
+    check_owner(queue)
+}
+
+pub fn load(queue: &Path) -> Result<Vec<Deferred>> {
+    match check_queue(queue) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+        Ok(()) => (),
+    }
+
+    match read_to_string(queue) {
+        Ok(content) => Ok(content.lines().filter_map(Deferred::parse).collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn save(queue: &Path, items: &[Deferred]) -> Result<()> {
+    if items.is_empty() {
+        return match remove_file(queue) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+
+    write(queue, items.iter().map(|i| i.to_line()).collect::<String>())
+}
+
+pub fn append(queue: &Path, items: &[Deferred]) -> Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(parent) = queue.parent().filter(|p| !p.as_os_str().is_empty()) {
+        create_dir(parent)?;
+    }
+
+    match check_owner(queue) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(queue)?;
+
+    file.write_all(items.iter().map(|i| i.to_line()).collect::<String>().as_bytes())
+}
+
+// Only the queued file is accepted, what appeared in a queued directory since
+// stays and the directory with it
+struct Queued<'a> {
+    item: &'a Deferred,
+}
+
+impl<'a> Policy for Queued<'a> {
+    fn accept(&mut self, path: &Path, current: &Fingerprint) -> bool {
+        path == self.item.path && self.item.fingerprint.as_ref() == Some(current)
+    }
+
+    fn disposal(&mut self, _path: &Path, _current: &Fingerprint) -> Disposal {
+        match self.item.destination {
+            Some(ref d) => Disposal::Move(d.clone()),
+            None => Disposal::Delete,
+        }
+    }
+}
+
+fn exists(path: &Path) -> bool {
+    symlink_metadata(path).is_ok()
+}
+
+// Removes what is still there with the same checks as the clean, then looks
+// at where everything ended
+fn settle_item(item: &Deferred) -> Outcome {
+    if !exists(&item.path) {
+        return match item.destination {
+            Some(ref d) if !exists(d) => Outcome::Lost,
+            Some(_) => Outcome::Moved,
+            None => Outcome::Gone,
+        };
+    }
+
+    if let Err(e) = remove_contained(&item.root, &item.path, &mut Queued { item }) {
+        return Outcome::Left(e.to_string());
+    }
+
+    match item.destination {
+        Some(_) => Outcome::Moved,
+        None => Outcome::Removed,
+    }
+}
+
+// Run at every start: what the system still holds for its reboot stays
+// queued, everything else is finished and verified
+pub fn settle(queue: &Path) -> Result<Vec<(Deferred, Outcome)>> {
+    let items: Vec<Deferred> = load(queue)?;
+
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let pending: Vec<PathBuf> = pending();
+
+    // Queued parents first, their content has to go before them
+    let mut settled: Vec<(Deferred, Outcome)> = items.into_iter()
+        .rev()
+        .map(|item| match pending.contains(&item.path) {
+            true => (item, Outcome::Pending),
+            false => {
+                let outcome: Outcome = settle_item(&item);
+                (item, outcome)
+            }
+        })
+        .collect();
+
+    settled.reverse();
+
+    let waiting: Vec<Deferred> = settled.iter()
+        .filter(|(_, o)| *o == Outcome::Pending)
+        .map(|(i, _)| i.clone())
+        .collect();
+
+    save(queue, &waiting)?;
+
+    Ok(settled)
+}
+
+// Drops the given entries from the queue and from the system, every entry
+// without any path given
+pub fn cancel(queue: &Path, paths: &[PathBuf]) -> Result<Vec<Deferred>> {
+    let (cancelled, kept): (Vec<Deferred>, Vec<Deferred>) = load(queue)?
+        .into_iter()
+        .partition(|i| paths.is_empty() || paths.contains(&i.path));
+
+    forget(&cancelled.iter().map(|i| i.path.clone()).collect::<Vec<PathBuf>>())?;
+    save(queue, &kept)?;
+
+    Ok(cancelled)
+}
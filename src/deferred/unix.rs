@@ -0,0 +1,13 @@
+use std::io::Result;
+use std::path::PathBuf;
+
+pub const WHEN: &str = "at the next start";
+
+// Nothing removes files at boot, the whole queue runs at the next start
+pub fn pending() -> Vec<PathBuf> {
+    Vec::new()
+}
+
+pub fn forget(_paths: &[PathBuf]) -> Result<()> {
+    Ok(())
+}
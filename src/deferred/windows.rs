@@ -0,0 +1,136 @@
+use std::ffi::OsString;
+use std::io::{Error, Result};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
+use std::ptr::null_mut;
+
+use winapi::shared::minwindef::{DWORD, HKEY};
+use winapi::shared::winerror::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS};
+use winapi::um::winnt::{KEY_QUERY_VALUE, KEY_SET_VALUE, REG_MULTI_SZ};
+use winapi::um::winreg::{
+    HKEY_LOCAL_MACHINE, RegCloseKey, RegDeleteValueW, RegGetValueW, RegOpenKeyExW, RegSetValueExW, RRF_RT_REG_MULTI_SZ,
+};
+
+use crate::utils::to_utf16;
+
+pub const WHEN: &str = "at the next reboot";
+
+// Where MoveFileExW keeps what it delays until reboot: pairs of source and
+// destination, an empty destination for a deletion
+const SESSION_MANAGER: &str = "SYSTEM\\CurrentControlSet\\Control\\Session Manager";
+const PENDING_OPERATIONS: &str = "PendingFileRenameOperations";
+const NT_PREFIX: &str = "\\??\\";
+
+fn operations() -> Result<Vec<(String, String)>> {
+    let mut size: DWORD = 0;
+    let key: Vec<u16> = to_utf16(SESSION_MANAGER);
+    let value: Vec<u16> = to_utf16(PENDING_OPERATIONS);
+
+    let status = unsafe {
+        RegGetValueW(HKEY_LOCAL_MACHINE, key.as_ptr(), value.as_ptr(), RRF_RT_REG_MULTI_SZ, null_mut(), null_mut(), &mut size)
+    };
+
+    if status == ERROR_FILE_NOT_FOUND as i32 {
+        return Ok(Vec::new());
+    }
+
+    if status != ERROR_SUCCESS as i32 {
+        return Err(Error::from_raw_os_error(status));
+    }
+
+    let mut buffer: Vec<u16> = vec![0; size as usize / 2];
+
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_MULTI_SZ,
+            null_mut(),
+            buffer.as_mut_ptr() as _,
+            &mut size,
+        )
+    };
+
+    if status != ERROR_SUCCESS as i32 {
+        return Err(Error::from_raw_os_error(status));
+    }
+
+    // Empty destinations make the double nul ambiguous, only the size tells
+    // where the list ends
+    buffer.truncate(size as usize / 2);
+
+    let mut strings: Vec<String> = buffer.split(|c| *c == 0)
+        .map(|s| OsString::from_wide(s).to_string_lossy().to_string())
+        .collect();
+
+    strings.pop();
+
+    if strings.len() % 2 == 1 {
+        strings.pop();
+    }
+
+    Ok(strings.chunks(2).map(|p| (p[0].clone(), p[1].clone())).collect())
+}
+
+fn save_operations(operations: &[(String, String)]) -> Result<()> {
+    let mut key: HKEY = null_mut();
+    let status = unsafe {
+        RegOpenKeyExW(HKEY_LOCAL_MACHINE, to_utf16(SESSION_MANAGER).as_ptr(), 0, KEY_QUERY_VALUE | KEY_SET_VALUE, &mut key)
+    };
+
+    if status != ERROR_SUCCESS as i32 {
+        return Err(Error::from_raw_os_error(status));
+    }
+
+    let value: Vec<u16> = to_utf16(PENDING_OPERATIONS);
+
+    let status = match operations.is_empty() {
+        true => unsafe { RegDeleteValueW(key, value.as_ptr()) },
+        false => {
+            let data: Vec<u16> = operations.iter()
+                .flat_map(|(s, d)| [s, d])
+                .flat_map(|s| OsString::from(s).encode_wide().chain(Some(0)).collect::<Vec<u16>>())
+                .chain(Some(0))
+                .collect();
+
+            unsafe {
+                RegSetValueExW(key, value.as_ptr(), 0, REG_MULTI_SZ, data.as_ptr() as _, (data.len() * 2) as DWORD)
+            }
+        }
+    };
+
+    unsafe { RegCloseKey(key); }
+
+    if status != ERROR_SUCCESS as i32 {
+        return Err(Error::from_raw_os_error(status));
+    }
+
+    Ok(())
+}
+
+fn source(operation: &(String, String)) -> PathBuf {
+    PathBuf::from(operation.0.trim_start_matches(NT_PREFIX))
+}
+
+// Sources the system still has to move or delete at its next reboot
+pub fn pending() -> Vec<PathBuf> {
+    operations().unwrap_or_default().iter().map(source).collect()
+}
+
+// Takes the operations of the paths out of the list, the others are left as
+// they are
+pub fn forget(paths: &[PathBuf]) -> Result<()> {
+    let operations: Vec<(String, String)> = operations()?;
+
+    let kept: Vec<(String, String)> = operations.iter()
+        .filter(|o| !paths.contains(&source(o)))
+        .cloned()
+        .collect();
+
+    if kept.len() == operations.len() {
+        return Ok(());
+    }
+
+    save_operations(&kept)
+}
@@ -1,8 +1,13 @@
+#[cfg(windows)]
 use std::ptr::null_mut;
 
+#[cfg(windows)]
 use winapi::shared::windef::HWND;
 
+#[cfg(windows)]
 pub const TOTAL_STEP: u32 = 141;
 pub const KPTEMP_VERSION: &str = "1.4";
+#[cfg(windows)]
 pub static mut LABEL_HANDLE: HWND = null_mut();
+#[cfg(windows)]
 pub static mut PROGRESS_HANDLE: HWND = null_mut();
@@ -1,6 +1,8 @@
 use std::ptr::null_mut;
 use std::thread;
 
+use pretty_bytes::converter::convert;
+
 use winapi::shared::minwindef::{LOWORD, LPARAM, LRESULT, UINT, WPARAM};
 use winapi::shared::windef::{HMENU, HWND};
 use winapi::um::winuser::{
@@ -16,7 +18,6 @@ use winapi::um::winuser::{
     WS_VISIBLE,
 };
 
-use crate::clean::clean;
 use crate::globals::{LABEL_HANDLE, PROGRESS_HANDLE, TOTAL_STEP};
 use crate::gui::{CheckState, ControlT, HTextAlign, ProgressBarState};
 use crate::gui::button::ButtonT;
@@ -25,12 +26,14 @@ use crate::gui::events::dispatch_events;
 use crate::gui::label::LabelT;
 use crate::gui::progress_bar::{advance_progress_bar, ProgressBarT};
 use crate::gui::windows_helper::{build_sysclass, build_window, CENTER_POSITION, set_window_enabled, set_window_text, WINDOW_CLASS_NAME, WindowParams};
-use crate::process::kill_process;
-use crate::utils::{exit_all, restart};
+use crate::settings::Settings;
+use crate::utils::{error_box, exit_all, message_box, restart};
+use crate::worker::request_or_launch;
 
 const BUTTON_EVENT: u16 = 1;
 static mut STATE_RUNNING: bool = false;
 static mut WINDOWS_OLD_HANDLE: HWND = null_mut();
+static mut QUARANTINE_HANDLE: HWND = null_mut();
 static mut RUN_HANDLE: HWND = null_mut();
 
 pub unsafe extern "system" fn window_proc(h_wnd: HWND, msg: UINT, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
@@ -43,16 +46,22 @@ pub unsafe extern "system" fn window_proc(h_wnd: HWND, msg: UINT, w_param: WPARA
                 STATE_RUNNING = true;
                 set_window_enabled(RUN_HANDLE, 0);
                 set_window_enabled(WINDOWS_OLD_HANDLE, 0);
-                advance_progress_bar(PROGRESS_HANDLE, 1);
-                set_window_text(LABEL_HANDLE, "Kill process ...");
-                kill_process();
-                advance_progress_bar(PROGRESS_HANDLE, 1);
+                set_window_enabled(QUARANTINE_HANDLE, 0);
+                advance_progress_bar(PROGRESS_HANDLE, 2);
                 thread::spawn(move || {
                     let old_check: bool = get_checkstate(WINDOWS_OLD_HANDLE) == CheckState::Checked;
-                    set_window_text(LABEL_HANDLE, "Start clean ...");
-                    let _ = clean(old_check);
-                    set_window_text(LABEL_HANDLE, "Restart ...");
-                    restart();
+                    let quarantine: bool = get_checkstate(QUARANTINE_HANDLE) == CheckState::Checked;
+                    set_window_text(LABEL_HANDLE, "Clean ...");
+
+                    // Restarts only once the worker cleaned and left files for
+                    // the next start
+                    match request_clean(old_check, quarantine) {
+                        true => {
+                            set_window_text(LABEL_HANDLE, "Restart ...");
+                            restart();
+                        }
+                        false => set_window_text(LABEL_HANDLE, "Done"),
+                    }
                 });
 
                 return 1;
@@ -63,6 +72,39 @@ pub unsafe extern "system" fn window_proc(h_wnd: HWND, msg: UINT, w_param: WPARA
     return DefWindowProcW(h_wnd, msg, w_param, l_param);
 }
 
+// The window runs as the user, the privileged worker does the clean. A worker
+// is started elevated when none runs. Returns whether a restart is needed.
+fn request_clean(old: bool, quarantine: bool) -> bool {
+    let flag = |b: bool| if b { "1" } else { "0" };
+
+    let answer: Vec<Vec<String>> = match request_or_launch(&["clean", flag(old), flag(quarantine)]) {
+        Ok(a) => a,
+        Err(e) => {
+            error_box(format!("KpTemp worker unavailable : {}", e));
+            return false;
+        }
+    };
+
+    let mut reboot: bool = false;
+
+    for line in answer {
+        let fields: Vec<&str> = line.iter().map(|f| f.as_str()).collect();
+
+        match fields.as_slice() {
+            ["done", len, size, action, restart, report] => {
+                message_box(format!(
+                    "Success {} files and {} {}\r\nReport : {}", len, convert(size.parse().unwrap_or(0.0)), action, report
+                ));
+                reboot = *restart == "1";
+            }
+            ["error", message] => error_box(message.to_string()),
+            _ => continue,
+        }
+    }
+
+    reboot
+}
+
 pub unsafe fn build_root_window() {
     match build_sysclass() {
         Err(e) => panic!("{:?}", e),
@@ -97,7 +139,7 @@ pub unsafe fn build_root_window() {
 
     let windows_old = CheckBoxT {
         text: "Remove Windows.old (can take a long time)",
-        position: (100, 50),
+        position: (100, 32),
         size: (320, 20),
         visible: true,
         disabled: false,
@@ -107,6 +149,21 @@ pub unsafe fn build_root_window() {
         font: None,
     };
 
+    let quarantine = CheckBoxT {
+        text: "Move to quarantine instead of deleting",
+        position: (100, 54),
+        size: (320, 20),
+        visible: true,
+        disabled: false,
+        parent: windows_handle,
+        checkstate: match Settings::load().quarantine {
+            true => CheckState::Checked,
+            false => CheckState::Unchecked,
+        },
+        tristate: false,
+        font: None,
+    };
+
     let progressbar = ProgressBarT {
         position: (15, 80),
         size: (470, 25),
@@ -146,11 +203,13 @@ pub unsafe fn build_root_window() {
     let label_handle = status_label.build().expect("Fail");
     let run_button_handle = run_button.build().expect("Fail");
     let windows_old_handle = windows_old.build().expect("Fail");
+    let quarantine_handle = quarantine.build().expect("Fail");
     let progressbar_handle = progressbar.build().expect("Fail");
 
     LABEL_HANDLE = label_handle.handle();
     RUN_HANDLE = run_button_handle.handle();
     WINDOWS_OLD_HANDLE = windows_old_handle.handle();
+    QUARANTINE_HANDLE = quarantine_handle.handle();
     PROGRESS_HANDLE = progressbar_handle.handle();
 
     dispatch_events()
@@ -0,0 +1,368 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::symlink_metadata;
+use std::path::{Component, MAIN_SEPARATOR, Path, PathBuf};
+
+use crate::clean::{read_system_vars, reference_layout, resolve_targets};
+use crate::rules::{path_matches, profile_dirs, PROFILE_VARS, Rule};
+
+// Variables resolving to a whole system location, never cleared as a whole
+const SYSTEM_WIDE: [&str; 8] = [
+    "root", "system_drive", "system_root", "sys32", "all_user_profile", "program_files", "users", "user_profile",
+];
+
+// Segments with the other platform separator were joined by mistake
+#[cfg(windows)]
+const FOREIGN_SEPARATOR: char = '/';
+#[cfg(unix)]
+const FOREIGN_SEPARATOR: char = '\\';
+
+// One problem of one rule
+pub struct Finding {
+    pub rule: String,
+    pub problem: String,
+}
+
+fn finding(rule: &Rule, problem: String) -> Finding {
+    Finding { rule: rule.describe(), problem }
+}
+
+fn segments(rule: &Rule) -> Vec<String> {
+    rule.path.components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn known_vars(system_vars: &HashMap<&'static str, PathBuf>) -> Vec<String> {
+    system_vars.keys().map(|v| v.to_string())
+        .chain(PROFILE_VARS.iter().map(|v| v.to_string()))
+        .chain(profile_dirs().iter().map(|d| d[0].to_string()))
+        .collect()
+}
+
+// Whether cleaning `outer` already removes everything `inner` would. Files
+// below a cleared root are left to it by the clean, a nested root is cleaned
+// on its own and only adds nothing without applications, shredding, age or
+// kept entries of its own.
+fn redundant(outer: &Rule, inner: &Rule) -> bool {
+    let outer_segments: Vec<String> = segments(outer);
+    let inner_segments: Vec<String> = segments(inner);
+
+    let below: bool = outer.files.is_none()
+        && outer.var == inner.var
+        && outer.min_age.is_none()
+        && inner_segments.len() >= outer_segments.len()
+        && path_matches(
+            &outer_segments.iter().collect::<PathBuf>(),
+            &inner_segments[..outer_segments.len()].iter().collect::<PathBuf>(),
+        );
+
+    match inner.files {
+        Some(_) => below,
+        None => below
+            && inner.apps.iter().all(|a| outer.apps.contains(a))
+            && inner.shred == outer.shred
+            && inner.keep.is_empty()
+            && inner.min_age.is_none(),
+    }
+}
+
+fn check_overlaps(rules: &[Rule], findings: &mut Vec<Finding>) {
+    for (i, rule) in rules.iter().enumerate() {
+        let duplicate: Option<&Rule> = rules[..i].iter().find(|o| {
+            o.var == rule.var && o.path == rule.path && o.files == rule.files && o.recursive == rule.recursive
+        });
+
+        if let Some(other) = duplicate {
+            findings.push(finding(rule, format!("duplicate of {}", other.describe())));
+            continue;
+        }
+
+        let covering: Option<&Rule> = rules.iter().enumerate()
+            .find(|(j, o)| *j != i && redundant(o, rule) && !(redundant(rule, o) && *j > i))
+            .map(|(_, o)| o);
+
+        if let Some(other) = covering {
+            findings.push(finding(rule, format!("already cleaned by {}", other.describe())));
+        }
+    }
+}
+
+fn check_safety(rule: &Rule, findings: &mut Vec<Finding>) {
+    if rule.path.components().any(|c| c == Component::ParentDir) {
+        findings.push(finding(rule, format!("leaves %{}% with ..", rule.var)));
+    }
+
+    // A drive letter alone has no root, the path below it brings its own
+    if rule.path.has_root() && rule.var != "system_drive" {
+        findings.push(finding(rule, format!("absolute path replaces %{}%", rule.var)));
+    }
+
+    let everything: bool = match rule.files {
+        Some(ref f) => f == "*" && rule.recursive,
+        None => true,
+    };
+
+    if everything && segments(rule).is_empty() && SYSTEM_WIDE.contains(&rule.var.as_str()) {
+        findings.push(finding(rule, format!("clears the whole %{}%", rule.var)));
+    }
+}
+
+fn is_wildcard(segment: &str) -> bool {
+    segment.contains(['*', '?'])
+}
+
+fn spaces(segment: &str) -> usize {
+    segment.matches(char::is_whitespace).count()
+}
+
+// Typos that still give a valid path: stray spaces, the wrong separator, two
+// segments glued together or spelled without a space they have elsewhere
+fn check_segments(rule: &Rule, known: &BTreeSet<String>, findings: &mut Vec<Finding>) {
+    for segment in segments(rule).iter().filter(|s| !is_wildcard(s)) {
+        if segment.trim() != segment {
+            findings.push(finding(rule, format!("\"{}\" starts or ends with a space", segment)));
+        }
+
+        if segment.contains(FOREIGN_SEPARATOR) {
+            findings.push(finding(rule, format!("\"{}\" holds a {} separator", segment, FOREIGN_SEPARATOR)));
+        }
+
+        let glued: Option<(&String, &String)> = known.iter()
+            .filter(|k| k.len() >= 3 && segment.len() > k.len() && segment.starts_with(k.as_str()))
+            .find_map(|k| known.get(&segment[k.len()..]).filter(|r| r.len() >= 3).map(|r| (k, r)));
+
+        if let Some((first, second)) = glued {
+            findings.push(finding(rule, format!(
+                "\"{}\" looks like \"{}{}{}\" without its separator", segment, first, MAIN_SEPARATOR, second
+            )));
+        }
+
+        let squeezed: String = segment.split_whitespace().collect();
+        let spaced: Option<&String> = known.iter()
+            .filter(|k| spaces(k) > spaces(segment))
+            .find(|k| k.split_whitespace().collect::<String>() == squeezed);
+
+        if let Some(other) = spaced {
+            findings.push(finding(rule, format!("\"{}\" is spelled \"{}\" elsewhere", segment, other)));
+        }
+    }
+}
+
+// The checks that need nothing but the rules themselves
+pub fn lint(rules: &[Rule]) -> Vec<Finding> {
+    let vars: Vec<String> = known_vars(&read_system_vars());
+    let known: BTreeSet<String> = rules.iter().flat_map(segments).collect();
+    let mut findings: Vec<Finding> = Vec::new();
+
+    for rule in rules {
+        if !vars.contains(&rule.var) {
+            findings.push(finding(rule, format!("unknown variable %{}%", rule.var)));
+        }
+
+        check_safety(rule, &mut findings);
+        check_segments(rule, &known, &mut findings);
+    }
+
+    check_overlaps(rules, &mut findings);
+    findings
+}
+
+// Rules whose every target is missing from the reference layout, and targets
+// a clean would refuse
+pub fn lint_reference(rules: &[Rule], root: &Path) -> Vec<Finding> {
+    let (system_vars, profiles) = reference_layout(root);
+    let mut findings: Vec<Finding> = Vec::new();
+
+    for rule in rules {
+        let targets: Vec<(PathBuf, bool)> = resolve_targets(rule, &system_vars, &profiles);
+        let existing: Vec<&(PathBuf, bool)> = targets.iter().filter(|(p, _)| symlink_metadata(p).is_ok()).collect();
+
+        if existing.is_empty() {
+            findings.push(finding(rule, format!("matches nothing in {}", root.display())));
+        }
+
+        for (path, _) in existing.iter().filter(|(_, safe)| !safe) {
+            findings.push(finding(rule, format!("{} fails the safety checks", path.display())));
+        }
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    use crate::rules::{profile_dirs, Rule};
+    use crate::testing::TempRoot;
+
+    use super::{check_overlaps, check_safety, check_segments, Finding, lint, lint_reference, redundant, segments};
+
+    #[cfg(windows)]
+    const HOME: &str = "Users/u";
+    #[cfg(unix)]
+    const HOME: &str = "home/u";
+
+    fn problems(findings: &[Finding], rule: &Rule) -> Vec<String> {
+        findings.iter().filter(|f| f.rule == rule.describe()).map(|f| f.problem.clone()).collect()
+    }
+
+    fn segment_problems(rules: &[Rule], rule: &Rule) -> Vec<String> {
+        let known: BTreeSet<String> = rules.iter().flat_map(segments).collect();
+        let mut findings: Vec<Finding> = Vec::new();
+
+        check_segments(rule, &known, &mut findings);
+        problems(&findings, rule)
+    }
+
+    fn recursive(mut rule: Rule) -> Rule {
+        rule.recursive = true;
+        rule
+    }
+
+    #[test]
+    fn unknown_variables() {
+        let unknown: Rule = Rule::new_clear("nowhere", "cache");
+        let known: Vec<Rule> = vec![
+            Rule::new_clear("users", ".cache/app"),
+            Rule::new_clear("user_profile", "tmp/app"),
+            Rule::new_clear(profile_dirs()[0][0], "cache2"),
+        ];
+
+        let mut rules: Vec<Rule> = known.clone();
+        rules.push(unknown.clone());
+
+        let findings: Vec<Finding> = lint(&rules);
+
+        assert_eq!(problems(&findings, &unknown), vec!["unknown variable %nowhere%"]);
+        assert!(known.iter().all(|r| problems(&findings, r).is_empty()));
+    }
+
+    #[test]
+    fn segments_with_typos() {
+        let rules: Vec<Rule> = vec![
+            Rule::new_clear("users", "AppData/Local/Google/Chrome/User Data"),
+            Rule::new_clear("users", "AppData/Local/GoogleChrome"),
+            Rule::new_clear("users", "AppData/Local/Google/Chrome/UserData"),
+            Rule::new_clear("users", "AppData/Local/ Temp"),
+            Rule::new_clear("users", "AppData/Local/Go*Chrome"),
+        ];
+
+        assert!(segment_problems(&rules, &rules[0]).is_empty());
+
+        let glued: Vec<String> = segment_problems(&rules, &rules[1]);
+        assert_eq!(glued.len(), 1);
+        assert!(glued[0].starts_with("\"GoogleChrome\" looks like \"Google"));
+        assert!(glued[0].ends_with("Chrome\" without its separator"));
+
+        assert_eq!(segment_problems(&rules, &rules[2]), vec!["\"UserData\" is spelled \"User Data\" elsewhere"]);
+        assert_eq!(segment_problems(&rules, &rules[3]), vec!["\" Temp\" starts or ends with a space"]);
+
+        // Wildcards are never taken for typos
+        assert!(segment_problems(&rules, &rules[4]).is_empty());
+    }
+
+    // Only Windows splits on both separators, a backslash is a name on Unix
+    #[cfg(unix)]
+    #[test]
+    fn segments_with_a_foreign_separator() {
+        let rule: Rule = Rule::new_clear("users", ".cache/app\\data");
+
+        assert_eq!(segment_problems(std::slice::from_ref(&rule), &rule), vec!["\"app\\data\" holds a \\ separator"]);
+    }
+
+    #[test]
+    fn overlaps() {
+        let cache: Rule = Rule::new_clear("users", ".cache");
+        let logs: Rule = Rule::new_files("users", ".cache/app", "*.log");
+        let mut browser: Rule = Rule::new_clear("users", ".cache/browser");
+        browser.apps = vec!["browser".to_string()];
+        let mut aged: Rule = Rule::new_clear("root", "tmp");
+        aged.min_age = Some(Duration::from_secs(86_400));
+        let below_aged: Rule = Rule::new_clear("root", "tmp/app");
+
+        assert!(redundant(&cache, &logs));
+        assert!(!redundant(&logs, &cache));
+
+        // The nested root closes its application first
+        assert!(!redundant(&cache, &browser));
+
+        // What the outer rule keeps for its age the inner one removes
+        assert!(!redundant(&aged, &below_aged));
+        assert!(!redundant(&Rule::new_clear("root", ".cache"), &logs));
+
+        let rules: Vec<Rule> = vec![cache.clone(), logs.clone(), browser.clone(), cache.clone()];
+        let mut findings: Vec<Finding> = Vec::new();
+        check_overlaps(&rules, &mut findings);
+
+        let problems: Vec<String> = findings.iter().map(|f| format!("{} : {}", f.rule, f.problem)).collect();
+        assert_eq!(problems, vec![
+            format!("{} : already cleaned by {}", logs.describe(), cache.describe()),
+            format!("{} : duplicate of {}", cache.describe(), cache.describe()),
+        ]);
+    }
+
+    // Two rules cleaning the same root only report the later one
+    #[test]
+    fn mutually_redundant_rules() {
+        let first: Rule = Rule::new_clear("users", ".cache/*");
+        let second: Rule = recursive(Rule::new_clear("users", ".cache/*"));
+
+        assert!(redundant(&first, &second) && redundant(&second, &first));
+
+        let mut findings: Vec<Finding> = Vec::new();
+        check_overlaps(&[first.clone(), second.clone()], &mut findings);
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].problem, format!("already cleaned by {}", first.describe()));
+
+        let mut findings: Vec<Finding> = Vec::new();
+        check_overlaps(&[second.clone(), first.clone()], &mut findings);
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].problem, format!("already cleaned by {}", second.describe()));
+    }
+
+    #[test]
+    fn safety() {
+        let check = |rule: &Rule| {
+            let mut findings: Vec<Finding> = Vec::new();
+            check_safety(rule, &mut findings);
+            findings.into_iter().map(|f| f.problem).collect::<Vec<String>>()
+        };
+
+        assert_eq!(check(&Rule::new_clear("users", ".cache/../.ssh")), vec!["leaves %users% with .."]);
+        assert_eq!(check(&Rule::new_clear("users", "/etc")), vec!["absolute path replaces %users%"]);
+        assert_eq!(check(&Rule::new_clear("root", "")), vec!["clears the whole %root%"]);
+        assert_eq!(check(&recursive(Rule::new_files("users", "", "*"))), vec!["clears the whole %users%"]);
+
+        // Some files of a system location, or the whole of a narrower one
+        assert!(check(&Rule::new_files("root", "", "*")).is_empty());
+        assert!(check(&Rule::new_files("users", "", "*.log")).is_empty());
+        assert!(check(&Rule::new_clear("user_temp", "")).is_empty());
+        assert!(check(&Rule::new_clear("root", "tmp")).is_empty());
+    }
+
+    #[test]
+    fn reference_layout() {
+        let root: TempRoot = TempRoot::new();
+        root.file(&format!("{}/.cache/app/data", HOME), "x");
+        root.file(&format!("{}/.cache/file", HOME), "x");
+
+        let present: Rule = Rule::new_clear("users", ".cache/app");
+        let missing: Rule = Rule::new_clear("users", ".cache/other");
+        let file: Rule = Rule::new_clear("users", ".cache/file");
+
+        let findings: Vec<Finding> = lint_reference(&[present.clone(), missing.clone(), file.clone()], root.path());
+
+        assert!(problems(&findings, &present).is_empty());
+        assert_eq!(problems(&findings, &missing), vec![format!("matches nothing in {}", root.path().display())]);
+
+        let target: String = root.path().join(HOME).join(".cache").join("file").display().to_string();
+        assert_eq!(problems(&findings, &file), vec![format!("{} fails the safety checks", target)]);
+    }
+}
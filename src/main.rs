@@ -2,27 +2,73 @@
 
 extern crate chrono;
 extern crate pretty_bytes;
+extern crate roxmltree;
+extern crate sha2;
 extern crate walkdir;
+#[cfg(windows)]
 extern crate winapi;
 
+use std::env::args;
+use std::process::exit;
+
+use crate::commands::run_command;
+#[cfg(windows)]
 use crate::gui::windows::build_root_window;
+use crate::utils::attach_console;
+#[cfg(windows)]
 use crate::utils::eula;
 
+#[cfg(windows)]
 mod gui;
 mod clean;
 mod process;
 mod privilege;
 mod globals;
 mod utils;
+mod safe_remove;
+mod secure;
+mod settings;
+mod quarantine;
+mod commands;
+mod deferred;
+mod lint;
+mod snapshot;
+mod vfs;
+mod rules;
+mod shred;
+mod trash;
+mod worker;
+mod profiles;
+mod progress;
+#[cfg(test)]
+mod testing;
 
 trait Ignore: Sized {
-    fn ignore(self) -> () {}
+    fn ignore(self) {}
 }
 
 impl<T, E> Ignore for Result<T, E> {}
 
 
 fn main() {
+    let args: Vec<String> = args().skip(1).collect();
+
+    if !args.is_empty() {
+        attach_console();
+        exit(run_command(&args));
+    }
+
+    run_gui();
+}
+
+#[cfg(windows)]
+fn run_gui() {
     eula();
     unsafe { build_root_window(); }
 }
+
+// No GUI outside Windows, a plain run cleans from the terminal
+#[cfg(not(windows))]
+fn run_gui() {
+    exit(run_command(&["clean".to_string()]));
+}
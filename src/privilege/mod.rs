@@ -0,0 +1,70 @@
+#[cfg(unix)]
+pub use self::unix::is_elevated;
+#[cfg(windows)]
+pub use self::windows::{adjust_privilege, is_elevated};
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+pub const SE_RESTORE: &str = "SeRestorePrivilege";
+pub const SE_DEBUG: &str = "SeDebugPrivilege";
+pub const SE_SHUTDOWN: &str = "SeShutdownPrivilege";
+
+// What the process is really allowed to do, taken once before cleaning
+#[derive(Clone, Debug)]
+pub struct Privileges {
+    pub elevated: bool,
+    pub granted: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+impl Privileges {
+    #[cfg(windows)]
+    pub fn acquire(names: &[&str]) -> Privileges {
+        let mut privileges = Privileges { elevated: is_elevated(), granted: Vec::new(), missing: Vec::new() };
+
+        for name in names {
+            match adjust_privilege(name) {
+                true => privileges.granted.push(name.to_string()),
+                false => privileges.missing.push(name.to_string()),
+            }
+        }
+
+        privileges
+    }
+
+    #[cfg(unix)]
+    pub fn acquire(_names: &[&str]) -> Privileges {
+        let elevated: bool = is_elevated();
+
+        Privileges {
+            elevated,
+            granted: if elevated { vec!["root".to_string()] } else { Vec::new() },
+            missing: if elevated { Vec::new() } else { vec!["root".to_string()] },
+        }
+    }
+
+    // What the process may already do, without asking for anything more
+    pub fn current() -> Privileges {
+        Privileges { elevated: is_elevated(), granted: Vec::new(), missing: Vec::new() }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut text: String = match self.elevated {
+            true => "elevated".to_string(),
+            false => "not elevated, current user only".to_string(),
+        };
+
+        if !self.granted.is_empty() {
+            text.push_str(&format!(", granted: {}", self.granted.join(" ")));
+        }
+
+        if !self.missing.is_empty() {
+            text.push_str(&format!(", missing: {}", self.missing.join(" ")));
+        }
+
+        text
+    }
+}
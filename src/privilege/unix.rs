@@ -0,0 +1,4 @@
+// Unix has no named privileges, everything the cleaner needs comes with root
+pub fn is_elevated() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
@@ -1,16 +1,21 @@
-extern crate winapi;
-
 use std::ffi::OsStr;
 use std::mem::{size_of, zeroed};
 use std::os::windows::ffi::OsStrExt;
 use std::ptr::null_mut;
 
 use winapi::shared::minwindef::DWORD;
+use winapi::shared::winerror::ERROR_NOT_ALL_ASSIGNED;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::handleapi::CloseHandle;
 use winapi::um::processthreadsapi::{GetCurrentProcess, GetCurrentThread, OpenProcessToken, OpenThreadToken};
-use winapi::um::securitybaseapi::AdjustTokenPrivileges;
+use winapi::um::securitybaseapi::{AdjustTokenPrivileges, GetTokenInformation};
 use winapi::um::winbase::LookupPrivilegeValueW;
-use winapi::um::winnt::{SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES};
+use winapi::um::winnt::{
+    HANDLE, SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES, TOKEN_ELEVATION, TOKEN_PRIVILEGES, TOKEN_QUERY, TokenElevation,
+};
 
+// Only true when the privilege is really enabled, AdjustTokenPrivileges
+// succeeds without assigning anything when the token does not hold it
 pub fn adjust_privilege(privilege_name: &str) -> bool {
     let mut token_handle = null_mut();
 
@@ -50,6 +55,7 @@ pub fn adjust_privilege(privilege_name: &str) -> bool {
     };
 
     if status == 0 {
+        unsafe { CloseHandle(token_handle); }
         return false;
     }
 
@@ -68,5 +74,33 @@ pub fn adjust_privilege(privilege_name: &str) -> bool {
         )
     };
 
-    return status != 0;
-}
\ No newline at end of file
+    let last_error: DWORD = unsafe { GetLastError() };
+    unsafe { CloseHandle(token_handle); }
+
+    return status != 0 && last_error != ERROR_NOT_ALL_ASSIGNED;
+}
+
+pub fn is_elevated() -> bool {
+    let mut token_handle: HANDLE = null_mut();
+
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token_handle) } == 0 {
+        return false;
+    }
+
+    let mut elevation: TOKEN_ELEVATION = unsafe { zeroed() };
+    let mut returned: DWORD = 0;
+
+    let status: i32 = unsafe {
+        GetTokenInformation(
+            token_handle,
+            TokenElevation,
+            &mut elevation as *mut TOKEN_ELEVATION as _,
+            size_of::<TOKEN_ELEVATION>() as DWORD,
+            &mut returned,
+        )
+    };
+
+    unsafe { CloseHandle(token_handle); }
+
+    status != 0 && elevation.TokenIsElevated != 0
+}
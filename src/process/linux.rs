@@ -0,0 +1,179 @@
+use std::ffi::CStr;
+use std::fs::{metadata, Metadata, read, read_dir, read_link, read_to_string, symlink_metadata};
+use std::io::{Error, Result};
+use std::mem::zeroed;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+
+use crate::process::{OpenFile, ProcessInfo, ProcessProvider, Termination};
+
+const PROC: &str = "/proc";
+const DELETED: &str = " (deleted)";
+// Longest name the kernel keeps in comm
+const COMM_LEN: usize = 15;
+
+pub struct ProcProvider;
+
+impl ProcProvider {
+    pub fn new() -> ProcProvider {
+        ProcProvider
+    }
+}
+
+fn user_name(uid: u32) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { zeroed() };
+    let mut buffer: Vec<libc::c_char> = vec![0; 4096];
+    let mut result: *mut libc::passwd = null_mut();
+
+    let status: i32 = unsafe {
+        libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+
+    if status != 0 || result.is_null() {
+        return None;
+    }
+
+    Some(unsafe { CStr::from_ptr(passwd.pw_name) }.to_string_lossy().to_string())
+}
+
+// The state is the first field after the command name, which is wrapped in
+// parentheses and may itself contain spaces or parentheses
+fn process_state(dir: &Path) -> Option<char> {
+    let stat: String = read_to_string(dir.join("stat")).ok()?;
+    let (_, rest) = stat.split_at(stat.rfind(')')? + 1);
+
+    rest.trim_start().chars().next()
+}
+
+// comm is cut to 15 bytes, argv[0] gives the rest when it starts the same.
+// Processes rewriting their title append their arguments to it.
+fn full_name(comm: &str, cmdline: &[u8]) -> String {
+    if comm.len() < COMM_LEN {
+        return comm.to_string();
+    }
+
+    let argv0: String = String::from_utf8_lossy(cmdline.split(|b| *b == 0).next().unwrap_or_default()).to_string();
+    let program: &str = argv0.split(' ').next().unwrap_or_default();
+
+    match Path::new(program).file_name().map(|n| n.to_string_lossy().to_string()) {
+        Some(n) if n.starts_with(comm) => n,
+        _ => comm.to_string(),
+    }
+}
+
+fn process_info(pid: u32, dir: &Path) -> Option<ProcessInfo> {
+    let exe: Option<PathBuf> = read_link(dir.join("exe")).ok();
+
+    // The executable of other users' processes needs privileges, their
+    // command line does not
+    let name: String = match exe.as_ref().and_then(|e| e.file_name()) {
+        Some(n) => n.to_string_lossy().trim_end_matches(" (deleted)").to_string(),
+        None => full_name(
+            read_to_string(dir.join("comm")).ok()?.trim_end_matches('\n'),
+            &read(dir.join("cmdline")).unwrap_or_default(),
+        ),
+    };
+
+    let owner: Option<String> = symlink_metadata(dir).ok().and_then(|m| user_name(m.uid()));
+
+    Some(ProcessInfo { pid, name, exe, owner })
+}
+
+fn pids() -> Result<Vec<(u32, PathBuf)>> {
+    let mut pids: Vec<(u32, PathBuf)> = Vec::new();
+
+    for entry in read_dir(PROC)?.filter_map(|e| e.ok()) {
+        if let Some(pid) = entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+            pids.push((pid, entry.path()));
+        }
+    }
+
+    Ok(pids)
+}
+
+// Descriptors of other users need privileges, their processes are skipped.
+// Sockets, pipes and anonymous inodes have no path.
+fn process_files(pid: u32, dir: &Path) -> Vec<OpenFile> {
+    let entries = match read_dir(dir.join("fd")) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+
+    let process: String = process_info(pid, dir).map(|p| p.name).unwrap_or_default();
+    let mut files: Vec<OpenFile> = Vec::new();
+
+    for fd in entries.filter_map(|e| e.ok()) {
+        let target: String = match read_link(fd.path()) {
+            Ok(t) if t.is_absolute() => t.to_string_lossy().to_string(),
+            _ => continue,
+        };
+
+        // The link itself resolves to the open file, unlinked or not
+        let file: Metadata = match metadata(fd.path()) {
+            Ok(m) if m.is_file() => m,
+            _ => continue,
+        };
+
+        let deleted: bool = target.ends_with(DELETED) && file.nlink() == 0;
+
+        files.push(OpenFile {
+            pid,
+            process: process.clone(),
+            path: PathBuf::from(if deleted { target.trim_end_matches(DELETED) } else { &target }),
+            deleted,
+            dev: file.dev(),
+            id: file.ino(),
+            size: file.len(),
+        });
+    }
+
+    files
+}
+
+impl ProcessProvider for ProcProvider {
+    fn processes(&self) -> Result<Vec<ProcessInfo>> {
+        // Processes can exit between the listing and the reads
+        Ok(pids()?.iter().filter_map(|(pid, dir)| process_info(*pid, dir)).collect())
+    }
+
+    fn is_running(&self, pid: u32) -> bool {
+        !matches!(process_state(&Path::new(PROC).join(pid.to_string())), Some('Z') | Some('X') | None)
+    }
+
+    fn terminate(&self, pid: u32, termination: Termination) -> Result<()> {
+        let signal: i32 = match termination {
+            Termination::Graceful => libc::SIGTERM,
+            Termination::Forced => libc::SIGKILL,
+        };
+
+        if unsafe { libc::kill(pid as libc::pid_t, signal) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn open_files(&self) -> Result<Vec<OpenFile>> {
+        let current_pid: u32 = std::process::id();
+
+        Ok(pids()?.iter()
+            .filter(|(pid, _)| *pid != current_pid)
+            .flat_map(|(pid, dir)| process_files(*pid, dir))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::full_name;
+
+    #[test]
+    fn names_cut_by_comm() {
+        assert_eq!(full_name("chromium-browse", b"/usr/lib/chromium/chromium-browser\0--type=renderer\0"), "chromium-browser");
+        assert_eq!(full_name("chromium-browse", b"/usr/lib/chromium/chromium-browser --type=renderer"), "chromium-browser");
+        assert_eq!(full_name("chromium-browse", b"renamed\0"), "chromium-browse");
+        assert_eq!(full_name("chromium-browse", b""), "chromium-browse");
+        assert_eq!(full_name("firefox", b"/usr/lib/firefox/firefox-bin\0"), "firefox");
+    }
+}
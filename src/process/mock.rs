@@ -0,0 +1,117 @@
+use std::cell::RefCell;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::process::{OpenFile, ProcessInfo, ProcessProvider, Termination};
+
+pub const OWNER: &str = "HOST\\me";
+
+// How a scripted process answers termination requests
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reaction {
+    // Exits as soon as it is asked to close
+    Closes,
+    // Ignores close requests and only exits when forced
+    Hangs,
+    // Survives everything, like a process of another session
+    Protected,
+    // Exits on its own between the listing and the close request
+    Exited,
+}
+
+struct Scripted {
+    info: ProcessInfo,
+    reaction: Reaction,
+    running: bool,
+}
+
+// In-memory provider driven by a script, it records every termination request
+// so the closing logic can be checked without touching real processes
+pub struct ScriptedProvider {
+    processes: RefCell<Vec<Scripted>>,
+    requests: RefCell<Vec<(u32, Termination)>>,
+    files: RefCell<Vec<OpenFile>>,
+}
+
+impl ScriptedProvider {
+    pub fn new() -> ScriptedProvider {
+        ScriptedProvider {
+            processes: RefCell::new(Vec::new()),
+            requests: RefCell::new(Vec::new()),
+            files: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Run by `OWNER` unless `owned` says otherwise
+    pub fn spawn(self, pid: u32, name: &str, reaction: Reaction) -> ScriptedProvider {
+        self.processes.borrow_mut().push(Scripted {
+            info: ProcessInfo { pid, name: name.to_string(), exe: None, owner: Some(OWNER.to_string()) },
+            reaction,
+            running: true,
+        });
+
+        self
+    }
+
+    pub fn owned(self, pid: u32, owner: Option<&str>) -> ScriptedProvider {
+        if let Some(p) = self.processes.borrow_mut().iter_mut().find(|p| p.info.pid == pid) {
+            p.info.owner = owner.map(|o| o.to_string());
+        }
+
+        self
+    }
+
+    // The file stays open as long as the process runs
+    pub fn hold(self, pid: u32, path: &str, size: u64, deleted: bool) -> ScriptedProvider {
+        let process: String = self.processes.borrow().iter()
+            .find(|p| p.info.pid == pid)
+            .map(|p| p.info.name.clone())
+            .unwrap_or_default();
+        let id: u64 = self.files.borrow().len() as u64 + 1;
+
+        self.files.borrow_mut().push(OpenFile { pid, process, path: path.into(), deleted, dev: 0, id, size });
+
+        self
+    }
+
+    pub fn requests(&self) -> Vec<(u32, Termination)> {
+        self.requests.borrow().clone()
+    }
+}
+
+impl ProcessProvider for ScriptedProvider {
+    fn processes(&self) -> Result<Vec<ProcessInfo>> {
+        Ok(self.processes.borrow().iter().filter(|p| p.running).map(|p| p.info.clone()).collect())
+    }
+
+    fn is_running(&self, pid: u32) -> bool {
+        self.processes.borrow().iter().any(|p| p.info.pid == pid && p.running)
+    }
+
+    fn terminate(&self, pid: u32, termination: Termination) -> Result<()> {
+        self.requests.borrow_mut().push((pid, termination));
+
+        let mut processes = self.processes.borrow_mut();
+
+        let process: &mut Scripted = match processes.iter_mut().find(|p| p.info.pid == pid && p.running) {
+            Some(p) => p,
+            None => return Err(Error::from(ErrorKind::NotFound)),
+        };
+
+        if process.reaction == Reaction::Exited {
+            process.running = false;
+            return Err(Error::from(ErrorKind::NotFound));
+        }
+
+        match (process.reaction, termination) {
+            (Reaction::Closes, _) | (Reaction::Hangs, Termination::Forced) => process.running = false,
+            (Reaction::Protected, Termination::Forced) => return Err(Error::from(ErrorKind::PermissionDenied)),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn open_files(&self) -> Result<Vec<OpenFile>> {
+        Ok(self.files.borrow().iter().filter(|f| self.is_running(f.pid)).cloned().collect())
+    }
+}
@@ -0,0 +1,289 @@
+use std::io::Result;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+pub use self::linux::ProcProvider as SystemProvider;
+#[cfg(windows)]
+pub use self::windows::WindowsProvider as SystemProvider;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(windows)]
+mod windows;
+#[cfg(test)]
+pub mod mock;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    pub exe: Option<PathBuf>,
+    pub owner: Option<String>,
+}
+
+// A file some process holds open. `deleted` is already unlinked, its space only
+// comes back once every process closed it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpenFile {
+    pub pid: u32,
+    pub process: String,
+    pub path: PathBuf,
+    pub deleted: bool,
+    pub dev: u64,
+    pub id: u64,
+    pub size: u64,
+}
+
+// What a clean does with the files other processes hold open
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpenFiles {
+    // Removed without looking
+    Ignore,
+    // Removed, the processes holding them named in the report
+    Report,
+    // Left in place and reported
+    Skip,
+}
+
+impl OpenFiles {
+    pub fn parse(value: &str) -> Option<OpenFiles> {
+        match value.trim().to_lowercase().as_str() {
+            "ignore" | "off" => Some(OpenFiles::Ignore),
+            "report" => Some(OpenFiles::Report),
+            "skip" => Some(OpenFiles::Skip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Termination {
+    // WM_CLOSE to the windows of the process, SIGTERM on Unix
+    Graceful,
+    // TerminateProcess, SIGKILL on Unix
+    Forced,
+}
+
+pub trait ProcessProvider {
+    fn processes(&self) -> Result<Vec<ProcessInfo>>;
+
+    fn is_running(&self, pid: u32) -> bool;
+
+    fn terminate(&self, pid: u32, termination: Termination) -> Result<()>;
+
+    // Only Linux tells which files a process holds
+    fn open_files(&self) -> Result<Vec<OpenFile>> {
+        Ok(Vec::new())
+    }
+}
+
+// Windows names the owner DOMAIN\user, profiles only carry the user
+fn owned_by(process: &ProcessInfo, owners: &[String]) -> bool {
+    match process.owner {
+        Some(ref o) => {
+            let user: &str = o.rsplit('\\').next().unwrap_or(o);
+            owners.iter().any(|w| w.eq_ignore_ascii_case(user))
+        }
+        None => false,
+    }
+}
+
+// Processes of other accounts are not ours to close, nor do they hold the
+// files of the profiles cleaned
+fn find_processes(provider: &dyn ProcessProvider, names: &[String], owners: &[String]) -> Vec<ProcessInfo> {
+    let current_pid: u32 = std::process::id();
+
+    provider.processes()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|p| p.pid != current_pid)
+        .filter(|p| names.iter().any(|n| n.eq_ignore_ascii_case(&p.name)))
+        .filter(|p| owned_by(p, owners))
+        .collect()
+}
+
+fn wait_exit(provider: &dyn ProcessProvider, processes: &[ProcessInfo], timeout: Duration) -> Vec<ProcessInfo> {
+    let start: Instant = Instant::now();
+
+    loop {
+        let remaining: Vec<ProcessInfo> = processes.iter()
+            .filter(|p| provider.is_running(p.pid))
+            .cloned()
+            .collect();
+
+        if remaining.is_empty() || start.elapsed() >= timeout {
+            return remaining;
+        }
+
+        sleep(Duration::from_millis(250));
+    }
+}
+
+fn names_of(processes: &[ProcessInfo]) -> Vec<String> {
+    let mut names: Vec<String> = processes.iter().map(|p| p.name.to_lowercase()).collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+// Names of the given applications `owners` run, nothing is closed
+pub fn running_applications(provider: &dyn ProcessProvider, names: &[String], owners: &[String]) -> Vec<String> {
+    names_of(&find_processes(provider, names, owners))
+}
+
+// Asks the given applications `owners` run to close once `confirm` accepts
+// their names, the ones still running after the timeout are only forced to
+// close once it accepts again. Returns the names of the applications that
+// are still running.
+pub fn close_applications(
+    provider: &dyn ProcessProvider,
+    names: &[String],
+    owners: &[String],
+    timeout: Duration,
+    confirm: &mut dyn FnMut(&[String], Termination) -> bool,
+) -> Vec<String> {
+    let processes: Vec<ProcessInfo> = find_processes(provider, names, owners);
+
+    if processes.is_empty() || !confirm(&names_of(&processes), Termination::Graceful) {
+        return names_of(&processes);
+    }
+
+    for process in &processes {
+        let _ = provider.terminate(process.pid, Termination::Graceful);
+    }
+
+    let mut remaining: Vec<ProcessInfo> = wait_exit(provider, &processes, timeout);
+
+    if !remaining.is_empty() && confirm(&names_of(&remaining), Termination::Forced) {
+        // Only the processes that took the signal are waited for
+        let (forced, refused): (Vec<ProcessInfo>, Vec<ProcessInfo>) = remaining.into_iter()
+            .partition(|p| provider.terminate(p.pid, Termination::Forced).is_ok() || !provider.is_running(p.pid));
+
+        remaining = wait_exit(provider, &forced, Duration::from_secs(5));
+        remaining.extend(refused);
+    }
+
+    names_of(&remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{close_applications, ProcessProvider, running_applications, Termination};
+    use super::mock::{Reaction, ScriptedProvider};
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn me() -> Vec<String> {
+        names(&["me"])
+    }
+
+    #[test]
+    fn closing_applications_exit() {
+        let provider: ScriptedProvider = ScriptedProvider::new()
+            .spawn(10, "Firefox", Reaction::Closes)
+            .spawn(11, "other", Reaction::Hangs)
+            .hold(10, "/tmp/cache", 10, false);
+
+        let left: Vec<String> = close_applications(&provider, &names(&["firefox"]), &me(), Duration::ZERO, &mut |_, t| {
+            t == Termination::Graceful
+        });
+
+        assert!(left.is_empty());
+        assert_eq!(provider.requests(), vec![(10, Termination::Graceful)]);
+        assert!(provider.open_files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn applications_gone_before_the_request() {
+        let provider: ScriptedProvider = ScriptedProvider::new().spawn(10, "chrome", Reaction::Exited);
+
+        let mut asked: Vec<Termination> = Vec::new();
+        let left: Vec<String> = close_applications(&provider, &names(&["chrome"]), &me(), Duration::ZERO, &mut |_, t| {
+            asked.push(t);
+            true
+        });
+
+        assert!(left.is_empty());
+        assert_eq!(asked, vec![Termination::Graceful]);
+        assert_eq!(provider.requests(), vec![(10, Termination::Graceful)]);
+    }
+
+    #[test]
+    fn declined_applications_are_left_running() {
+        let provider: ScriptedProvider = ScriptedProvider::new().spawn(10, "firefox", Reaction::Closes);
+
+        let left: Vec<String> = close_applications(&provider, &names(&["firefox"]), &me(), Duration::ZERO, &mut |_, _| false);
+
+        assert_eq!(left, names(&["firefox"]));
+        assert!(provider.requests().is_empty());
+        assert!(provider.is_running(10));
+    }
+
+    #[test]
+    fn hanging_applications_are_forced_once_confirmed() {
+        let provider = || ScriptedProvider::new()
+            .spawn(10, "brave", Reaction::Hangs)
+            .hold(10, "/tmp/lock", 1, false);
+
+        // Left running after the timeout when the user refuses
+        let refused: ScriptedProvider = provider();
+        let mut offered: Vec<String> = Vec::new();
+        let left: Vec<String> = close_applications(&refused, &names(&["brave"]), &me(), Duration::ZERO, &mut |n, t| {
+            offered = n.to_vec();
+            t == Termination::Graceful
+        });
+
+        assert_eq!(left, names(&["brave"]));
+        assert_eq!(offered, names(&["brave"]));
+        assert_eq!(refused.requests(), vec![(10, Termination::Graceful)]);
+        assert_eq!(refused.open_files().unwrap()[0].path, PathBuf::from("/tmp/lock"));
+
+        let confirmed: ScriptedProvider = provider();
+        let left: Vec<String> = close_applications(&confirmed, &names(&["brave"]), &me(), Duration::ZERO, &mut |_, _| true);
+
+        assert!(left.is_empty());
+        assert_eq!(confirmed.requests(), vec![(10, Termination::Graceful), (10, Termination::Forced)]);
+    }
+
+    #[test]
+    fn refused_signals_leave_the_application_running() {
+        let provider: ScriptedProvider = ScriptedProvider::new()
+            .spawn(10, "vivaldi-bin", Reaction::Protected)
+            .spawn(11, "vivaldi-bin", Reaction::Hangs);
+
+        let left: Vec<String> = close_applications(&provider, &names(&["vivaldi-bin"]), &me(), Duration::ZERO, &mut |_, _| true);
+
+        assert_eq!(left, names(&["vivaldi-bin"]));
+        assert!(provider.is_running(10));
+        assert!(!provider.is_running(11));
+        assert_eq!(provider.requests().iter().filter(|(_, t)| *t == Termination::Forced).count(), 2);
+    }
+
+    #[test]
+    fn other_accounts_are_out_of_scope() {
+        let provider: ScriptedProvider = ScriptedProvider::new()
+            .spawn(10, "firefox", Reaction::Closes)
+            .owned(10, Some("HOST\\other"))
+            .spawn(11, "firefox", Reaction::Closes)
+            .owned(11, None)
+            .spawn(12, "firefox", Reaction::Closes)
+            .owned(12, Some("ME"));
+
+        assert_eq!(running_applications(&provider, &names(&["firefox"]), &me()), names(&["firefox"]));
+        assert!(running_applications(&provider, &names(&["firefox"]), &names(&["nobody"])).is_empty());
+
+        let left: Vec<String> = close_applications(&provider, &names(&["firefox"]), &me(), Duration::ZERO, &mut |_, _| true);
+
+        assert!(left.is_empty());
+        assert_eq!(provider.requests(), vec![(12, Termination::Graceful)]);
+        assert!(provider.is_running(10));
+        assert!(provider.is_running(11));
+    }
+}
@@ -0,0 +1,216 @@
+use std::ffi::OsString;
+use std::io::{Error, Result};
+use std::mem::size_of;
+use std::os::windows::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::ptr::null_mut;
+
+use winapi::shared::minwindef::{BOOL, DWORD, LPARAM, TRUE};
+use winapi::shared::windef::HWND;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::minwinbase::STILL_ACTIVE;
+use winapi::um::processthreadsapi::{GetExitCodeProcess, OpenProcess, OpenProcessToken, TerminateProcess};
+use winapi::um::psapi::EnumProcesses;
+use winapi::um::securitybaseapi::GetTokenInformation;
+use winapi::um::winbase::{LookupAccountSidW, QueryFullProcessImageNameW};
+use winapi::um::winnt::{HANDLE, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE, TOKEN_QUERY, TOKEN_USER, TokenUser};
+use winapi::um::winuser::{EnumWindows, GetWindowThreadProcessId, PostMessageW, WM_CLOSE};
+
+use crate::privilege::{adjust_privilege, SE_DEBUG};
+use crate::process::{ProcessInfo, ProcessProvider, Termination};
+
+pub struct WindowsProvider;
+
+impl WindowsProvider {
+    pub fn new() -> WindowsProvider {
+        adjust_privilege(SE_DEBUG);
+        WindowsProvider
+    }
+}
+
+// The buffer grows until EnumProcesses leaves room in it, a full buffer means
+// the list may have been truncated
+fn get_process_ids() -> Option<Vec<u32>> {
+    let mut buffer_len: usize = 1024;
+
+    loop {
+        let mut a_process: Vec<u32> = vec![0; buffer_len];
+        let mut cb_needed: DWORD = 0;
+
+        let enum_status: i32 = unsafe {
+            EnumProcesses(
+                a_process.as_mut_ptr(),
+                (buffer_len * size_of::<DWORD>()) as DWORD,
+                &mut cb_needed,
+            )
+        };
+
+        if enum_status == 0 {
+            return None;
+        }
+
+        let c_process: usize = cb_needed as usize / size_of::<DWORD>();
+
+        if c_process < buffer_len {
+            a_process.truncate(c_process);
+            return Some(a_process);
+        }
+
+        buffer_len *= 2;
+    }
+}
+
+fn get_handle(pid: u32, desired_access: DWORD) -> Option<HANDLE> {
+    let handle: HANDLE = unsafe { OpenProcess(desired_access, 0, pid) };
+
+    if handle.is_null() {
+        return None;
+    }
+
+    Some(handle)
+}
+
+fn get_process_exe(handle: HANDLE) -> Option<PathBuf> {
+    let mut buffer = [0u16; 32768];
+    let mut size: DWORD = buffer.len() as DWORD;
+
+    let status: i32 = unsafe {
+        QueryFullProcessImageNameW(handle, 0, buffer.as_mut_ptr(), &mut size)
+    };
+
+    if status == 0 {
+        return None;
+    }
+
+    Some(PathBuf::from(OsString::from_wide(&buffer[..size as usize])))
+}
+
+// DOMAIN\user of the token owning the process
+fn get_process_owner(handle: HANDLE) -> Option<String> {
+    let mut token: HANDLE = null_mut();
+
+    if unsafe { OpenProcessToken(handle, TOKEN_QUERY, &mut token) } == 0 {
+        return None;
+    }
+
+    let mut buffer: Vec<u64> = vec![0; 64];
+    let mut returned: DWORD = 0;
+
+    let status: i32 = unsafe {
+        GetTokenInformation(
+            token,
+            TokenUser,
+            buffer.as_mut_ptr() as _,
+            (buffer.len() * size_of::<u64>()) as DWORD,
+            &mut returned,
+        )
+    };
+
+    unsafe { CloseHandle(token); }
+
+    if status == 0 {
+        return None;
+    }
+
+    let token_user: &TOKEN_USER = unsafe { &*(buffer.as_ptr() as *const TOKEN_USER) };
+    let mut name = [0u16; 256];
+    let mut name_len: DWORD = name.len() as DWORD;
+    let mut domain = [0u16; 256];
+    let mut domain_len: DWORD = domain.len() as DWORD;
+    let mut sid_type = 0;
+
+    let status: i32 = unsafe {
+        LookupAccountSidW(
+            null_mut(),
+            token_user.User.Sid,
+            name.as_mut_ptr(),
+            &mut name_len,
+            domain.as_mut_ptr(),
+            &mut domain_len,
+            &mut sid_type,
+        )
+    };
+
+    if status == 0 {
+        return None;
+    }
+
+    Some(format!(
+        "{}\\{}",
+        String::from_utf16_lossy(&domain[..domain_len as usize]),
+        String::from_utf16_lossy(&name[..name_len as usize])
+    ))
+}
+
+unsafe extern "system" fn close_window(h_wnd: HWND, l_param: LPARAM) -> BOOL {
+    let pid: u32 = l_param as u32;
+    let mut window_pid: DWORD = 0;
+
+    GetWindowThreadProcessId(h_wnd, &mut window_pid);
+
+    if window_pid == pid {
+        PostMessageW(h_wnd, WM_CLOSE, 0, 0);
+    }
+
+    TRUE
+}
+
+impl ProcessProvider for WindowsProvider {
+    fn processes(&self) -> Result<Vec<ProcessInfo>> {
+        let process_ids: Vec<u32> = get_process_ids().ok_or_else(Error::last_os_error)?;
+        let mut processes: Vec<ProcessInfo> = Vec::new();
+
+        for pid in process_ids {
+            let handle: HANDLE = match get_handle(pid, PROCESS_QUERY_LIMITED_INFORMATION) {
+                Some(h) => h,
+                None => continue,
+            };
+
+            let exe: Option<PathBuf> = get_process_exe(handle);
+            let owner: Option<String> = get_process_owner(handle);
+            unsafe { CloseHandle(handle); }
+
+            let name: String = match exe.as_ref().and_then(|e| e.file_name()) {
+                Some(n) => n.to_string_lossy().to_string(),
+                None => continue,
+            };
+
+            processes.push(ProcessInfo { pid, name, exe, owner });
+        }
+
+        Ok(processes)
+    }
+
+    fn is_running(&self, pid: u32) -> bool {
+        let handle: HANDLE = match get_handle(pid, PROCESS_QUERY_LIMITED_INFORMATION) {
+            Some(h) => h,
+            None => return false,
+        };
+
+        let mut exit_code: DWORD = 0;
+        let status: i32 = unsafe { GetExitCodeProcess(handle, &mut exit_code) };
+        unsafe { CloseHandle(handle); }
+
+        status != 0 && exit_code == STILL_ACTIVE
+    }
+
+    fn terminate(&self, pid: u32, termination: Termination) -> Result<()> {
+        match termination {
+            Termination::Graceful => {
+                unsafe { EnumWindows(Some(close_window), pid as LPARAM); }
+                Ok(())
+            }
+            Termination::Forced => {
+                let handle: HANDLE = get_handle(pid, PROCESS_TERMINATE).ok_or_else(Error::last_os_error)?;
+                let status: i32 = unsafe { TerminateProcess(handle, 0) };
+                let error: Error = Error::last_os_error();
+                unsafe { CloseHandle(handle); }
+
+                match status {
+                    0 => Err(error),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::io::Result;
+use std::path::PathBuf;
+
+use crate::profiles::{AccountKind, Profile, ProfileSource};
+
+// Profiles given up front, whatever the system has
+pub struct FixedProfiles {
+    profiles: Vec<Profile>,
+}
+
+impl FixedProfiles {
+    pub fn new() -> FixedProfiles {
+        FixedProfiles { profiles: Vec::new() }
+    }
+
+    pub fn with(mut self, id: &str, name: &str, path: &str, kind: AccountKind) -> FixedProfiles {
+        self.profiles.push(Profile {
+            id: id.to_string(),
+            name: name.to_string(),
+            path: PathBuf::from(path),
+            temp: None,
+            kind,
+        });
+
+        self
+    }
+}
+
+impl ProfileSource for FixedProfiles {
+    fn profiles(&self) -> Result<Vec<Profile>> {
+        Ok(self.profiles.clone())
+    }
+}
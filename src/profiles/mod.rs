@@ -0,0 +1,114 @@
+use std::io::Result;
+use std::path::PathBuf;
+
+#[cfg(unix)]
+pub use self::unix::PasswdProfiles as SystemProfiles;
+#[cfg(windows)]
+pub use self::windows::RegistryProfiles as SystemProfiles;
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+#[cfg(test)]
+pub mod mock;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccountKind {
+    User,
+    Service,
+}
+
+// One profile known to the OS, `id` is the SID on Windows and the uid on Unix
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub path: PathBuf,
+    pub temp: Option<PathBuf>,
+    pub kind: AccountKind,
+}
+
+impl Profile {
+    pub fn describe(&self) -> String {
+        let kind: &str = match self.kind {
+            AccountKind::User => "user",
+            AccountKind::Service => "service",
+        };
+
+        format!("{} ({}, {})", self.name, kind, self.id)
+    }
+}
+
+// Which profiles the per-user rules reach, a comma separated list of
+// `current`, `interactive`, `services` and account names
+#[derive(Clone, Debug)]
+pub struct UserScope {
+    pub current: bool,
+    pub interactive: bool,
+    pub services: bool,
+    pub names: Vec<String>,
+}
+
+impl UserScope {
+    pub fn parse(value: &str) -> UserScope {
+        let mut scope = UserScope { current: false, interactive: false, services: false, names: Vec::new() };
+
+        for item in value.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()) {
+            match item.to_lowercase().as_str() {
+                "current" => scope.current = true,
+                "interactive" => scope.interactive = true,
+                "services" => scope.services = true,
+                _ => scope.names.push(item.to_string()),
+            }
+        }
+
+        scope
+    }
+
+    pub fn includes(&self, profile: &Profile, current: bool) -> bool {
+        (self.current && current)
+            || (self.interactive && profile.kind == AccountKind::User)
+            || (self.services && profile.kind == AccountKind::Service)
+            || self.names.iter().any(|n| n.eq_ignore_ascii_case(&profile.name))
+    }
+}
+
+pub trait ProfileSource {
+    fn profiles(&self) -> Result<Vec<Profile>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccountKind, Profile, ProfileSource, UserScope};
+    use super::mock::FixedProfiles;
+
+    fn reached(scope: &str) -> Vec<String> {
+        let profiles: Vec<Profile> = FixedProfiles::new()
+            .with("1000", "alice", "/home/alice", AccountKind::User)
+            .with("1001", "bob", "/home/bob", AccountKind::User)
+            .with("0", "root", "/root", AccountKind::Service)
+            .with("33", "www-data", "/var/www", AccountKind::Service)
+            .profiles()
+            .unwrap();
+
+        let scope: UserScope = UserScope::parse(scope);
+
+        profiles.iter()
+            .filter(|p| scope.includes(p, p.name == "alice"))
+            .map(|p| p.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn scopes() {
+        assert_eq!(reached("current"), vec!["alice"]);
+        assert_eq!(reached("interactive"), vec!["alice", "bob"]);
+        assert_eq!(reached(" Services "), vec!["root", "www-data"]);
+        assert_eq!(reached("current,WWW-DATA"), vec!["alice", "www-data"]);
+        assert_eq!(reached("interactive,services"), vec!["alice", "bob", "root", "www-data"]);
+        assert!(reached("").is_empty());
+        assert!(reached(",,").is_empty());
+        assert!(reached("nobody").is_empty());
+    }
+}
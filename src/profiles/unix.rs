@@ -0,0 +1,93 @@
+use std::env::var_os;
+use std::fs::read_to_string;
+use std::io::Result;
+use std::path::PathBuf;
+
+use crate::profiles::{AccountKind, Profile, ProfileSource};
+
+const PASSWD: &str = "/etc/passwd";
+
+// Accounts listed in /etc/passwd that own a home directory
+pub struct PasswdProfiles;
+
+impl PasswdProfiles {
+    pub fn new() -> PasswdProfiles {
+        PasswdProfiles
+    }
+}
+
+// Regular accounts start at 1000, nobody is the usual exception
+fn account_kind(uid: u32) -> AccountKind {
+    match uid >= 1000 && uid != 65534 {
+        true => AccountKind::User,
+        false => AccountKind::Service,
+    }
+}
+
+// The private directory of pam_tmpdir, or TMPDIR for the user running the clean
+fn temp_dir(uid: u32) -> Option<PathBuf> {
+    let private: PathBuf = PathBuf::from(format!("/tmp/user/{}", uid));
+
+    if private.is_dir() {
+        return Some(private);
+    }
+
+    match var_os("TMPDIR") {
+        Some(t) if uid == unsafe { libc::geteuid() } => Some(PathBuf::from(t)),
+        _ => None,
+    }
+}
+
+fn parse_passwd(content: &str) -> Vec<Profile> {
+    let mut profiles: Vec<Profile> = Vec::new();
+
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+
+        if fields.len() < 7 || line.starts_with('#') {
+            continue;
+        }
+
+        let uid: u32 = match fields[2].parse() {
+            Ok(u) => u,
+            _ => continue,
+        };
+
+        let path: PathBuf = PathBuf::from(fields[5]);
+
+        if fields[5] == "/" || !path.is_dir() {
+            continue;
+        }
+
+        profiles.push(Profile {
+            id: uid.to_string(),
+            name: fields[0].to_string(),
+            path,
+            temp: temp_dir(uid),
+            kind: account_kind(uid),
+        });
+    }
+
+    profiles
+}
+
+impl ProfileSource for PasswdProfiles {
+    fn profiles(&self) -> Result<Vec<Profile>> {
+        Ok(parse_passwd(&read_to_string(PASSWD)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profiles::AccountKind;
+
+    use super::account_kind;
+
+    #[test]
+    fn system_accounts_are_services() {
+        assert_eq!(account_kind(0), AccountKind::Service);
+        assert_eq!(account_kind(999), AccountKind::Service);
+        assert_eq!(account_kind(65534), AccountKind::Service);
+        assert_eq!(account_kind(1000), AccountKind::User);
+    }
+}
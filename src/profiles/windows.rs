@@ -0,0 +1,206 @@
+use std::ffi::OsString;
+use std::io::{Error, Result};
+use std::os::windows::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+
+use winapi::shared::minwindef::{DWORD, HKEY};
+use winapi::shared::sddl::ConvertStringSidToSidW;
+use winapi::shared::winerror::{ERROR_NO_MORE_ITEMS, ERROR_SUCCESS};
+use winapi::um::processenv::ExpandEnvironmentStringsW;
+use winapi::um::winbase::{LocalFree, LookupAccountSidW};
+use winapi::um::winnt::{KEY_READ, PSID};
+use winapi::um::winreg::{
+    HKEY_LOCAL_MACHINE, HKEY_USERS, RegCloseKey, RegEnumKeyExW, RegGetValueW, RegOpenKeyExW, RRF_NOEXPAND,
+    RRF_RT_REG_EXPAND_SZ, RRF_RT_REG_SZ,
+};
+
+use crate::profiles::{AccountKind, Profile, ProfileSource};
+use crate::utils::to_utf16;
+
+const PROFILE_LIST: &str = "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion\\ProfileList";
+
+// Profiles listed by the OS under ProfileList, one sub-key per SID
+pub struct RegistryProfiles;
+
+impl RegistryProfiles {
+    pub fn new() -> RegistryProfiles {
+        RegistryProfiles
+    }
+}
+
+fn from_wide(buffer: &[u16]) -> OsString {
+    let len: usize = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+    OsString::from_wide(&buffer[..len])
+}
+
+fn sub_keys(root: HKEY, path: &str) -> Result<Vec<String>> {
+    let mut key: HKEY = null_mut();
+    let status = unsafe { RegOpenKeyExW(root, to_utf16(path).as_ptr(), 0, KEY_READ, &mut key) };
+
+    if status != ERROR_SUCCESS as i32 {
+        return Err(Error::from_raw_os_error(status));
+    }
+
+    let mut names: Vec<String> = Vec::new();
+    let mut index: DWORD = 0;
+
+    loop {
+        let mut name = [0u16; 256];
+        let mut name_len: DWORD = name.len() as DWORD;
+
+        let status = unsafe {
+            RegEnumKeyExW(key, index, name.as_mut_ptr(), &mut name_len, null_mut(), null_mut(), null_mut(), null_mut())
+        };
+
+        if status == ERROR_NO_MORE_ITEMS as i32 {
+            break;
+        }
+
+        if status == ERROR_SUCCESS as i32 {
+            names.push(String::from_utf16_lossy(&name[..name_len as usize]));
+        }
+
+        index += 1;
+    }
+
+    unsafe { RegCloseKey(key); }
+
+    Ok(names)
+}
+
+// The raw string, environment variables are left for the caller to expand
+fn string_value(root: HKEY, path: &str, value: &str) -> Option<String> {
+    let mut buffer = [0u16; 1024];
+    let mut size: DWORD = (buffer.len() * 2) as DWORD;
+
+    let status = unsafe {
+        RegGetValueW(
+            root,
+            to_utf16(path).as_ptr(),
+            to_utf16(value).as_ptr(),
+            RRF_RT_REG_SZ | RRF_RT_REG_EXPAND_SZ | RRF_NOEXPAND,
+            null_mut(),
+            buffer.as_mut_ptr() as _,
+            &mut size,
+        )
+    };
+
+    if status != ERROR_SUCCESS as i32 {
+        return None;
+    }
+
+    Some(from_wide(&buffer).to_string_lossy().to_string())
+}
+
+fn expand(value: &str) -> String {
+    let mut buffer = [0u16; 1024];
+    let len: DWORD = unsafe { ExpandEnvironmentStringsW(to_utf16(value).as_ptr(), buffer.as_mut_ptr(), buffer.len() as DWORD) };
+
+    if len == 0 || len as usize > buffer.len() {
+        return value.to_string();
+    }
+
+    from_wide(&buffer).to_string_lossy().to_string()
+}
+
+// Variables of the profile owner are replaced by their own values, the
+// process environment only describes the user running the clean
+fn expand_for(value: &str, profile: &Path) -> String {
+    let mut value: String = value.to_string();
+
+    for (name, path) in [
+        ("%USERPROFILE%", profile.to_path_buf()),
+        ("%LOCALAPPDATA%", profile.join("AppData\\Local")),
+        ("%APPDATA%", profile.join("AppData\\Roaming")),
+    ].iter() {
+        while let Some(start) = value.to_ascii_uppercase().find(name) {
+            value.replace_range(start..start + name.len(), &path.display().to_string());
+        }
+    }
+
+    expand(&value)
+}
+
+fn account_name(sid: &str) -> Option<String> {
+    let mut psid: PSID = null_mut();
+
+    if unsafe { ConvertStringSidToSidW(to_utf16(sid).as_ptr(), &mut psid) } == 0 {
+        return None;
+    }
+
+    let mut name = [0u16; 256];
+    let mut name_len: DWORD = name.len() as DWORD;
+    let mut domain = [0u16; 256];
+    let mut domain_len: DWORD = domain.len() as DWORD;
+    let mut sid_type = 0;
+
+    let status: i32 = unsafe {
+        LookupAccountSidW(
+            null_mut(),
+            psid,
+            name.as_mut_ptr(),
+            &mut name_len,
+            domain.as_mut_ptr(),
+            &mut domain_len,
+            &mut sid_type,
+        )
+    };
+
+    unsafe { LocalFree(psid as _); }
+
+    if status == 0 {
+        return None;
+    }
+
+    Some(String::from_utf16_lossy(&name[..name_len as usize]))
+}
+
+// S-1-5-21 holds local and domain accounts, everything else in the list is
+// SYSTEM, LocalService, NetworkService or a virtual service account
+fn account_kind(sid: &str) -> AccountKind {
+    match sid.starts_with("S-1-5-21-") {
+        true => AccountKind::User,
+        false => AccountKind::Service,
+    }
+}
+
+// HKU only holds the hives of loaded profiles, the others fall back to the
+// default location
+fn temp_dir(sid: &str, path: &Path) -> PathBuf {
+    match string_value(HKEY_USERS, &format!("{}\\Environment", sid), "TEMP") {
+        Some(t) => PathBuf::from(expand_for(&t, path)),
+        None => path.join("AppData\\Local\\Temp"),
+    }
+}
+
+impl ProfileSource for RegistryProfiles {
+    fn profiles(&self) -> Result<Vec<Profile>> {
+        let mut profiles: Vec<Profile> = Vec::new();
+
+        for sid in sub_keys(HKEY_LOCAL_MACHINE, PROFILE_LIST)? {
+            let image_path: String = match string_value(
+                HKEY_LOCAL_MACHINE, &format!("{}\\{}", PROFILE_LIST, sid), "ProfileImagePath",
+            ) {
+                Some(p) => expand(&p),
+                None => continue,
+            };
+
+            let path: PathBuf = PathBuf::from(image_path);
+
+            let name: String = account_name(&sid)
+                .or(path.file_name().map(|f| f.to_string_lossy().to_string()))
+                .unwrap_or(sid.clone());
+
+            profiles.push(Profile {
+                temp: Some(temp_dir(&sid, &path)),
+                kind: account_kind(&sid),
+                id: sid,
+                name,
+                path,
+            });
+        }
+
+        Ok(profiles)
+    }
+}
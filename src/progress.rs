@@ -0,0 +1,31 @@
+// Progress of a clean, shown by the GUI on Windows and dropped elsewhere
+#[cfg(windows)]
+use crate::globals::{LABEL_HANDLE, PROGRESS_HANDLE, TOTAL_STEP};
+#[cfg(windows)]
+use crate::gui::progress_bar::advance_progress_bar;
+#[cfg(windows)]
+use crate::gui::windows_helper::set_window_text;
+
+#[cfg(windows)]
+pub fn advance(step: u32) {
+    advance_progress_bar(unsafe { PROGRESS_HANDLE }, step);
+}
+
+#[cfg(windows)]
+pub fn status(text: &str) {
+    unsafe { set_window_text(LABEL_HANDLE, text); }
+}
+
+#[cfg(windows)]
+pub fn finish() {
+    advance(TOTAL_STEP);
+}
+
+#[cfg(not(windows))]
+pub fn advance(_step: u32) {}
+
+#[cfg(not(windows))]
+pub fn status(_text: &str) {}
+
+#[cfg(not(windows))]
+pub fn finish() {}
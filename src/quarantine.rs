@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::fs::{File, Metadata, OpenOptions, read_dir, read_to_string, remove_dir_all, symlink_metadata};
+use std::io::{copy as io_copy, Error, ErrorKind, Result, Write};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(windows)]
+use std::os::windows::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{Duration, Local, NaiveDateTime};
+use sha2::{Digest, Sha256};
+
+use crate::safe_remove::{Attributes, restore_contained};
+#[cfg(windows)]
+use crate::secure::owner_sid;
+use crate::secure::{check_owner, create_dir};
+
+pub const MANIFEST_FILE: &str = "manifest.txt";
+pub const PENDING_HASH: &str = "pending-reboot";
+const RUN_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+// One line of a run manifest: `size <TAB> sha256 <TAB> mode <TAB> owner <TAB>
+// stored name <TAB> original path`, mode and owner `-` when they could not be read
+#[derive(Clone)]
+pub struct ManifestEntry {
+    pub size: u64,
+    pub hash: String,
+    pub attributes: Option<Attributes>,
+    pub stored: String,
+    pub original: PathBuf,
+}
+
+impl ManifestEntry {
+    fn to_line(&self) -> String {
+        let (mode, owner): (String, &str) = match self.attributes {
+            Some(ref a) => (format!("{:x}", a.mode), &a.owner),
+            None => ("-".to_string(), "-"),
+        };
+
+        format!("{}\t{}\t{}\t{}\t{}\t{}\r\n", self.size, self.hash, mode, owner, self.stored, self.original.display())
+    }
+
+    fn parse(line: &str) -> Option<ManifestEntry> {
+        let mut fields = line.trim_end_matches(['\r', '\n']).splitn(6, '\t');
+
+        let size: u64 = fields.next()?.parse().ok()?;
+        let hash: String = fields.next()?.to_string();
+        let mode: &str = fields.next()?;
+        let owner: &str = fields.next()?;
+        let stored: String = fields.next()?.to_string();
+        let original: PathBuf = PathBuf::from(fields.next()?);
+
+        let attributes: Option<Attributes> = match (u32::from_str_radix(mode, 16), owner) {
+            (Ok(mode), o) if o != "-" => Some(Attributes { mode, owner: o.to_string() }),
+            _ => None,
+        };
+
+        Some(ManifestEntry { size, hash, attributes, stored, original })
+    }
+}
+
+#[cfg(unix)]
+fn attributes(path: &Path) -> Option<Attributes> {
+    let metadata: Metadata = symlink_metadata(path).ok()?;
+
+    Some(Attributes { mode: metadata.mode() & 0o7777, owner: metadata.uid().to_string() })
+}
+
+#[cfg(windows)]
+fn attributes(path: &Path) -> Option<Attributes> {
+    let metadata: Metadata = symlink_metadata(path).ok()?;
+
+    Some(Attributes { mode: metadata.file_attributes(), owner: owner_sid(path).ok()? })
+}
+
+pub struct Run {
+    pub name: String,
+    pub path: PathBuf,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Run {
+    pub fn size(&self) -> u64 {
+        self.entries.iter().map(|e| e.size).sum()
+    }
+
+    fn date(&self) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(&self.name[..19.min(self.name.len())], RUN_FORMAT).ok()
+    }
+}
+
+// A quarantine run, every file moved during one clean lands in the same
+// directory named after the date of the clean
+pub struct Quarantine {
+    run: PathBuf,
+    manifest: File,
+    counter: u64,
+    // Attributes of the files on their way in, by destination
+    taken: HashMap<PathBuf, Attributes>,
+}
+
+impl Quarantine {
+    pub fn create(dir: &Path) -> Result<Quarantine> {
+        let base: String = Local::now().format(RUN_FORMAT).to_string();
+        let mut run: PathBuf = dir.join(&base);
+        let mut suffix: u32 = 1;
+
+        while run.exists() {
+            run = dir.join(format!("{}_{}", base, suffix));
+            suffix += 1;
+        }
+
+        // The store is for the administrators only, a store someone else
+        // prepared is refused
+        create_dir(&run)?;
+
+        let manifest: File = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(run.join(MANIFEST_FILE))?;
+
+        Ok(Quarantine { run, manifest, counter: 0, taken: HashMap::new() })
+    }
+
+    pub fn run_dir(&self) -> &Path {
+        &self.run
+    }
+
+    // Files are stored under a counter, the original name only lives in the
+    // manifest so long paths never overflow the store. Mode and owner are
+    // read before the file moves, a copy onto another volume loses them.
+    pub fn next_destination(&mut self, original: &Path) -> PathBuf {
+        self.counter += 1;
+        let destination: PathBuf = self.run.join(format!("{:08}", self.counter));
+
+        if let Some(a) = attributes(original) {
+            self.taken.insert(destination.clone(), a);
+        }
+
+        destination
+    }
+
+    pub fn record(&mut self, original: &Path, stored: &Path, size: u64) -> Result<()> {
+        let hash: String = sha256_file(stored)?;
+        self.write_entry(original, stored, size, hash)
+    }
+
+    // Moved by the system at the next reboot, the hash can not be known yet
+    pub fn record_pending(&mut self, original: &Path, stored: &Path, size: u64) -> Result<()> {
+        self.write_entry(original, stored, size, PENDING_HASH.to_string())
+    }
+
+    fn write_entry(&mut self, original: &Path, stored: &Path, size: u64, hash: String) -> Result<()> {
+        let entry = ManifestEntry {
+            size,
+            hash,
+            attributes: self.taken.remove(stored),
+            stored: stored.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            original: original.to_path_buf(),
+        };
+
+        self.manifest.write_all(entry.to_line().as_bytes())
+    }
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file: File = File::open(path)?;
+    let mut hasher = Sha256::new();
+
+    io_copy(&mut file, &mut hasher)?;
+
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// A run is named by one plain component, never a path leading out of the store
+fn run_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    let mut components = Path::new(name).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(dir.join(name)),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("{} is not a run name", name))),
+    }
+}
+
+pub fn load_run(dir: &Path, name: &str) -> Result<Run> {
+    let path: PathBuf = run_path(dir, name)?;
+
+    // What a run holds goes back to where its manifest says, only the
+    // administrators may have written either
+    check_owner(dir)?;
+    check_owner(&path)?;
+    check_owner(&path.join(MANIFEST_FILE))?;
+
+    let content: String = read_to_string(path.join(MANIFEST_FILE))?;
+
+    Ok(Run {
+        name: name.to_string(),
+        entries: content.lines().filter_map(ManifestEntry::parse).collect(),
+        path,
+    })
+}
+
+pub fn list_runs(dir: &Path) -> Result<Vec<Run>> {
+    let mut runs: Vec<Run> = Vec::new();
+
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+
+        if !entry.path().join(MANIFEST_FILE).is_file() {
+            continue;
+        }
+
+        let name: String = entry.file_name().to_string_lossy().to_string();
+
+        if let Ok(run) = load_run(dir, &name) {
+            runs.push(run);
+        }
+    }
+
+    runs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(runs)
+}
+
+fn save_manifest(run: &Run) -> Result<()> {
+    let mut manifest: File = File::create(run.path.join(MANIFEST_FILE))?;
+
+    for entry in &run.entries {
+        manifest.write_all(entry.to_line().as_bytes())?;
+    }
+
+    Ok(())
+}
+
+// Only what a clean could have taken goes back: a path below one of its
+// targets, with nothing climbing out of it. The target is where the restore
+// starts from.
+fn restorable_root<'a>(original: &Path, roots: &'a [PathBuf]) -> Option<&'a PathBuf> {
+    roots.iter().find(|root| match original.strip_prefix(root) {
+        Ok(relative) => {
+            relative.components().next().is_some() && relative.components().all(|c| matches!(c, Component::Normal(_)))
+        }
+        _ => false,
+    })
+}
+
+fn restore_entry(run: &Run, entry: &ManifestEntry, roots: &[PathBuf]) -> Result<()> {
+    let root: &PathBuf = match restorable_root(&entry.original, roots) {
+        Some(r) => r,
+        None => return Err(Error::new(ErrorKind::PermissionDenied, "not below a target of the clean")),
+    };
+
+    let stored: PathBuf = run_path(&run.path, &entry.stored)?;
+
+    if !stored.is_file() {
+        return Err(Error::new(ErrorKind::NotFound, "missing from the quarantine"));
+    }
+
+    if entry.hash != PENDING_HASH && sha256_file(&stored)? != entry.hash {
+        return Err(Error::new(ErrorKind::InvalidData, "hash mismatch"));
+    }
+
+    restore_contained(root, &entry.original, &stored, entry.attributes.as_ref()).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => Error::new(ErrorKind::AlreadyExists, "original path already exists"),
+        _ => e,
+    })
+}
+
+// Restores the whole run, or only the given original paths, as long as they
+// are below `roots`. Restored entries leave the manifest, an emptied run is
+// removed.
+pub fn restore(dir: &Path, name: &str, only: &[PathBuf], roots: &[PathBuf]) -> Result<Vec<(PathBuf, Result<()>)>> {
+    let mut run: Run = load_run(dir, name)?;
+    let mut results: Vec<(PathBuf, Result<()>)> = Vec::new();
+    let mut remaining: Vec<ManifestEntry> = Vec::new();
+
+    for entry in std::mem::take(&mut run.entries) {
+        if !only.is_empty() && !only.iter().any(|p| p == &entry.original) {
+            remaining.push(entry);
+            continue;
+        }
+
+        let status: Result<()> = restore_entry(&run, &entry, roots);
+
+        if status.is_err() {
+            remaining.push(entry.clone());
+        }
+
+        results.push((entry.original, status));
+    }
+
+    run.entries = remaining;
+
+    if run.entries.is_empty() {
+        remove_dir_all(&run.path)?;
+    } else {
+        save_manifest(&run)?;
+    }
+
+    Ok(results)
+}
+
+pub fn purge(dir: &Path, days: u32) -> Result<Vec<Run>> {
+    let limit: NaiveDateTime = (Local::now() - Duration::days(i64::from(days))).naive_local();
+    let mut purged: Vec<Run> = Vec::new();
+
+    for run in list_runs(dir)? {
+        match run.date() {
+            Some(date) if date < limit => {
+                remove_dir_all(&run.path)?;
+                purged.push(run);
+            }
+            _ => continue,
+        }
+    }
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::fs::{create_dir, read_to_string, remove_dir_all, rename, set_permissions, symlink_metadata, Permissions};
+    #[cfg(unix)]
+    use std::os::unix::fs::{PermissionsExt, symlink};
+    use std::path::{Path, PathBuf};
+
+    #[cfg(unix)]
+    use crate::testing::TempRoot;
+
+    #[cfg(unix)]
+    use super::{Quarantine, restore};
+    use super::{restorable_root, run_path};
+
+    #[test]
+    fn run_names_stay_in_the_store() {
+        let store: &Path = Path::new("/store");
+
+        assert_eq!(run_path(store, "2024-01-01_00-00-00").unwrap(), store.join("2024-01-01_00-00-00"));
+        assert!(run_path(store, "..").is_err());
+        assert!(run_path(store, ".").is_err());
+        assert!(run_path(store, "").is_err());
+        assert!(run_path(store, "a/b").is_err());
+        assert!(run_path(store, "/etc").is_err());
+    }
+
+    #[test]
+    fn only_paths_below_a_target_are_restored() {
+        let roots: Vec<PathBuf> = vec![PathBuf::from("/tmp"), PathBuf::from("/home/a/.cache")];
+
+        assert_eq!(restorable_root(Path::new("/tmp/a"), &roots), Some(&roots[0]));
+        assert_eq!(restorable_root(Path::new("/home/a/.cache/b/c"), &roots), Some(&roots[1]));
+        assert!(restorable_root(Path::new("/tmp"), &roots).is_none());
+        assert!(restorable_root(Path::new("/tmp/../etc/passwd"), &roots).is_none());
+        assert!(restorable_root(Path::new("/etc/passwd"), &roots).is_none());
+        assert!(restorable_root(Path::new("/home/a/.bashrc"), &roots).is_none());
+    }
+
+    // Parents come back as directories, a link on the way is never followed
+    #[test]
+    #[cfg(unix)]
+    fn restored_with_mode_below_real_directories() {
+        let root: TempRoot = TempRoot::new();
+        let target: PathBuf = root.path().join("target");
+        let outside: PathBuf = root.path().join("outside");
+        let kept: PathBuf = root.file("target/a/b/kept", "kept");
+        let linked: PathBuf = root.file("target/link/linked", "linked");
+
+        set_permissions(&kept, Permissions::from_mode(0o640)).unwrap();
+
+        let mut quarantine: Quarantine = Quarantine::create(&root.path().join("store")).unwrap();
+        let run: String = quarantine.run_dir().file_name().unwrap().to_string_lossy().to_string();
+
+        for original in [&kept, &linked] {
+            let stored: PathBuf = quarantine.next_destination(original);
+            rename(original, &stored).unwrap();
+            quarantine.record(original, &stored, 4).unwrap();
+        }
+
+        drop(quarantine);
+
+        remove_dir_all(target.join("a")).unwrap();
+        remove_dir_all(target.join("link")).unwrap();
+        create_dir(&outside).unwrap();
+        symlink(&outside, target.join("link")).unwrap();
+
+        let results = restore(&root.path().join("store"), &run, &[], std::slice::from_ref(&target)).unwrap();
+
+        assert!(results.iter().any(|(p, s)| p == &kept && s.is_ok()));
+        assert!(results.iter().any(|(p, s)| p == &linked && s.is_err()));
+        assert_eq!(read_to_string(&kept).unwrap(), "kept");
+        assert_eq!(symlink_metadata(&kept).unwrap().permissions().mode() & 0o7777, 0o640);
+        assert!(symlink_metadata(target.join("a")).unwrap().is_dir());
+        assert!(symlink_metadata(outside.join("linked")).is_err());
+    }
+}
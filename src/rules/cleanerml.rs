@@ -0,0 +1,452 @@
+use std::fs::{read_dir, read_to_string};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use roxmltree::{Document, Node};
+
+use crate::rules::{Entry, Import, Rule};
+
+#[cfg(windows)]
+const OS: &str = "windows";
+#[cfg(not(windows))]
+const OS: &str = "linux";
+
+// Environment variables of the definitions, as an engine variable and the
+// path below it
+const VARIABLES: [(&str, &str, &str); 14] = [
+    ("~", "users", ""),
+    ("HOME", "users", ""),
+    ("XDG_CACHE_HOME", "xdg_cache", ""),
+    ("XDG_CONFIG_HOME", "users", ".config"),
+    ("XDG_DATA_HOME", "users", ".local/share"),
+    ("USERPROFILE", "users", ""),
+    ("APPDATA", "users", "AppData\\Roaming"),
+    ("LOCALAPPDATA", "users", "AppData\\Local"),
+    ("TEMP", "user_temp", ""),
+    ("TMP", "user_temp", ""),
+    ("windir", "system_root", ""),
+    ("SystemRoot", "system_root", ""),
+    ("ALLUSERSPROFILE", "all_user_profile", ""),
+    ("ProgramFiles", "program_files", ""),
+];
+
+// Options about private data, shredded when shredding is enabled
+const PRIVATE: [&str; 9] = [
+    "cache", "cookies", "history", "session", "passwords", "form_history", "download_history", "url_history", "dom",
+];
+
+// `os` takes one system, or several separated by `|`. Without it the element
+// applies everywhere.
+fn for_this_os(node: &Node) -> bool {
+    match node.attribute("os") {
+        Some(os) => os.split('|').any(|o| o.trim() == OS || (o.trim() == "posix" && cfg!(unix))),
+        None => true,
+    }
+}
+
+fn label(node: &Node, fallback: &str) -> String {
+    node.children()
+        .find(|c| c.has_tag_name("label"))
+        .and_then(|l| l.text())
+        .map(|l| l.trim().to_string())
+        .unwrap_or(fallback.to_string())
+}
+
+// `~`, `$NAME`, `${NAME}` or `%NAME%` in front, an absolute path is taken from
+// the root of the system
+fn target(path: &str) -> Option<(String, PathBuf)> {
+    let (name, rest): (&str, &str) = if let Some(rest) = path.strip_prefix('~') {
+        ("~", rest)
+    } else if let Some(rest) = path.strip_prefix("${") {
+        rest.split_once('}')?
+    } else if let Some(rest) = path.strip_prefix('$') {
+        rest.split_at(rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len()))
+    } else if let Some(rest) = path.strip_prefix('%') {
+        rest.split_once('%')?
+    } else if path.starts_with('/') {
+        ("/", path)
+    } else {
+        return None;
+    };
+
+    let (var, base): (&str, &str) = match name {
+        "/" => ("root", ""),
+        _ => VARIABLES.iter().find(|v| v.0.eq_ignore_ascii_case(name)).map(|v| (v.1, v.2))?,
+    };
+
+    let mut joined: PathBuf = PathBuf::new();
+
+    for part in base.split(['/', '\\']).chain(rest.split(['/', '\\'])).filter(|p| !p.is_empty()) {
+        joined.push(part);
+    }
+
+    Some((var.to_string(), joined))
+}
+
+fn describe_target(path: &str) -> String {
+    if !path.starts_with(['$', '%']) {
+        return "relative path: not supported".to_string();
+    }
+
+    let name: &str = path.trim_start_matches(['$', '{', '%']);
+    let end: usize = name.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(name.len());
+
+    match end {
+        0 => "relative path: not supported".to_string(),
+        _ => format!("${}: unknown variable", &name[..end]),
+    }
+}
+
+// The few regular expressions that are a plain wildcard in disguise, like
+// `\.log$` or `^cache_.*`. Anything else cannot be matched by the engine.
+fn regex_pattern(regex: &str) -> Option<String> {
+    let anchored_start: bool = regex.starts_with('^');
+    let anchored_end: bool = regex.ends_with('$') && !regex.ends_with("\\$");
+    let inner: &str = &regex[anchored_start as usize..regex.len() - anchored_end as usize];
+
+    let mut pattern: String = String::new();
+    let mut chars = inner.chars().peekable();
+
+    if !anchored_start {
+        pattern.push('*');
+    }
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(e)) if "._-+()[]{}^$|/ ".contains(*e) => {
+                pattern.push(*e);
+                chars.next();
+            }
+            ('.', Some('*')) => {
+                pattern.push('*');
+                chars.next();
+            }
+            ('.', Some('+')) => {
+                pattern.push_str("?*");
+                chars.next();
+            }
+            ('.', _) => pattern.push('?'),
+            ('\\' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '^' | '$', _) => return None,
+            _ => pattern.push(c),
+        }
+    }
+
+    if !anchored_end {
+        pattern.push('*');
+    }
+
+    while pattern.contains("**") {
+        pattern = pattern.replace("**", "*");
+    }
+
+    Some(pattern)
+}
+
+fn split_path(path: &Path) -> (String, String) {
+    let parent: String = path.parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+    let name: String = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+    (parent, name)
+}
+
+// One `delete` action, `search` telling how the path is searched
+fn action(node: &Node, path: &str, name: &str, import: &mut Import) -> Option<Rule> {
+    let (var, target): (String, PathBuf) = match target(path) {
+        Some(t) => t,
+        None => {
+            import.ignore(&describe_target(path), name);
+            return None;
+        }
+    };
+
+    if node.attribute("nregex").is_some() || node.attribute("wholeregex").is_some() {
+        import.ignore("nregex and wholeregex: not supported", name);
+        return None;
+    }
+
+    if node.attribute("type") == Some("d") {
+        import.ignore("type=\"d\": directories only are not supported", name);
+        return None;
+    }
+
+    if path.contains('[') {
+        import.ignore("character classes in paths: not supported", name);
+        return None;
+    }
+
+    let files: Option<String> = match node.attribute("regex") {
+        Some(r) => match regex_pattern(r) {
+            Some(p) => Some(p),
+            None => {
+                import.ignore(&format!("regex {}: not a plain wildcard", r), name);
+                return None;
+            }
+        },
+        None => None,
+    };
+
+    let full: String = target.to_string_lossy().to_string();
+    let (parent, file_name) = split_path(&target);
+
+    match (node.attribute("search").unwrap_or("file"), files) {
+        ("file" | "glob", None) => Some(Rule::new_files(&var, &parent, &file_name)),
+        ("walk.files", f) => Some(Rule::new_files(&var, &full, &f.unwrap_or("*".to_string())).recursive()),
+        // walk.top removes the folder with its content, only the empty
+        // folder is left here
+        ("walk.all" | "walk.top", None) => Some(Rule::new_clear(&var, &full)),
+        ("walk.all", Some(f)) => Some(Rule::new_files(&var, &full, &f).recursive()),
+        (search @ ("file" | "glob" | "walk.top"), Some(_)) => {
+            import.ignore(&format!("regex with search=\"{}\": not supported", search), name);
+            None
+        }
+        (search, _) => {
+            import.ignore(&format!("search=\"{}\": not supported", search), name);
+            None
+        }
+    }
+}
+
+// `$$name$$` stands for every value of the variable declared by the cleaner
+fn substitute(path: &str, vars: &[(String, Vec<String>)]) -> Vec<String> {
+    let mut paths: Vec<String> = vec![path.to_string()];
+
+    for (var, values) in vars {
+        let token: String = format!("$${}$$", var);
+
+        paths = paths.iter()
+            .flat_map(|p| match p.contains(&token) {
+                true => values.iter().map(|v| p.replace(&token, v)).collect(),
+                false => vec![p.clone()],
+            })
+            .collect();
+    }
+
+    paths
+}
+
+fn cleaner(node: &Node, import: &mut Import) {
+    let id: &str = node.attribute("id").unwrap_or("cleaner");
+    let cleaner_label: String = label(node, id);
+
+    let mut apps: Vec<String> = Vec::new();
+    let mut vars: Vec<(String, Vec<String>)> = Vec::new();
+
+    for child in node.children().filter(|c| c.is_element() && for_this_os(c)) {
+        match child.tag_name().name() {
+            "running" if child.attribute("type") == Some("exe") => {
+                apps.extend(child.text().map(|t| t.trim().to_string()));
+            }
+            "running" => import.ignore("running other than an exe: not checked", &cleaner_label),
+            "var" => {
+                let values: Vec<String> = child.children()
+                    .filter(|v| v.has_tag_name("value") && for_this_os(v))
+                    .filter_map(|v| v.text().map(|t| t.trim().to_string()))
+                    .collect();
+
+                vars.push((child.attribute("name").unwrap_or("").to_string(), values));
+            }
+            _ => {}
+        }
+    }
+
+    let apps: Vec<&str> = apps.iter().map(|a| a.as_str()).collect();
+
+    for option in node.children().filter(|c| c.has_tag_name("option") && for_this_os(c)) {
+        let option_id: &str = option.attribute("id").unwrap_or("option");
+        let name: String = format!("{}: {}", cleaner_label, label(&option, option_id));
+
+        if option.children().any(|c| c.has_tag_name("warning")) {
+            import.ignore("warning: options asking for confirmation are skipped", &name);
+            continue;
+        }
+
+        let mut rules: Vec<Rule> = Vec::new();
+
+        for node in option.children().filter(|c| c.has_tag_name("action") && for_this_os(c)) {
+            match node.attribute("command") {
+                Some("delete") => {}
+                command => {
+                    import.ignore(&format!("command {}: not supported", command.unwrap_or("none")), &name);
+                    continue;
+                }
+            }
+
+            for path in substitute(node.attribute("path").unwrap_or(""), &vars) {
+                if let Some(rule) = action(&node, &path, &name, import) {
+                    let rule: Rule = rule.apps(&apps);
+
+                    rules.push(match PRIVATE.contains(&option_id) {
+                        true => rule.shred(),
+                        false => rule,
+                    });
+                }
+            }
+        }
+
+        if !rules.is_empty() {
+            import.entries.push(Entry { name, detect: Vec::new(), rules });
+        }
+    }
+}
+
+pub fn parse(content: &str, import: &mut Import) -> Result<()> {
+    let document: Document = Document::parse(content).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    let root: Node = document.root_element();
+
+    if root.has_tag_name("cleaner") && for_this_os(&root) {
+        cleaner(&root, import);
+    }
+
+    Ok(())
+}
+
+// One CleanerML file, or every .xml file of a directory. A file that cannot
+// be parsed is reported, the others are still imported.
+pub fn load(path: &Path) -> Result<Import> {
+    let mut import: Import = Import::default();
+
+    let files: Vec<PathBuf> = match path.is_dir() {
+        true => {
+            let mut files: Vec<PathBuf> = read_dir(path)?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().map(|e| e == "xml").unwrap_or(false))
+                .collect();
+
+            files.sort();
+            files
+        }
+        false => vec![path.to_path_buf()],
+    };
+
+    for file in files {
+        let parsed: Result<()> = read_to_string(&file).and_then(|c| parse(&c, &mut import));
+
+        if let Err(e) = parsed {
+            let name: String = file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            import.ignore("unreadable or invalid file", &format!("{} ({})", name, e));
+        }
+    }
+
+    Ok(import)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_dir, read_to_string};
+    use std::path::{Path, PathBuf};
+
+    use crate::rules::{Import, Rule};
+
+    use super::{load, parse, regex_pattern, substitute};
+
+    // The entries with their rules and what was left out, one per line
+    fn render(import: &Import) -> String {
+        let mut text: String = String::new();
+
+        for entry in &import.entries {
+            text.push_str(&format!("{}\n", entry.name));
+
+            for rule in &entry.rules {
+                text.push_str(&format!("    {}", rule.describe()));
+
+                if rule.shred {
+                    text.push_str(" shred");
+                }
+
+                if !rule.apps.is_empty() {
+                    text.push_str(&format!(" [{}]", rule.apps.join(", ")));
+                }
+
+                text.push('\n');
+            }
+        }
+
+        for (reason, names) in &import.ignored {
+            for name in names {
+                text.push_str(&format!("ignored {} : {}\n", reason, name));
+            }
+        }
+
+        text
+    }
+
+    fn import(content: &str) -> Import {
+        let mut import: Import = Import::default();
+        parse(content, &mut import).unwrap();
+        import
+    }
+
+    #[test]
+    fn regex_patterns() {
+        assert_eq!(regex_pattern("\\.log$").as_deref(), Some("*.log"));
+        assert_eq!(regex_pattern("^cache_.*").as_deref(), Some("cache_*"));
+        assert_eq!(regex_pattern("^core\\..+$").as_deref(), Some("core.?*"));
+        assert_eq!(regex_pattern("^a.b$").as_deref(), Some("a?b"));
+        assert_eq!(regex_pattern("tmp").as_deref(), Some("*tmp*"));
+        assert_eq!(regex_pattern("\\$x$").as_deref(), Some("*$x"));
+        assert_eq!(regex_pattern("[0-9]+"), None);
+        assert_eq!(regex_pattern("^(a|b)$"), None);
+        assert_eq!(regex_pattern("a\\d"), None);
+    }
+
+    #[test]
+    fn variables() {
+        let vars: Vec<(String, Vec<String>)> = vec![
+            ("a".to_string(), vec!["1".to_string(), "2".to_string()]),
+            ("b".to_string(), vec!["x".to_string()]),
+        ];
+
+        assert_eq!(substitute("/$$a$$/$$b$$/$$a$$", &vars), vec!["/1/x/1", "/2/x/2"]);
+        assert_eq!(substitute("/$$c$$", &vars), vec!["/$$c$$"]);
+        assert!(substitute("/$$a$$", &[("a".to_string(), Vec::new())]).is_empty());
+    }
+
+    #[test]
+    fn systems() {
+        let content = |os: &str| format!(
+            "<cleaner id=\"c\" {}><option id=\"o\"><action command=\"delete\" search=\"walk.all\" path=\"/x\"/></option></cleaner>",
+            os
+        );
+
+        let imported = |os: &str| !import(&content(os)).entries.is_empty();
+
+        assert!(imported(""));
+        assert_eq!(imported("os=\"posix\""), cfg!(unix));
+        assert_eq!(imported("os=\"linux\""), cfg!(target_os = "linux"));
+        assert_eq!(imported("os=\"windows\""), cfg!(windows));
+        assert!(imported("os=\"windows|posix\""));
+    }
+
+    #[test]
+    fn walk_top_is_imported_as_a_clear() {
+        let import: Import = import(
+            "<cleaner id=\"c\"><option id=\"o\"><action command=\"delete\" search=\"walk.top\" path=\"/x/y\"/></option></cleaner>"
+        );
+
+        let rule: &Rule = &import.entries[0].rules[0];
+
+        assert_eq!(rule.path, PathBuf::from("x").join("y"));
+        assert!(rule.files.is_none());
+        assert!(import.ignored.is_empty());
+    }
+
+    // Every sample of the corpus against the rules it is expected to give
+    #[cfg(unix)]
+    #[test]
+    fn corpus() {
+        let dir: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cleanerml");
+        let mut samples: Vec<PathBuf> = read_dir(&dir).unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().map(|e| e == "xml").unwrap_or(false))
+            .collect();
+
+        samples.sort();
+        assert!(!samples.is_empty());
+
+        for sample in samples {
+            let expected: String = read_to_string(sample.with_extension("expected")).unwrap();
+            assert_eq!(render(&load(&sample).unwrap()), expected, "{}", sample.display());
+        }
+    }
+}
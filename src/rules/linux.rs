@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::rules::Rule;
+use crate::rules::tmpfiles::{age_rules, declares, Entry, load};
+
+// Sockets of the X server, never aged whatever tmpfiles.d says
+const X_SOCKETS: [&str; 5] = [".X11-unix", ".ICE-unix", ".XIM-unix", ".font-unix", ".Test-unix"];
+
+// The root of the layout, tmpfiles.d is read below it
+fn system_root<'a>(system_vars: &'a HashMap<&'static str, PathBuf>) -> &'a Path {
+    system_vars.get("root").map(PathBuf::as_path).unwrap_or(Path::new("/"))
+}
+
+pub fn walk_all_rules(system_vars: &HashMap<&'static str, PathBuf>) -> Vec<Rule> {
+    let mut rules: Vec<Rule> = vec![
+        Rule::new_clear("xdg_cache", ""),
+        Rule::new_clear("users", ".thumbnails"),
+        Rule::new_clear("xdg_cache", "chromium/Default/Cache").shred().apps(&["chromium", "chromium-browser"]),
+        Rule::new_clear("xdg_cache", "chromium/Default/Code Cache").apps(&["chromium", "chromium-browser"]),
+        Rule::new_clear("users", ".config/chromium/Default/GPUCache").apps(&["chromium", "chromium-browser"]),
+        Rule::new_clear("users", ".config/chromium/Default/Service Worker/CacheStorage").shred().apps(&["chromium", "chromium-browser"]),
+        Rule::new_clear("xdg_cache", "google-chrome/Default/Cache").shred().apps(&["chrome"]),
+        Rule::new_clear("xdg_cache", "google-chrome/Default/Code Cache").apps(&["chrome"]),
+        Rule::new_clear("users", ".config/google-chrome/Default/GPUCache").apps(&["chrome"]),
+        Rule::new_clear("xdg_cache", "BraveSoftware/Brave-Browser/Default/Cache").shred().apps(&["brave"]),
+        Rule::new_clear("xdg_cache", "BraveSoftware/Brave-Browser/Default/Code Cache").apps(&["brave"]),
+        Rule::new_clear("users", ".config/BraveSoftware/Brave-Browser/Default/GPUCache").apps(&["brave"]),
+        Rule::new_clear("xdg_cache", "vivaldi/Default/Cache").shred().apps(&["vivaldi-bin"]),
+        Rule::new_clear("xdg_cache", "vivaldi/Default/Code Cache").apps(&["vivaldi-bin"]),
+        Rule::new_clear("users", ".config/vivaldi/Default/GPUCache").apps(&["vivaldi-bin"]),
+        Rule::new_clear("user_temp", ""),
+    ];
+
+    rules.extend(temp_rules(&load(system_root(system_vars))));
+    rules
+}
+
+// Same ages as the systemd defaults, for the paths tmpfiles.d does not declare
+fn temp_defaults() -> Vec<Rule> {
+    vec![
+        Rule::new_clear("root", "tmp").older_than(10),
+        Rule::new_clear("root", "var/tmp").older_than(30),
+        Rule::new_clear("root", "var/lib/systemd/coredump").older_than(3),
+    ]
+}
+
+fn temp_rules(entries: &[Entry]) -> Vec<Rule> {
+    let mut rules: Vec<Rule> = age_rules(entries);
+    rules.extend(temp_defaults().into_iter().filter(|r| !declares(entries, &Path::new("/").join(&r.path))));
+
+    rules.into_iter()
+        .map(|r| match r.path == Path::new("tmp") {
+            true => r.keep(&X_SOCKETS),
+            false => r,
+        })
+        .collect()
+}
+
+// Excluded or declared without an age by tmpfiles.d
+pub fn kept_rules(system_vars: &HashMap<&'static str, PathBuf>) -> Vec<Rule> {
+    super::tmpfiles::kept_rules(&load(system_root(system_vars)))
+}
+
+// Package managers keep their locks and partial downloads next to the
+// packages, only the packages themselves are removed
+pub fn glob_file_rules() -> Vec<Rule> {
+    vec![
+        Rule::new_extension("root", "deb").inside("var/cache/apt/archives"),
+        Rule::new_extension("root", "rpm").inside("var/cache/dnf").recursive(),
+        Rule::new_extension("root", "zst").inside("var/cache/pacman/pkg"),
+        Rule::new_extension("root", "xz").inside("var/cache/pacman/pkg"),
+        Rule::new_extension("root", "gz").inside("var/log").recursive(),
+        Rule::new_extension("root", "1").inside("var/log").recursive(),
+    ]
+}
+
+// Directories holding one sub-directory per browser profile
+pub fn profile_dirs() -> Vec<[&'static str; 2]> {
+    vec![
+        ["firefox_profil", ".cache/mozilla/firefox"],
+    ]
+}
+
+pub fn profile_rules() -> Vec<Rule> {
+    vec![
+        Rule::new_clear("firefox_profil", "cache2").shred().apps(&["firefox", "firefox-esr", "firefox-bin"]),
+        Rule::new_clear("firefox_profil", "startupCache").apps(&["firefox", "firefox-esr", "firefox-bin"]),
+        Rule::new_clear("firefox_profil", "thumbnails").apps(&["firefox", "firefox-esr", "firefox-bin"]),
+        Rule::new_clear("firefox_profil", "OfflineCache").shred().apps(&["firefox", "firefox-esr", "firefox-bin"]),
+    ]
+}
+
+pub fn windows_old_rules() -> Vec<Rule> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use crate::clean::{Action, explain};
+    use crate::rules::{Rule, Stamps};
+    use crate::settings::Settings;
+    use crate::testing::TempRoot;
+
+    use super::{kept_rules, walk_all_rules, X_SOCKETS};
+
+    const DAY: u64 = 86_400;
+
+    fn layout(root: &TempRoot) -> HashMap<&'static str, PathBuf> {
+        let mut system_vars: HashMap<&'static str, PathBuf> = HashMap::new();
+        system_vars.insert("root", root.path().to_path_buf());
+        system_vars
+    }
+
+    fn rule<'a>(rules: &'a [Rule], path: &str) -> Option<&'a Rule> {
+        rules.iter().find(|r| r.var == "root" && r.path == Path::new(path))
+    }
+
+    fn days(n: u64) -> Option<Duration> {
+        Some(Duration::from_secs(n * DAY))
+    }
+
+    #[test]
+    fn defaults_without_tmpfiles() {
+        let root: TempRoot = TempRoot::new();
+        let rules: Vec<Rule> = walk_all_rules(&layout(&root));
+
+        let tmp: &Rule = rule(&rules, "tmp").unwrap();
+        assert_eq!(tmp.min_age, days(10));
+        assert_eq!(tmp.keep, X_SOCKETS.iter().map(PathBuf::from).collect::<Vec<PathBuf>>());
+
+        assert_eq!(rule(&rules, "var/tmp").unwrap().min_age, days(30));
+        assert_eq!(rule(&rules, "var/lib/systemd/coredump").unwrap().min_age, days(3));
+        assert!(kept_rules(&layout(&root)).is_empty());
+    }
+
+    #[test]
+    fn tmpfiles_replace_the_defaults() {
+        let root: TempRoot = TempRoot::new();
+        root.file("usr/lib/tmpfiles.d/tmp.conf", "q /tmp 1777 root root 10d\nq /var/tmp 1777 root root 30d\n");
+        root.file("etc/tmpfiles.d/tmp.conf", "q /tmp 1777 root root m:1d\nx /tmp/keep-*\nX /tmp/session\n");
+        root.file("etc/tmpfiles.d/var.conf", "d /var/tmp 1777 root root -\n");
+
+        let rules: Vec<Rule> = walk_all_rules(&layout(&root));
+
+        // The file in /etc masks the one of the same name in /usr/lib
+        let tmp: &Rule = rule(&rules, "tmp").unwrap();
+        assert_eq!(tmp.min_age, days(1));
+        assert_eq!(tmp.age_by.files, Stamps { modified: true, ..Stamps::default() });
+        assert_eq!(tmp.keep, X_SOCKETS.iter().map(PathBuf::from).collect::<Vec<PathBuf>>());
+        assert_eq!(tmp.keep_dirs, vec![PathBuf::from("session")]);
+
+        // Declared without an age, neither aged by default nor cleared
+        assert!(rule(&rules, "var/tmp").is_none());
+        assert_eq!(rule(&rules, "var/lib/systemd/coredump").unwrap().min_age, days(3));
+
+        let kept: Vec<Rule> = kept_rules(&layout(&root));
+        assert!(rule(&kept, "var/tmp").is_some());
+        assert!(rule(&kept, "tmp/keep-*").is_some());
+    }
+
+    #[test]
+    fn selection() {
+        let root: TempRoot = TempRoot::new();
+        root.file("etc/tmpfiles.d/tmp.conf", "q /tmp 1777 root root m:1d\nx /tmp/keep-*\nd /var/tmp 1777 root root -\n");
+
+        let old: Duration = Duration::from_secs(2 * DAY);
+        let paths: [(&str, Action); 12] = [
+            ("tmp/old", Action::Delete),
+            ("tmp/young", Action::Keep),
+            ("tmp/.X11-unix/X0", Action::Protected),
+            ("tmp/keep-this/old", Action::Protected),
+            ("var/tmp/old", Action::Protected),
+            ("home/u/.cache/app/data", Action::Delete),
+            ("var/cache/apt/archives/app.deb", Action::Delete),
+            ("var/cache/apt/archives/lock", Action::Keep),
+            ("var/cache/apt/archives/partial/next.deb", Action::Keep),
+            ("var/log/syslog", Action::Keep),
+            ("var/log/syslog.1", Action::Delete),
+            ("var/log/nginx/access.log.2.gz", Action::Delete),
+        ];
+
+        for (path, _) in paths.iter() {
+            root.file(path, "content");
+
+            if *path != "tmp/young" {
+                root.aged(path, old);
+            }
+        }
+
+        let settings: Settings = Settings::default();
+
+        for (path, action) in paths.iter() {
+            let explanation = explain(&root.path().join(path), Some(root.path()), false, &settings);
+            assert_eq!(explanation.action, *action, "{}", path);
+        }
+    }
+}
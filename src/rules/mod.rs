@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{MAIN_SEPARATOR, Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+pub use self::linux::{glob_file_rules, kept_rules, profile_dirs, profile_rules, walk_all_rules, windows_old_rules};
+#[cfg(windows)]
+pub use self::windows::{glob_file_rules, kept_rules, profile_dirs, profile_rules, walk_all_rules, windows_old_rules};
+pub use self::cleanerml::load as load_cleanerml;
+pub use self::winapp2::load as load_winapp2;
+
+mod cleanerml;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+mod tmpfiles;
+#[cfg(windows)]
+mod windows;
+mod winapp2;
+
+const DAY: u64 = 24 * 60 * 60;
+
+// Variables resolved once per profile: its root, its cache and its own temp
+pub const PROFILE_VARS: [&str; 3] = ["users", "xdg_cache", "user_temp"];
+
+// A cleaning target: `path` is joined to every directory `var` resolves to,
+// `*` and `?` in it match any directory. Without `files` the whole content of
+// the target is cleared, with a file name pattern only the matching files at
+// the first level are removed.
+// `apps` are the executables holding the target open, they are asked to
+// close before cleaning and the rule is skipped while one of them still runs.
+// With `min_age` only what was left untouched that long by the time stamps of
+// `age_by` is removed, `keep` names entries of the target that are never
+// touched and `keep_dirs` directories that stay while their content goes.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub var: String,
+    pub path: PathBuf,
+    pub files: Option<String>,
+    pub recursive: bool,
+    pub min_age: Option<Duration>,
+    pub age_by: AgeBy,
+    pub keep: Vec<PathBuf>,
+    pub keep_dirs: Vec<PathBuf>,
+    pub shred: bool,
+    pub apps: Vec<String>,
+}
+
+impl Rule {
+    pub fn new_clear(var: &str, path: &str) -> Rule {
+        Rule {
+            var: var.to_string(),
+            path: PathBuf::from(path),
+            files: None,
+            recursive: false,
+            min_age: None,
+            age_by: AgeBy::default(),
+            keep: Vec::new(),
+            keep_dirs: Vec::new(),
+            shred: false,
+            apps: Vec::new(),
+        }
+    }
+
+    pub fn new_extension(var: &str, extension: &str) -> Rule {
+        Rule::new_files(var, "", &format!("*.{}", extension))
+    }
+
+    pub fn new_files(var: &str, path: &str, pattern: &str) -> Rule {
+        Rule {
+            var: var.to_string(),
+            path: PathBuf::from(path),
+            files: Some(pattern.to_string()),
+            recursive: false,
+            min_age: None,
+            age_by: AgeBy::default(),
+            keep: Vec::new(),
+            keep_dirs: Vec::new(),
+            shred: false,
+            apps: Vec::new(),
+        }
+    }
+
+    // Privacy-sensitive target, overwritten before unlink when shredding is enabled
+    fn shred(mut self) -> Rule {
+        self.shred = true;
+        self
+    }
+
+    fn apps(mut self, apps: &[&str]) -> Rule {
+        self.apps = apps.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    // File rules look for their files in a sub-directory of the variable
+    #[cfg_attr(windows, allow(dead_code))]
+    fn inside(mut self, path: &str) -> Rule {
+        self.path = PathBuf::from(path);
+        self
+    }
+
+    // File rules look below the first level too
+    fn recursive(mut self) -> Rule {
+        self.recursive = true;
+        self
+    }
+
+    #[cfg_attr(windows, allow(dead_code))]
+    fn older_than(self, days: u64) -> Rule {
+        self.aged(Duration::from_secs(days * DAY))
+    }
+
+    #[cfg_attr(windows, allow(dead_code))]
+    fn aged(mut self, min_age: Duration) -> Rule {
+        self.min_age = Some(min_age);
+        self
+    }
+
+    #[cfg_attr(windows, allow(dead_code))]
+    fn aged_by(mut self, age_by: AgeBy) -> Rule {
+        self.age_by = age_by;
+        self
+    }
+
+    #[cfg_attr(windows, allow(dead_code))]
+    fn keep(mut self, names: &[&str]) -> Rule {
+        self.keep = names.iter().map(PathBuf::from).collect();
+        self
+    }
+
+    #[cfg_attr(windows, allow(dead_code))]
+    fn keep_dirs(mut self, names: Vec<PathBuf>) -> Rule {
+        self.keep_dirs = names;
+        self
+    }
+
+    pub fn describe(&self) -> String {
+        let mut text: String = format!("%{}%", self.var);
+        let path: String = self.path.display().to_string();
+        let path: &str = path.trim_start_matches(MAIN_SEPARATOR);
+
+        if !path.is_empty() {
+            text.push(MAIN_SEPARATOR);
+            text.push_str(path);
+        }
+
+        if let Some(ref f) = self.files {
+            if self.recursive {
+                text.push_str(&format!("{}**", MAIN_SEPARATOR));
+            }
+
+            text.push_str(&format!("{}{}", MAIN_SEPARATOR, f));
+        }
+
+        if let Some(age) = self.min_age {
+            text.push_str(&format!(" older than {}", describe_age(age)));
+        }
+
+        text
+    }
+
+    // Inside a user profile, reachable without elevation for the current user
+    pub fn is_per_user(&self) -> bool {
+        PROFILE_VARS.contains(&self.var.as_str()) || profile_dirs().iter().any(|d| d[0] == self.var)
+    }
+
+    pub fn blocked_by(&self, running: &[String]) -> Vec<String> {
+        self.apps.iter()
+            .filter(|a| running.iter().any(|r| r.eq_ignore_ascii_case(a)))
+            .cloned()
+            .collect()
+    }
+}
+
+// The time stamps of an entry an age is judged on
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stamps {
+    pub accessed: bool,
+    pub created: bool,
+    pub changed: bool,
+    pub modified: bool,
+}
+
+impl Stamps {
+    const ALL: Stamps = Stamps { accessed: true, created: true, changed: true, modified: true };
+}
+
+// tmpfiles.d age-by letters: `a`, `b`, `c` and `m` for the access, birth,
+// change and modification times of files, the capitals for directories
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AgeBy {
+    pub files: Stamps,
+    pub dirs: Stamps,
+}
+
+impl Default for AgeBy {
+    // Listing a directory moves its access time, the scan itself would keep
+    // every directory young
+    fn default() -> AgeBy {
+        AgeBy { files: Stamps::ALL, dirs: Stamps { accessed: false, ..Stamps::ALL } }
+    }
+}
+
+impl AgeBy {
+    // Only tmpfiles.d names them
+    #[cfg(any(unix, test))]
+    pub fn parse(letters: &str) -> Option<AgeBy> {
+        if letters.is_empty() {
+            return None;
+        }
+
+        let mut age_by = AgeBy { files: Stamps::default(), dirs: Stamps::default() };
+
+        for letter in letters.chars() {
+            let stamps: &mut Stamps = match letter.is_ascii_uppercase() {
+                true => &mut age_by.dirs,
+                false => &mut age_by.files,
+            };
+
+            match letter.to_ascii_lowercase() {
+                'a' => stamps.accessed = true,
+                'b' => stamps.created = true,
+                'c' => stamps.changed = true,
+                'm' => stamps.modified = true,
+                _ => return None,
+            }
+        }
+
+        Some(age_by)
+    }
+}
+
+fn describe_age(age: Duration) -> String {
+    let seconds: u64 = age.as_secs();
+
+    match seconds {
+        s if s >= DAY && s % DAY == 0 => format!("{} days", s / DAY),
+        s if s >= 3600 && s % 3600 == 0 => format!("{} hours", s / 3600),
+        s if s >= 60 && s % 60 == 0 => format!("{} minutes", s / 60),
+        s => format!("{} seconds", s),
+    }
+}
+
+// Shell-like match, `*` and `?` never cross a separator. A path without
+// wildcards only matches itself.
+pub fn path_matches(pattern: &Path, path: &Path) -> bool {
+    let pattern: Vec<String> = pattern.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+    let path: Vec<String> = path.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+
+    pattern.len() == path.len() && pattern.iter().zip(path.iter()).all(|(p, c)| {
+        wildcard(&p.chars().collect::<Vec<char>>(), &c.chars().collect::<Vec<char>>())
+    })
+}
+
+pub fn name_matches(pattern: &str, name: &OsStr) -> bool {
+    wildcard(&pattern.chars().collect::<Vec<char>>(), &name.to_string_lossy().chars().collect::<Vec<char>>())
+}
+
+pub fn has_wildcards(name: &OsStr) -> bool {
+    name.to_string_lossy().contains(['*', '?'])
+}
+
+fn wildcard(pattern: &[char], text: &[char]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some('*'), _) => wildcard(&pattern[1..], text) || (!text.is_empty() && wildcard(pattern, &text[1..])),
+        (Some('?'), Some(_)) => wildcard(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) => p == t && wildcard(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+// One entry of an imported rule database. The rules only apply once one of
+// the `detect` targets exists, an entry without any applies everywhere.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub detect: Vec<Rule>,
+    pub rules: Vec<Rule>,
+}
+
+// The imported entries, and by reason the entries something was left out of
+#[derive(Debug, Default)]
+pub struct Import {
+    pub entries: Vec<Entry>,
+    pub ignored: BTreeMap<String, Vec<String>>,
+}
+
+impl Import {
+    fn ignore(&mut self, reason: &str, entry: &str) {
+        let names: &mut Vec<String> = self.ignored.entry(reason.to_string()).or_default();
+
+        if !names.iter().any(|n| n == entry) {
+            names.push(entry.to_string());
+        }
+    }
+}
+
+// Every application the rules may need closed, without duplicates
+pub fn rule_apps(rules: &[Rule]) -> Vec<String> {
+    let mut apps: Vec<String> = rules.iter().flat_map(|r| r.apps.iter().cloned()).collect();
+    apps.sort();
+    apps.dedup();
+    apps
+}
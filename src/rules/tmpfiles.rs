@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use std::fs::{read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::rules::{AgeBy, path_matches, Rule};
+
+// Searched in this order, a file hides the ones with the same name further down
+const CONFIG_DIRS: [&str; 4] = ["etc/tmpfiles.d", "run/tmpfiles.d", "usr/local/lib/tmpfiles.d", "usr/lib/tmpfiles.d"];
+
+// What tmpfiles.d says about a path: age out what it holds, declare it
+// without any age, exclude it and its content from every clean (`x`) or keep
+// the directory itself while its content is aged (`X`)
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Age(Duration, AgeBy),
+    Declare,
+    Exclude,
+    KeepDir,
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub action: Action,
+}
+
+// Every configuration file, by name, from the directory with the highest precedence
+fn config_files(root: &Path) -> Vec<PathBuf> {
+    let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+
+    for dir in CONFIG_DIRS.iter() {
+        let entries = match read_dir(root.join(dir)) {
+            Ok(e) => e,
+            _ => continue,
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name: String = entry.file_name().to_string_lossy().to_string();
+
+            if name.ends_with(".conf") && !files.contains_key(&name) {
+                files.insert(name, entry.path());
+            }
+        }
+    }
+
+    files.into_values().collect()
+}
+
+// Fields are separated by spaces, a field may be quoted to hold some
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let mut field: String = String::new();
+
+        if c == '"' {
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+
+                field.push(c);
+            }
+        } else {
+            field.push(c);
+
+            while let Some(c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+
+                field.push(*c);
+                chars.next();
+            }
+        }
+
+        fields.push(field);
+    }
+
+    fields
+}
+
+fn unit_seconds(unit: &str) -> Option<f64> {
+    Some(match unit {
+        "us" | "usec" => 0.000_001,
+        "ms" | "msec" => 0.001,
+        "" | "s" | "sec" | "second" | "seconds" => 1.0,
+        "m" | "min" | "minute" | "minutes" => 60.0,
+        "h" | "hr" | "hour" | "hours" => 3600.0,
+        "d" | "day" | "days" => 86_400.0,
+        "w" | "week" | "weeks" => 604_800.0,
+        "M" | "month" | "months" => 2_629_800.0,
+        "y" | "year" | "years" => 31_557_600.0,
+        _ => return None,
+    })
+}
+
+// `1d12h`, `30min`, `10d` or plain seconds, optionally after age-by letters
+// and a colon: `m:10d` only looks at the modification time of files
+fn parse_age(age: &str) -> Option<(Duration, AgeBy)> {
+    let (age_by, age): (AgeBy, &str) = match age.split_once(':') {
+        Some((letters, age)) => (AgeBy::parse(letters)?, age),
+        None => (AgeBy::default(), age),
+    };
+
+    let mut seconds: f64 = 0.0;
+    let mut rest: &str = age;
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let digits: usize = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let unit: usize = rest[digits..].find(|c: char| c.is_ascii_digit()).map(|u| u + digits).unwrap_or(rest.len());
+
+        let value: f64 = rest[..digits].parse().ok()?;
+        seconds += value * unit_seconds(rest[digits..unit].trim())?;
+        rest = &rest[unit..];
+    }
+
+    Some((Duration::try_from_secs_f64(seconds).ok()?, age_by))
+}
+
+// Specifiers depend on the machine and the boot, an exclusion covers every
+// value they could take and anything else using them is left out
+fn expand_specifiers(path: &str, exclusion: bool) -> Option<String> {
+    let mut expanded: String = String::new();
+    let mut chars = path.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, exclusion) {
+            ('%', _) if chars.as_str().starts_with('%') => {
+                chars.next();
+                expanded.push('%');
+            }
+            ('%', true) => {
+                chars.next();
+                expanded.push('*');
+            }
+            ('%', false) => return None,
+            _ => expanded.push(c),
+        }
+    }
+
+    Some(expanded)
+}
+
+// Only the lines deciding what may be aged out, everything else creates or
+// adjusts files and is none of the cleaner's business
+fn parse_line(line: &str) -> Option<Entry> {
+    let fields: Vec<String> = split_fields(line);
+    let kind: &str = fields.first()?;
+    let path: &str = fields.get(1)?;
+
+    if kind.starts_with('#') || !path.starts_with('/') {
+        return None;
+    }
+
+    let (letter, modifiers) = kind.split_at(1);
+
+    match letter {
+        "x" => Some(Entry { path: PathBuf::from(expand_specifiers(path, true)?), action: Action::Exclude }),
+        "X" => Some(Entry { path: PathBuf::from(expand_specifiers(path, true)?), action: Action::KeepDir }),
+        "d" | "D" | "e" | "v" | "q" | "Q" => {
+            let path: PathBuf = PathBuf::from(expand_specifiers(path, false)?);
+
+            // Boot-only lines and ages sparing the first level are not applied
+            // by a regular clean, the directory is only declared
+            let age: Option<(Duration, AgeBy)> = match fields.get(5).map(|a| a.as_str()) {
+                Some(a) if !modifiers.contains('!') && !a.starts_with('~') && a != "-" => parse_age(a),
+                _ => None,
+            };
+
+            Some(Entry {
+                path,
+                action: match age {
+                    Some((a, by)) => Action::Age(a, by),
+                    None => Action::Declare,
+                },
+            })
+        }
+        _ => None,
+    }
+}
+
+fn is_directory_line(entry: &Entry) -> bool {
+    matches!(entry.action, Action::Age(..) | Action::Declare)
+}
+
+// Files are read in name order, the first directory line about a path wins.
+// A path excluded with `x`, or below one, is never aged.
+pub fn load(root: &Path) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+
+    for file in config_files(root) {
+        let content: String = match read_to_string(&file) {
+            Ok(c) => c,
+            _ => continue,
+        };
+
+        for entry in content.lines().filter_map(parse_line) {
+            let declared: bool = entries.iter().any(|e| e.path == entry.path && is_directory_line(e));
+
+            if !is_directory_line(&entry) || !declared {
+                entries.push(entry);
+            }
+        }
+    }
+
+    let excluded: Vec<PathBuf> = entries.iter()
+        .filter(|e| e.action == Action::Exclude)
+        .map(|e| e.path.clone())
+        .collect();
+
+    entries.into_iter()
+        .map(|e| match e.action {
+            Action::Age(..) if e.path.ancestors().any(|a| excluded.iter().any(|x| path_matches(x, a))) => {
+                Entry { action: Action::Declare, ..e }
+            }
+            _ => e,
+        })
+        .collect()
+}
+
+// Paths are taken relative to the `root` variable
+fn relative(path: &Path) -> String {
+    path.to_string_lossy().trim_start_matches('/').to_string()
+}
+
+// Directories kept with `X` below an aged path stay, their content is aged
+pub fn age_rules(entries: &[Entry]) -> Vec<Rule> {
+    entries.iter()
+        .filter_map(|e| match e.action {
+            Action::Age(age, age_by) => {
+                let kept: Vec<PathBuf> = entries.iter()
+                    .filter(|k| k.action == Action::KeepDir)
+                    .filter_map(|k| k.path.strip_prefix(&e.path).ok())
+                    .filter(|k| k.components().next().is_some())
+                    .map(|k| k.to_path_buf())
+                    .collect();
+
+                Some(Rule::new_clear("root", &relative(&e.path)).aged(age).aged_by(age_by).keep_dirs(kept))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Declared directories without an age are kept like the excluded ones, the
+// system manages them on its own
+pub fn kept_rules(entries: &[Entry]) -> Vec<Rule> {
+    entries.iter()
+        .filter(|e| matches!(e.action, Action::Declare | Action::Exclude))
+        .map(|e| Rule::new_clear("root", &relative(&e.path)))
+        .collect()
+}
+
+// tmpfiles.d has a say on the path, whether it ages it or not
+pub fn declares(entries: &[Entry], path: &Path) -> bool {
+    entries.iter().any(|e| path_matches(&e.path, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use crate::rules::{AgeBy, Stamps};
+    use crate::testing::TempRoot;
+
+    use super::{Action, age_rules, Entry, kept_rules, load, parse_age, parse_line};
+
+    const DAY: u64 = 86_400;
+
+    fn action(entries: &[Entry], path: &str) -> Vec<Action> {
+        entries.iter().filter(|e| e.path == Path::new(path)).map(|e| e.action.clone()).collect()
+    }
+
+    #[test]
+    fn ages() {
+        assert_eq!(parse_age("10d").unwrap().0, Duration::from_secs(10 * DAY));
+        assert_eq!(parse_age("1d12h").unwrap().0, Duration::from_secs(DAY + 12 * 3600));
+        assert_eq!(parse_age("30min").unwrap().0, Duration::from_secs(1800));
+        assert_eq!(parse_age("90").unwrap().0, Duration::from_secs(90));
+        assert_eq!(parse_age("1w").unwrap().0, Duration::from_secs(7 * DAY));
+        assert!(parse_age("").is_none());
+        assert!(parse_age("10 parsecs").is_none());
+    }
+
+    #[test]
+    fn age_by_letters() {
+        let (age, age_by) = parse_age("mM:5d").unwrap();
+        let modified = Stamps { modified: true, ..Stamps::default() };
+
+        assert_eq!(age, Duration::from_secs(5 * DAY));
+        assert_eq!(age_by, AgeBy { files: modified, dirs: modified });
+
+        let (_, age_by) = parse_age("ab:1d").unwrap();
+        assert_eq!(age_by.files, Stamps { accessed: true, created: true, ..Stamps::default() });
+        assert_eq!(age_by.dirs, Stamps::default());
+
+        // Without letters every time stamp counts but the access time of a
+        // directory, which the scan moves
+        let (_, age_by) = parse_age("1d").unwrap();
+        assert_eq!(age_by, AgeBy::default());
+        assert!(age_by.files.accessed && !age_by.dirs.accessed && age_by.dirs.modified);
+
+        assert!(parse_age("z:1d").is_none());
+        assert!(parse_age(":1d").is_none());
+    }
+
+    #[test]
+    fn lines() {
+        let aged: Entry = parse_line("q /tmp 1777 root root 10d").unwrap();
+        assert_eq!(aged.action, Action::Age(Duration::from_secs(10 * DAY), AgeBy::default()));
+
+        // Boot-only lines and ages sparing the first level are only declared
+        assert_eq!(parse_line("d! /run/boot 0755 - - 1d").unwrap().action, Action::Declare);
+        assert_eq!(parse_line("d /var/cache/x 0755 - - ~1d").unwrap().action, Action::Declare);
+        assert_eq!(parse_line("d /var/lib/x 0755 - - -").unwrap().action, Action::Declare);
+
+        assert_eq!(parse_line("x /tmp/systemd-private-%b-*").unwrap().path, PathBuf::from("/tmp/systemd-private-*-*"));
+        assert_eq!(parse_line("X /var/tmp/keep").unwrap().action, Action::KeepDir);
+
+        // Other specifiers depend on the boot, such a directory is left out
+        assert!(parse_line("d /run/user/%U 0700 - - 1d").is_none());
+        assert!(parse_line("f /etc/motd 0644 - - -").is_none());
+        assert!(parse_line("# d /tmp - - - 1d").is_none());
+    }
+
+    #[test]
+    fn precedence() {
+        let root: TempRoot = TempRoot::new();
+
+        // /etc hides the file of the same name in /usr/lib, files are read in
+        // name order and the first directory line about a path wins
+        root.file("usr/lib/tmpfiles.d/tmp.conf", "q /tmp 1777 root root 10d\n");
+        root.file("etc/tmpfiles.d/tmp.conf", "q /tmp 1777 root root 2d\n");
+        root.file("usr/lib/tmpfiles.d/a.conf", "d /var/cache/a 0755 - - 5d\n");
+        root.file("run/tmpfiles.d/b.conf", "d /var/cache/a 0755 - - 1d\nd /var/cache/b 0755 - - -\n");
+        root.file("usr/local/lib/tmpfiles.d/c.conf", "d /var/cache/c 0755 - - 3d\n");
+        root.file("etc/tmpfiles.d/ignored.txt", "d /var/cache/d 0755 - - 3d\n");
+
+        let entries: Vec<Entry> = load(root.path());
+
+        assert_eq!(action(&entries, "/tmp"), vec![Action::Age(Duration::from_secs(2 * DAY), AgeBy::default())]);
+        assert_eq!(action(&entries, "/var/cache/a"), vec![Action::Age(Duration::from_secs(5 * DAY), AgeBy::default())]);
+        assert_eq!(action(&entries, "/var/cache/b"), vec![Action::Declare]);
+        assert_eq!(action(&entries, "/var/cache/c"), vec![Action::Age(Duration::from_secs(3 * DAY), AgeBy::default())]);
+        assert!(action(&entries, "/var/cache/d").is_empty());
+    }
+
+    #[test]
+    fn exclusions() {
+        let root: TempRoot = TempRoot::new();
+
+        root.file("usr/lib/tmpfiles.d/tmp.conf", "\
+            q /var/tmp 1777 root root 30d\n\
+            d /var/tmp/build 0755 - - 1d\n\
+            d /var/tmp/cache 0755 - - 1d\n\
+            x /var/tmp/build\n\
+            X /var/tmp/kept-*\n\
+            x /var/tmp/cache/*\n");
+
+        let entries: Vec<Entry> = load(root.path());
+
+        // `x` keeps the whole tree: an age at or below it is only declared
+        assert_eq!(action(&entries, "/var/tmp/build"), vec![Action::Declare, Action::Exclude]);
+        assert_eq!(action(&entries, "/var/tmp/cache"), vec![Action::Age(Duration::from_secs(DAY), AgeBy::default())]);
+
+        let rules = age_rules(&entries);
+        let var_tmp = rules.iter().find(|r| r.path == Path::new("var/tmp")).unwrap();
+
+        // `X` only keeps the directory, its content is aged with the rest
+        assert_eq!(var_tmp.keep_dirs, vec![PathBuf::from("kept-*")]);
+        assert!(var_tmp.keep.is_empty());
+        assert!(!rules.iter().any(|r| r.path == Path::new("var/tmp/build")));
+
+        let kept: Vec<PathBuf> = kept_rules(&entries).into_iter().map(|r| r.path).collect();
+
+        assert!(kept.contains(&PathBuf::from("var/tmp/build")));
+        assert!(kept.contains(&PathBuf::from("var/tmp/cache/*")));
+        assert!(!kept.iter().any(|k| k.starts_with("var/tmp/kept-*")));
+    }
+}
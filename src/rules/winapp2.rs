@@ -0,0 +1,239 @@
+use std::ffi::OsStr;
+use std::fs::read;
+use std::io::Result;
+use std::path::{Component, MAIN_SEPARATOR, Path, PathBuf};
+
+use crate::rules::{Entry, Import, name_matches, Rule};
+
+// winapp2.ini variables, as an engine variable and the path below it
+const VARIABLES: [(&str, &str, &str); 14] = [
+    ("AppData", "users", "AppData\\Roaming"),
+    ("LocalAppData", "users", "AppData\\Local"),
+    ("LocalLowAppData", "users", "AppData\\LocalLow"),
+    ("UserProfile", "users", ""),
+    ("Documents", "users", "Documents"),
+    ("Pictures", "users", "Pictures"),
+    ("Music", "users", "Music"),
+    ("Video", "users", "Videos"),
+    ("Desktop", "users", "Desktop"),
+    ("CommonAppData", "all_user_profile", ""),
+    ("WinDir", "system_root", ""),
+    ("SystemRoot", "system_root", ""),
+    ("SystemDrive", "system_drive", "\\"),
+    ("ProgramFiles", "program_files", ""),
+];
+
+// A path of the database, `%Variable%\sub\dir`
+struct Target {
+    var: String,
+    path: PathBuf,
+}
+
+fn target(value: &str) -> Option<Target> {
+    let rest: &str = value.trim().strip_prefix('%')?;
+    let end: usize = rest.find('%')?;
+    let (var, path) = VARIABLES.iter().find(|v| v.0.eq_ignore_ascii_case(&rest[..end])).map(|v| (v.1, v.2))?;
+    let path: String = format!("{}\\{}", path, &rest[end + 1..]);
+
+    Some(Target { var: var.to_string(), path: windows_path(&path) })
+}
+
+// The database always separates with `\`, whatever the platform
+fn windows_path(path: &str) -> PathBuf {
+    let mut joined: PathBuf = PathBuf::new();
+
+    if path.starts_with('\\') {
+        joined.push(MAIN_SEPARATOR.to_string());
+    }
+
+    for part in path.split('\\').filter(|p| !p.is_empty()) {
+        joined.push(part);
+    }
+
+    joined
+}
+
+// Case is not significant in Windows paths. A wildcard of the root matches
+// any directory, what is kept in one is then kept in all of them.
+fn relative(path: &Path, root: &Path) -> Option<PathBuf> {
+    let mut path = path.components();
+
+    for component in root.components() {
+        let pattern: String = component.as_os_str().to_string_lossy().to_lowercase();
+
+        match path.next() {
+            Some(c) if name_matches(&pattern, OsStr::new(&c.as_os_str().to_string_lossy().to_lowercase())) => continue,
+            _ => return None,
+        }
+    }
+
+    Some(path.filter(|c| matches!(c, Component::Normal(_))).collect())
+}
+
+fn describe_target(value: &str) -> String {
+    match value.trim().strip_prefix('%').and_then(|r| r.split('%').next()) {
+        Some(var) => format!("%{}%: unknown variable", var),
+        None => "path without a variable".to_string(),
+    }
+}
+
+// `path|pattern;pattern|RECURSE`. Every pattern becomes a rule of its own,
+// `*` removing the folders too when the whole tree goes.
+fn file_key(value: &str, name: &str, import: &mut Import) -> Vec<Rule> {
+    let fields: Vec<&str> = value.split('|').collect();
+
+    let target: Target = match target(fields[0]) {
+        Some(t) => t,
+        None => {
+            import.ignore(&describe_target(fields[0]), name);
+            return Vec::new();
+        }
+    };
+
+    let flag: &str = fields.get(2).map(|f| f.trim()).unwrap_or("");
+    let path: String = target.path.to_string_lossy().to_string();
+
+    // REMOVESELF also removes the folder, only the empty folder is left here
+    match flag.to_uppercase().as_str() {
+        "" | "RECURSE" | "REMOVESELF" => {}
+        _ => {
+            import.ignore(&format!("FileKey flag {}: not supported", flag), name);
+            return Vec::new();
+        }
+    }
+
+    fields.get(1).copied().unwrap_or("*").split(';')
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+        .map(|p| if p == "*.*" { "*" } else { p })
+        .map(|p| match (p, flag.to_uppercase().as_str()) {
+            ("*", "REMOVESELF") => Rule::new_clear(&target.var, &path),
+            (_, "") => Rule::new_files(&target.var, &path, p),
+            _ => Rule::new_files(&target.var, &path, p).recursive(),
+        })
+        .collect()
+}
+
+// `FILE|path|pattern` or `PATH|path|pattern`, kept inside every rule of the
+// entry it falls in. A rule excluded as a whole is dropped.
+fn exclude_key(value: &str, name: &str, rules: &mut Vec<Rule>, import: &mut Import) {
+    let fields: Vec<&str> = value.split('|').collect();
+    let kind: String = fields[0].trim().to_uppercase();
+
+    if kind != "FILE" && kind != "PATH" {
+        import.ignore(&format!("ExcludeKey {}: not supported", kind), name);
+        return;
+    }
+
+    let target: Target = match fields.get(1).and_then(|f| target(f)) {
+        Some(t) => t,
+        None => {
+            import.ignore(&describe_target(fields.get(1).copied().unwrap_or("")), name);
+            return;
+        }
+    };
+
+    let pattern: Option<&str> = fields.get(2).map(|p| p.trim()).filter(|p| !p.is_empty() && *p != "*.*" && *p != "*");
+
+    rules.retain_mut(|rule| {
+        let inside: PathBuf = match (rule.var == target.var, relative(&target.path, &rule.path)) {
+            (true, Some(r)) => r,
+            _ => return true,
+        };
+
+        match pattern {
+            Some(p) => rule.keep.push(inside.join(p)),
+            None if inside.as_os_str().is_empty() => return false,
+            None => rule.keep.push(inside),
+        }
+
+        true
+    });
+}
+
+fn section(name: &str, keys: &[(String, String)], import: &mut Import) {
+    let mut entry = Entry { name: name.to_string(), detect: Vec::new(), rules: Vec::new() };
+    let mut excludes: Vec<&str> = Vec::new();
+    let mut undetectable: Vec<&str> = Vec::new();
+
+    for (key, value) in keys {
+        // Keys of one kind are numbered, FileKey1, FileKey2 ...
+        let directive: &str = key.trim_end_matches(|c: char| c.is_ascii_digit());
+
+        match directive {
+            "LangSecRef" | "Section" => {}
+            "FileKey" => entry.rules.extend(file_key(value, name, import)),
+            "ExcludeKey" => excludes.push(value),
+            "DetectFile" => match target(value) {
+                Some(t) => entry.detect.push(Rule::new_clear(&t.var, &t.path.to_string_lossy())),
+                None => import.ignore(&describe_target(value), name),
+            },
+            "Detect" | "SpecialDetect" => undetectable.push(directive),
+            "DetectOS" => import.ignore("DetectOS: not checked", name),
+            "RegKey" => import.ignore("RegKey: registry values are not cleaned", name),
+            "Warning" => {
+                import.ignore("Warning: entries asking for confirmation are skipped", name);
+                return;
+            }
+            "Default" if value.trim().eq_ignore_ascii_case("false") => {
+                import.ignore("Default=False: entries disabled by default are skipped", name);
+                return;
+            }
+            "Default" => {}
+            _ => import.ignore(&format!("{}: unknown directive", directive), name),
+        }
+    }
+
+    // Whether the application is installed is unknown, better leave it alone
+    if entry.detect.is_empty() && !undetectable.is_empty() {
+        import.ignore(&format!("{}: only registry detection, entry skipped", undetectable[0]), name);
+        return;
+    }
+
+    for value in excludes {
+        exclude_key(value, name, &mut entry.rules, import);
+    }
+
+    if !entry.rules.is_empty() {
+        import.entries.push(entry);
+    }
+}
+
+// `[Name *]` sections of `Key=Value` lines, `;` starts a comment
+pub fn parse(content: &str) -> Import {
+    let mut import: Import = Import::default();
+    let mut current: Option<(String, Vec<(String, String)>)> = None;
+
+    for line in content.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if let Some((name, keys)) = current.take() {
+                section(&name, &keys, &mut import);
+            }
+
+            current = Some((name.trim_end_matches(" *").trim().to_string(), Vec::new()));
+            continue;
+        }
+
+        if let (Some((_, keys)), Some((key, value))) = (current.as_mut(), line.split_once('=')) {
+            keys.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    if let Some((name, keys)) = current {
+        section(&name, &keys, &mut import);
+    }
+
+    import
+}
+
+// The file is usually UTF-8, a BOM or a stray Windows-1252 byte is tolerated
+pub fn load(path: &Path) -> Result<Import> {
+    let bytes: Vec<u8> = read(path)?;
+    let content: String = String::from_utf8_lossy(&bytes).trim_start_matches('\u{feff}').to_string();
+
+    Ok(parse(&content))
+}
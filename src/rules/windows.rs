@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::rules::Rule;
+
+pub fn walk_all_rules(_system_vars: &HashMap<&'static str, PathBuf>) -> Vec<Rule> {
+    vec![
+        Rule::new_clear("system_root", "Temp"),
+        Rule::new_clear("system_root", "Prefetch"),
+        Rule::new_clear("system_root", "SoftwareDistribution\\Download"),
+        Rule::new_clear("system_root", "SoftwareDistribution\\DataStore\\Logs"),
+        Rule::new_clear("system_root", "SoftwareDistribution\\DataStore.bak\\Logs"),
+        Rule::new_clear("system_root", "Logs\\waasmedic"),
+        Rule::new_clear("system_root", "Logs\\WindowsUpdate"),
+        Rule::new_clear("all_user_profile", "Temp"),
+        Rule::new_clear("users", "AppData\\Local\\Temp"),
+        Rule::new_clear("users", "AppData\\Local\\Microsoft\\Windows\\Temporary Internet Files").shred().apps(&["iexplore.exe"]),
+        Rule::new_clear("users", "AppData\\Roaming\\Macromedia\\Flash Player\\#SharedObjects").shred().apps(&["iexplore.exe", "firefox.exe", "opera.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Microsoft\\Windows\\WebCache").shred().apps(&["iexplore.exe"]),
+        // Rule::new_clear("users", "AppData\\Local\\Microsoft\\Windows\\AppCache"),
+        // Rule::new_clear("users", "AppData\\Local\\Microsoft\\Windows\\Caches"),
+        Rule::new_clear("users", "AppData\\Local\\Microsoft\\Windows\\INetCache\\IE").shred().apps(&["iexplore.exe"]),
+        Rule::new_clear("system_root", "ie7updates"),
+        Rule::new_clear("system_root", "ie8updates"),
+        Rule::new_clear("users", "AppData\\Local\\Google\\Chrome\\User Data\\Default\\Cache").shred().apps(&["chrome.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Google\\Chrome\\User Data\\Default\\File System").shred().apps(&["chrome.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Google\\Chrome\\User Data\\Default\\Local Storage").shred().apps(&["chrome.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\BraveSoftware\\Brave-Browser\\User Data\\Default\\Cache").shred().apps(&["brave.exe"]),
+        Rule::new_clear("users", "AppData\\LocalLow\\Sun\\Java\\Deployment\\cache").apps(&["java.exe", "javaw.exe", "javaws.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Opera Software\\Opera Stable\\Cache").shred().apps(&["opera.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Packages\\Microsoft.MicrosoftEdge_8wekyb3d8bbwe\\AC\\MicrosoftEdge\\Cache").shred().apps(&["MicrosoftEdge.exe", "MicrosoftEdgeCP.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Yandex\\YandexBrowser\\Default\\Cache").shred().apps(&["browser.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Yandex\\YandexBrowser\\User Data\\Default\\Cache").shred().apps(&["browser.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Chromium\\User Data\\Default\\Cache").shred().apps(&["chrome.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Chromium\\User Data\\Default\\Application Cache").shred().apps(&["chrome.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Comodo\\Dragon\\User Data\\Default\\Cache").shred().apps(&["dragon.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Baidu\\User Data\\Cache").shred().apps(&["baidubrowser.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Vivaldi\\User Data\\Default\\Application Cache").shred().apps(&["vivaldi.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Vivaldi\\User Data\\Default\\Cache").shred().apps(&["vivaldi.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Vivaldi\\User Data\\Default\\GPUCache").apps(&["vivaldi.exe"]),
+        Rule::new_clear("users", "AppData\\Local\\Vivaldi\\User Data\\Default\\Media Cache").shred().apps(&["vivaldi.exe"]),
+        Rule::new_clear("system_drive", "\\Config.Msi"),
+        // TEMP moved out of AppData by the user
+        Rule::new_clear("user_temp", ""),
+    ]
+}
+
+// Nothing on Windows declares paths the cleaner must leave alone
+pub fn kept_rules(_system_vars: &HashMap<&'static str, PathBuf>) -> Vec<Rule> {
+    Vec::new()
+}
+
+pub fn glob_file_rules() -> Vec<Rule> {
+    vec![
+        Rule::new_extension("system_drive", "tmp"),
+        Rule::new_extension("system_root", "tmp"),
+        Rule::new_extension("sys32", "tmp"),
+    ]
+}
+
+// Directories holding one sub-directory per browser profile
+pub fn profile_dirs() -> Vec<[&'static str; 2]> {
+    vec![
+        ["firefox_profil", "AppData\\Local\\Mozilla\\Firefox\\Profiles"],
+        ["waterfox_profil", "AppData\\Local\\Mozilla\\Waterfox\\Profiles"],
+        ["seamonkey_profil", "AppData\\Local\\Mozilla\\SeaMonkey\\Profiles"],
+        ["palemoon_profil", "AppData\\Local\\Mozilla\\Pale Moon\\Profiles"],
+        ["icedragon_profil", "AppData\\Local\\Comodo\\IceDragon\\Profiles"],
+    ]
+}
+
+pub fn profile_rules() -> Vec<Rule> {
+    vec![
+        Rule::new_clear("firefox_profil", "cache2").shred().apps(&["firefox.exe"]),
+        Rule::new_clear("firefox_profil", "OfflineCache").shred().apps(&["firefox.exe"]),
+        Rule::new_clear("firefox_profil", "jumpListCache").apps(&["firefox.exe"]),
+        Rule::new_clear("firefox_profil", "startupCache").apps(&["firefox.exe"]),
+        Rule::new_clear("waterfox_profil", "cache2").shred().apps(&["waterfox.exe"]),
+        Rule::new_clear("waterfox_profil", "OfflineCache").shred().apps(&["waterfox.exe"]),
+        Rule::new_clear("waterfox_profil", "jumpListCache").apps(&["waterfox.exe"]),
+        Rule::new_clear("waterfox_profil", "startupCache").apps(&["waterfox.exe"]),
+        Rule::new_clear("seamonkey_profil", "").shred().apps(&["seamonkey.exe"]),
+        Rule::new_clear("palemoon_profil", "").shred().apps(&["palemoon.exe"]),
+        Rule::new_clear("icedragon_profil", "Cache").shred().apps(&["icedragon.exe"]),
+        Rule::new_clear("icedragon_profil", "OfflineCache").shred().apps(&["icedragon.exe"]),
+        Rule::new_clear("icedragon_profil", "mozilla-media-cache").shred().apps(&["icedragon.exe"]),
+        Rule::new_clear("icedragon_profil", "startupCache").apps(&["icedragon.exe"]),
+        Rule::new_clear("icedragon_profil", "jumpListCache").apps(&["icedragon.exe"]),
+    ]
+}
+
+pub fn windows_old_rules() -> Vec<Rule> {
+    vec![
+        Rule::new_clear("system_drive", "\\Windows.old"),
+        Rule::new_clear("system_drive", "\\Windows.old.000"),
+        Rule::new_clear("system_drive", "\\Windows.old.001"),
+        Rule::new_clear("system_drive", "\\Windows.old.002"),
+        Rule::new_clear("system_drive", "\\Windows.old.003"),
+        Rule::new_clear("system_drive", "\\Windows.old.004"),
+        Rule::new_clear("system_drive", "\\Windows.old.005"),
+        Rule::new_clear("system_drive", "\\$WINDOWS.~BT"),
+        Rule::new_clear("system_drive", "\\$Windows.~WS"),
+    ]
+}
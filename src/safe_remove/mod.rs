@@ -0,0 +1,203 @@
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+
+use crate::shred::Shred;
+
+#[cfg(unix)]
+pub use self::unix::fingerprint;
+#[cfg(unix)]
+use self::unix::Dir;
+#[cfg(windows)]
+pub use self::windows::fingerprint;
+#[cfg(windows)]
+use self::windows::Dir;
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Kind {
+    File,
+    Dir,
+    Link,
+}
+
+// Identity (device and inode, or volume serial and file id), size and
+// modification time of a file, taken at scan time and compared again just
+// before the file is deleted
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Fingerprint {
+    pub dev: u64,
+    pub id: u64,
+    pub size: u64,
+    pub modified: i64,
+}
+
+// What a quarantined file gets back when it is restored: permission bits and
+// uid on Unix, file attributes and SID on Windows
+#[derive(PartialEq, Debug, Clone)]
+pub struct Attributes {
+    pub mode: u32,
+    pub owner: String,
+}
+
+pub enum Disposal {
+    Delete,
+    Move(PathBuf),
+    Shred(Shred),
+}
+
+// How a moved file reached its destination
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Moved {
+    Renamed,
+    // The destination is on another volume, the file was copied and synced
+    // there before it was removed
+    Copied,
+}
+
+// Decides what happens to each file met during a removal
+pub trait Policy {
+    // Files are only touched when their current fingerprint is accepted
+    fn accept(&mut self, path: &Path, current: &Fingerprint) -> bool;
+
+    fn disposal(&mut self, _path: &Path, _current: &Fingerprint) -> Disposal {
+        Disposal::Delete
+    }
+
+    fn disposed(&mut self, _path: &Path, _disposal: &Disposal, _current: &Fingerprint) {}
+
+    fn link_removed(&mut self, _path: &Path) {}
+
+    fn copied(&mut self, _path: &Path) {}
+
+    // Directories refused here are left as they are, content included
+    fn enter(&mut self, _path: &Path) -> bool {
+        true
+    }
+}
+
+// Every directory between the root and the removed entry is opened relative to
+// the handle of its parent without following links. Swapping a directory for a
+// link in the middle of a clean makes the open fail instead of escaping the root.
+pub fn remove_contained(root: &Path, path: &Path, policy: &mut dyn Policy) -> Result<()> {
+    let relative: &Path = match path.strip_prefix(root) {
+        Ok(r) => r,
+        _ => return Err(Error::new(ErrorKind::InvalidInput, "path outside of the component root")),
+    };
+
+    let mut names: Vec<&OsStr> = Vec::new();
+
+    for c in relative.components() {
+        match c {
+            Component::Normal(n) => names.push(n),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "unexpected path component")),
+        }
+    }
+
+    // Removing the root itself (min_depth 0), start from its parent
+    if names.is_empty() {
+        let (parent, name) = match (root.parent(), root.file_name()) {
+            (Some(p), Some(n)) => (p, n),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "refusing to remove a volume root")),
+        };
+
+        let dir: Dir = Dir::open(parent)?;
+
+        return remove_entry(&dir, name, path.to_path_buf(), policy);
+    }
+
+    let leaf: &OsStr = names.pop().unwrap();
+    let mut dir: Dir = Dir::open(root)?;
+
+    for n in names {
+        dir = dir.open_dir(n)?;
+    }
+
+    remove_entry(&dir, leaf, path.to_path_buf(), policy)
+}
+
+// The way back from the quarantine, the same walk as a removal: directories
+// between the root and the entry are opened, or created when missing, relative
+// to the handle of their parent without following links. The file goes in
+// under its name, never over an existing entry, with its attributes set.
+pub fn restore_contained(root: &Path, path: &Path, stored: &Path, attributes: Option<&Attributes>) -> Result<()> {
+    let relative: &Path = match path.strip_prefix(root) {
+        Ok(r) => r,
+        _ => return Err(Error::new(ErrorKind::InvalidInput, "path outside of the component root")),
+    };
+
+    let mut names: Vec<&OsStr> = Vec::new();
+
+    for c in relative.components() {
+        match c {
+            Component::Normal(n) => names.push(n),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, "unexpected path component")),
+        }
+    }
+
+    let leaf: &OsStr = match names.pop() {
+        Some(n) => n,
+        None => return Err(Error::new(ErrorKind::InvalidInput, "refusing to replace the component root")),
+    };
+
+    // Every directory of the way stays open until the file is in place
+    let mut dirs: Vec<Dir> = vec![Dir::open(root)?];
+
+    for n in names {
+        let next: Dir = dirs[dirs.len() - 1].create_dir(n, attributes)?;
+        dirs.push(next);
+    }
+
+    dirs[dirs.len() - 1].restore(leaf, stored, attributes)
+}
+
+fn remove_entry(dir: &Dir, name: &OsStr, path: PathBuf, policy: &mut dyn Policy) -> Result<()> {
+    match dir.kind(name)? {
+        Kind::Link => {
+            dir.remove(name, Kind::Link, None)?;
+            policy.link_removed(&path);
+        }
+        Kind::File => {
+            let current: Fingerprint = dir.fingerprint(name)?;
+
+            if !policy.accept(&path, &current) {
+                return Err(Error::other("file changed since the scan"));
+            }
+
+            let disposal: Disposal = policy.disposal(&path, &current);
+
+            match disposal {
+                Disposal::Delete => dir.remove(name, Kind::File, Some(&current))?,
+                Disposal::Move(ref destination) => {
+                    if dir.move_to(name, &current, destination)? == Moved::Copied {
+                        policy.copied(&path);
+                    }
+                }
+                Disposal::Shred(ref shred) => dir.shred(name, &current, shred)?,
+            }
+
+            policy.disposed(&path, &disposal, &current);
+        }
+        Kind::Dir => {
+            if !policy.enter(&path) {
+                return Err(Error::other("directory excluded from the removal"));
+            }
+
+            {
+                let child: Dir = dir.open_dir(name)?;
+
+                for n in child.names()? {
+                    let _ = remove_entry(&child, &n, path.join(&n), policy);
+                }
+            }
+
+            dir.remove(name, Kind::Dir, None)?;
+        }
+    }
+
+    Ok(())
+}
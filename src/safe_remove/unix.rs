@@ -0,0 +1,375 @@
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::io::{copy, Error, ErrorKind, Result};
+use std::fs::{File, Metadata, OpenOptions, remove_file, symlink_metadata};
+use std::mem::zeroed;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+use libc::{
+    AT_FDCWD, AT_REMOVEDIR, AT_SYMLINK_NOFOLLOW, c_int, close, closedir, dup, fchmod, fchown, fdopendir, fstatat, gid_t,
+    linkat, mkdirat, mode_t, O_CLOEXEC, O_CREAT, O_DIRECTORY, O_EXCL, O_NOFOLLOW, O_NONBLOCK, O_RDONLY, O_WRONLY, open,
+    openat, readdir, renameat, rewinddir, S_IFDIR, S_IFLNK, S_IFMT, stat, uid_t, unlinkat,
+};
+
+use crate::shred::{overwrite, random_name, Shred};
+
+use super::{Attributes, Fingerprint, Kind, Moved};
+
+const OPEN_FLAGS: c_int = O_RDONLY | O_DIRECTORY | O_NOFOLLOW | O_CLOEXEC;
+
+pub struct Dir {
+    fd: c_int,
+}
+
+fn fingerprint_of(metadata: &Metadata) -> Fingerprint {
+    Fingerprint {
+        dev: metadata.dev(),
+        id: metadata.ino(),
+        size: metadata.size(),
+        modified: metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec(),
+    }
+}
+
+pub fn fingerprint(path: &Path) -> Result<Fingerprint> {
+    Ok(fingerprint_of(&symlink_metadata(path)?))
+}
+
+fn to_cstring(name: &OsStr) -> Result<CString> {
+    CString::new(name.as_bytes())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "path contains a nul byte"))
+}
+
+fn uid(attributes: &Attributes) -> Result<uid_t> {
+    attributes.owner.parse().map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} is not a uid", attributes.owner)))
+}
+
+// The group is left as it is
+fn set_owner(fd: c_int, uid: uid_t) -> Result<()> {
+    if unsafe { fchown(fd, uid, gid_t::MAX) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn set_attributes(file: &File, attributes: &Attributes) -> Result<()> {
+    set_owner(file.as_raw_fd(), uid(attributes)?)?;
+
+    if unsafe { fchmod(file.as_raw_fd(), (attributes.mode & 0o7777) as mode_t) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+impl Dir {
+    pub fn open(path: &Path) -> Result<Dir> {
+        let c_path: CString = to_cstring(path.as_os_str())?;
+        let fd: c_int = unsafe { open(c_path.as_ptr(), OPEN_FLAGS) };
+
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(Dir { fd })
+    }
+
+    // Fails with ELOOP or ENOTDIR when the entry has been replaced by a link
+    pub fn open_dir(&self, name: &OsStr) -> Result<Dir> {
+        let c_name: CString = to_cstring(name)?;
+        let fd: c_int = unsafe { openat(self.fd, c_name.as_ptr(), OPEN_FLAGS) };
+
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(Dir { fd })
+    }
+
+    // A directory created here belongs to the owner of the restored file
+    pub fn create_dir(&self, name: &OsStr, attributes: Option<&Attributes>) -> Result<Dir> {
+        let c_name: CString = to_cstring(name)?;
+
+        if unsafe { mkdirat(self.fd, c_name.as_ptr(), 0o755) } != 0 {
+            let error: Error = Error::last_os_error();
+
+            if error.kind() != ErrorKind::AlreadyExists {
+                return Err(error);
+            }
+
+            return self.open_dir(name);
+        }
+
+        let dir: Dir = self.open_dir(name)?;
+
+        if let Some(a) = attributes {
+            set_owner(dir.fd, uid(a)?)?;
+        }
+
+        Ok(dir)
+    }
+
+    pub fn names(&self) -> Result<Vec<OsString>> {
+        let mut names: Vec<OsString> = Vec::new();
+
+        unsafe {
+            let fd: c_int = dup(self.fd);
+
+            if fd < 0 {
+                return Err(Error::last_os_error());
+            }
+
+            let stream = fdopendir(fd);
+
+            if stream.is_null() {
+                let error = Error::last_os_error();
+                close(fd);
+                return Err(error);
+            }
+
+            rewinddir(stream);
+
+            loop {
+                let entry = readdir(stream);
+
+                if entry.is_null() {
+                    break;
+                }
+
+                let name: &[u8] = CStr::from_ptr((*entry).d_name.as_ptr()).to_bytes();
+
+                if name == b"." || name == b".." {
+                    continue;
+                }
+
+                names.push(OsString::from_vec(name.to_vec()));
+            }
+
+            closedir(stream);
+        }
+
+        Ok(names)
+    }
+
+    fn stat(&self, name: &OsStr) -> Result<stat> {
+        let c_name: CString = to_cstring(name)?;
+        let mut st: stat = unsafe { zeroed() };
+
+        let status: c_int = unsafe {
+            fstatat(self.fd, c_name.as_ptr(), &mut st, AT_SYMLINK_NOFOLLOW)
+        };
+
+        if status != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(st)
+    }
+
+    // stat field widths vary between targets
+    #[allow(clippy::unnecessary_cast)]
+    pub fn fingerprint(&self, name: &OsStr) -> Result<Fingerprint> {
+        let st: stat = self.stat(name)?;
+
+        Ok(Fingerprint {
+            dev: st.st_dev as u64,
+            id: st.st_ino as u64,
+            size: st.st_size as u64,
+            modified: st.st_mtime as i64 * 1_000_000_000 + st.st_mtime_nsec as i64,
+        })
+    }
+
+    pub fn kind(&self, name: &OsStr) -> Result<Kind> {
+        let st: stat = self.stat(name)?;
+
+        Ok(match st.st_mode & S_IFMT {
+            S_IFLNK => Kind::Link,
+            S_IFDIR => Kind::Dir,
+            _ => Kind::File,
+        })
+    }
+
+    fn check(&self, name: &OsStr, expected: Option<&Fingerprint>) -> Result<()> {
+        if let Some(expected) = expected {
+            if self.fingerprint(name)? != *expected {
+                return Err(Error::other("file changed since the scan"));
+            }
+        }
+
+        Ok(())
+    }
+
+    // A rename fails with EXDEV when the destination is on another file
+    // system, the file is copied there instead
+    pub fn move_to(&self, name: &OsStr, expected: &Fingerprint, destination: &Path) -> Result<Moved> {
+        self.check(name, Some(expected))?;
+
+        let c_name: CString = to_cstring(name)?;
+        let c_destination: CString = to_cstring(destination.as_os_str())?;
+
+        let status: c_int = unsafe {
+            renameat(self.fd, c_name.as_ptr(), AT_FDCWD, c_destination.as_ptr())
+        };
+
+        if status == 0 {
+            return Ok(Moved::Renamed);
+        }
+
+        let error: Error = Error::last_os_error();
+
+        if error.kind() != ErrorKind::CrossesDevices {
+            return Err(error);
+        }
+
+        self.copy_to(name, expected, destination)?;
+
+        Ok(Moved::Copied)
+    }
+
+    // The content is read through a descriptor checked against the expected
+    // fingerprint and synced to disk before the entry is unlinked. The copy
+    // goes again when any step fails, the entry stays.
+    fn copy_to(&self, name: &OsStr, expected: &Fingerprint, destination: &Path) -> Result<()> {
+        let c_name: CString = to_cstring(name)?;
+        let flags: c_int = O_RDONLY | O_NOFOLLOW | O_NONBLOCK | O_CLOEXEC;
+        let fd: c_int = unsafe { openat(self.fd, c_name.as_ptr(), flags) };
+
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut file: File = unsafe { File::from_raw_fd(fd) };
+        let metadata: Metadata = file.metadata()?;
+
+        if fingerprint_of(&metadata) != *expected {
+            return Err(Error::other("file changed since the scan"));
+        }
+
+        // Never read from fifos, sockets or devices
+        if !metadata.file_type().is_file() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not a regular file"));
+        }
+
+        let mut stored: File = OpenOptions::new().write(true).create_new(true).mode(0o600).open(destination)?;
+
+        let status: Result<()> = copy(&mut file, &mut stored)
+            .and_then(|_| stored.sync_all())
+            .and_then(|_| self.remove(name, Kind::File, Some(expected)));
+
+        if status.is_err() {
+            drop(stored);
+            let _ = remove_file(destination);
+        }
+
+        status
+    }
+
+    // The content is overwritten through a descriptor checked against the
+    // expected fingerprint, the name is then renamed away and unlinked
+    pub fn shred(&self, name: &OsStr, expected: &Fingerprint, shred: &Shred) -> Result<()> {
+        let c_name: CString = to_cstring(name)?;
+        let flags: c_int = O_WRONLY | O_NOFOLLOW | O_NONBLOCK | O_CLOEXEC;
+        let fd: c_int = unsafe { openat(self.fd, c_name.as_ptr(), flags) };
+
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut file: File = unsafe { File::from_raw_fd(fd) };
+        let metadata: Metadata = file.metadata()?;
+
+        if fingerprint_of(&metadata) != *expected {
+            return Err(Error::other("file changed since the scan"));
+        }
+
+        // Never write into fifos, sockets or devices
+        if metadata.file_type().is_file() {
+            overwrite(&mut file, expected.size, shred)?;
+        }
+
+        drop(file);
+
+        let c_random: CString = to_cstring(&random_name())?;
+
+        if unsafe { renameat(self.fd, c_name.as_ptr(), self.fd, c_random.as_ptr()) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        if unsafe { unlinkat(self.fd, c_random.as_ptr(), 0) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    // The stored file gets its attributes while it is still in the store, then
+    // a hard link puts it in place, which fails when the name is taken. Onto
+    // another file system the content goes to a new file instead. Either way
+    // the store only lets go of the file once it is back.
+    pub fn restore(&self, name: &OsStr, stored: &Path, attributes: Option<&Attributes>) -> Result<()> {
+        let mut source: File = OpenOptions::new().read(true).custom_flags(O_NOFOLLOW | O_NONBLOCK).open(stored)?;
+
+        if !source.metadata()?.file_type().is_file() {
+            return Err(Error::new(ErrorKind::InvalidInput, "not a regular file"));
+        }
+
+        if let Some(a) = attributes {
+            set_attributes(&source, a)?;
+        }
+
+        let c_name: CString = to_cstring(name)?;
+        let c_stored: CString = to_cstring(stored.as_os_str())?;
+
+        if unsafe { linkat(AT_FDCWD, c_stored.as_ptr(), self.fd, c_name.as_ptr(), 0) } == 0 {
+            return remove_file(stored);
+        }
+
+        let error: Error = Error::last_os_error();
+
+        if error.kind() != ErrorKind::CrossesDevices {
+            return Err(error);
+        }
+
+        let flags: c_int = O_WRONLY | O_CREAT | O_EXCL | O_NOFOLLOW | O_CLOEXEC;
+        let fd: c_int = unsafe { openat(self.fd, c_name.as_ptr(), flags, 0o600 as c_int) };
+
+        if fd < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut restored: File = unsafe { File::from_raw_fd(fd) };
+
+        let status: Result<()> = copy(&mut source, &mut restored)
+            .and_then(|_| attributes.map_or(Ok(()), |a| set_attributes(&restored, a)))
+            .and_then(|_| restored.sync_all());
+
+        if status.is_err() {
+            drop(restored);
+            unsafe { unlinkat(self.fd, c_name.as_ptr(), 0); }
+            return status;
+        }
+
+        remove_file(stored)
+    }
+
+    pub fn remove(&self, name: &OsStr, kind: Kind, expected: Option<&Fingerprint>) -> Result<()> {
+        self.check(name, expected)?;
+
+        let c_name: CString = to_cstring(name)?;
+        let flags: c_int = if kind == Kind::Dir { AT_REMOVEDIR } else { 0 };
+
+        if unsafe { unlinkat(self.fd, c_name.as_ptr(), flags) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        unsafe { close(self.fd); }
+    }
+}
@@ -0,0 +1,578 @@
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, OpenOptions, remove_file};
+use std::io::{copy, Error, ErrorKind, Result};
+use std::mem::{size_of, zeroed};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::path::{Path, PathBuf};
+use std::ptr::{copy_nonoverlapping, null_mut};
+use std::slice::from_raw_parts;
+
+use winapi::shared::minwindef::{DWORD, LPVOID};
+use winapi::shared::sddl::ConvertStringSidToSidW;
+use winapi::shared::winerror::{
+    ERROR_ACCESS_DENIED, ERROR_ALREADY_EXISTS, ERROR_NO_MORE_FILES, ERROR_NOT_SAME_DEVICE, ERROR_SUCCESS,
+};
+use winapi::um::accctrl::SE_FILE_OBJECT;
+use winapi::um::aclapi::SetSecurityInfo;
+use winapi::um::fileapi::{
+    BY_HANDLE_FILE_INFORMATION, CREATE_NEW, CreateDirectoryW, CreateFileW, FILE_BASIC_INFO, FILE_DISPOSITION_INFO,
+    FILE_ID_BOTH_DIR_INFO, FILE_RENAME_INFO, GetFileInformationByHandle, GetFinalPathNameByHandleW, OPEN_EXISTING,
+    SetFileInformationByHandle,
+};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::minwinbase::{FileBasicInfo, FileDispositionInfo, FileIdBothDirectoryInfo, FileRenameInfo};
+use winapi::um::winbase::{
+    FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT, FILE_ID_DESCRIPTOR, FileIdType,
+    GetFileInformationByHandleEx, LocalFree, OpenFileById,
+};
+use winapi::um::winnt::{
+    DELETE, FILE_ATTRIBUTE_DIRECTORY, GENERIC_READ, GENERIC_WRITE, FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_REPARSE_POINT, FILE_LIST_DIRECTORY,
+    FILE_READ_ATTRIBUTES, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, FILE_WRITE_ATTRIBUTES, HANDLE,
+    OWNER_SECURITY_INFORMATION, PSID, SYNCHRONIZE, WRITE_OWNER,
+};
+
+use crate::shred::{overwrite, random_name, Shred};
+
+use super::{Attributes, Fingerprint, Kind, Moved};
+
+const FLAGS: DWORD = FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT;
+const DIR_ACCESS: DWORD = FILE_LIST_DIRECTORY | FILE_READ_ATTRIBUTES | SYNCHRONIZE;
+
+// Directories are opened without FILE_SHARE_DELETE, they can not be renamed or
+// replaced while a handle is held on them
+const DIR_SHARE: DWORD = FILE_SHARE_READ | FILE_SHARE_WRITE;
+
+struct Entry {
+    name: OsString,
+    id: i64,
+    size: i64,
+    modified: i64,
+    attributes: DWORD,
+}
+
+pub struct Dir {
+    handle: HANDLE,
+}
+
+fn link_error() -> Error {
+    Error::other("directory has been replaced by a reparse point")
+}
+
+fn information_of(handle: HANDLE) -> Result<BY_HANDLE_FILE_INFORMATION> {
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { zeroed() };
+
+    if unsafe { GetFileInformationByHandle(handle, &mut info) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(info)
+}
+
+fn attributes_of(handle: HANDLE) -> Result<DWORD> {
+    Ok(information_of(handle)?.dwFileAttributes)
+}
+
+fn fingerprint_of(handle: HANDLE) -> Result<Fingerprint> {
+    let info: BY_HANDLE_FILE_INFORMATION = information_of(handle)?;
+    let modified: u64 = (info.ftLastWriteTime.dwHighDateTime as u64) << 32
+        | info.ftLastWriteTime.dwLowDateTime as u64;
+
+    Ok(Fingerprint {
+        dev: info.dwVolumeSerialNumber as u64,
+        id: (info.nFileIndexHigh as u64) << 32 | info.nFileIndexLow as u64,
+        size: (info.nFileSizeHigh as u64) << 32 | info.nFileSizeLow as u64,
+        modified: modified as i64,
+    })
+}
+
+pub fn fingerprint(path: &Path) -> Result<Fingerprint> {
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let share: DWORD = FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE;
+
+    let handle: HANDLE = unsafe {
+        CreateFileW(wide.as_ptr(), FILE_READ_ATTRIBUTES, share, null_mut(), OPEN_EXISTING, FLAGS, null_mut())
+    };
+
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(Error::last_os_error());
+    }
+
+    let status: Result<Fingerprint> = fingerprint_of(handle);
+    unsafe { CloseHandle(handle); }
+
+    status
+}
+
+impl Dir {
+    pub fn open(path: &Path) -> Result<Dir> {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+
+        let handle: HANDLE = unsafe {
+            CreateFileW(wide.as_ptr(), DIR_ACCESS, DIR_SHARE, null_mut(), OPEN_EXISTING, FLAGS, null_mut())
+        };
+
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(Error::last_os_error());
+        }
+
+        Dir::checked(handle)
+    }
+
+    fn checked(handle: HANDLE) -> Result<Dir> {
+        let dir = Dir { handle };
+        let attributes: DWORD = attributes_of(dir.handle)?;
+
+        if attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0 || attributes & FILE_ATTRIBUTE_DIRECTORY == 0 {
+            return Err(link_error());
+        }
+
+        Ok(dir)
+    }
+
+    fn entries(&self) -> Result<Vec<Entry>> {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut buffer: Vec<u64> = vec![0; 8192];
+        let buffer_size: DWORD = (buffer.len() * size_of::<u64>()) as DWORD;
+
+        loop {
+            let status = unsafe {
+                GetFileInformationByHandleEx(
+                    self.handle,
+                    FileIdBothDirectoryInfo,
+                    buffer.as_mut_ptr() as LPVOID,
+                    buffer_size,
+                )
+            };
+
+            if status == 0 {
+                let error = Error::last_os_error();
+
+                if error.raw_os_error() == Some(ERROR_NO_MORE_FILES as i32) {
+                    break;
+                }
+
+                return Err(error);
+            }
+
+            let mut offset: usize = 0;
+
+            loop {
+                let info = unsafe {
+                    &*((buffer.as_ptr() as *const u8).add(offset) as *const FILE_ID_BOTH_DIR_INFO)
+                };
+
+                let name: &[u16] = unsafe {
+                    from_raw_parts(info.FileName.as_ptr(), info.FileNameLength as usize / 2)
+                };
+
+                if name != [46] && name != [46, 46] {
+                    entries.push(Entry {
+                        name: OsString::from_wide(name),
+                        id: unsafe { *info.FileId.QuadPart() },
+                        size: unsafe { *info.EndOfFile.QuadPart() },
+                        modified: unsafe { *info.LastWriteTime.QuadPart() },
+                        attributes: info.FileAttributes,
+                    });
+                }
+
+                if info.NextEntryOffset == 0 {
+                    break;
+                }
+
+                offset += info.NextEntryOffset as usize;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn find(&self, name: &OsStr) -> Result<Entry> {
+        let lower: String = name.to_string_lossy().to_lowercase();
+        let mut found: Option<Entry> = None;
+
+        for entry in self.entries()? {
+            if entry.name == name {
+                return Ok(entry);
+            }
+
+            if found.is_none() && entry.name.to_string_lossy().to_lowercase() == lower {
+                found = Some(entry);
+            }
+        }
+
+        found.ok_or_else(|| Error::new(ErrorKind::NotFound, "entry not found"))
+    }
+
+    // The entry is opened by its file id, no name is resolved by the system
+    fn open_by_id(&self, entry: &Entry, access: DWORD, share: DWORD) -> Result<HANDLE> {
+        let mut descriptor: FILE_ID_DESCRIPTOR = unsafe { zeroed() };
+        descriptor.dwSize = size_of::<FILE_ID_DESCRIPTOR>() as DWORD;
+        descriptor.Type = FileIdType;
+        unsafe { *descriptor.u.FileId_mut().QuadPart_mut() = entry.id; }
+
+        let handle: HANDLE = unsafe {
+            OpenFileById(self.handle, &mut descriptor, access, share, null_mut(), FLAGS)
+        };
+
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(handle)
+    }
+
+    pub fn open_dir(&self, name: &OsStr) -> Result<Dir> {
+        let entry: Entry = self.find(name)?;
+
+        if entry.attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+            return Err(link_error());
+        }
+
+        let handle: HANDLE = self.open_by_id(&entry, DIR_ACCESS, DIR_SHARE)?;
+
+        Dir::checked(handle)
+    }
+
+    // What the system resolves the handle to, the caller holds every
+    // directory leading to it so none of them can be swapped meanwhile
+    fn path(&self) -> Result<PathBuf> {
+        let mut buffer: Vec<u16> = vec![0; 32768];
+        let len: DWORD = unsafe { GetFinalPathNameByHandleW(self.handle, buffer.as_mut_ptr(), buffer.len() as DWORD, 0) };
+
+        if len == 0 || len as usize >= buffer.len() {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(PathBuf::from(OsString::from_wide(&buffer[..len as usize])))
+    }
+
+    // Created by its path below this handle, then opened by file id like any
+    // other. A new directory inherits the access rules of its parent.
+    pub fn create_dir(&self, name: &OsStr, _attributes: Option<&Attributes>) -> Result<Dir> {
+        let wide: Vec<u16> = self.path()?.join(name).as_os_str().encode_wide().chain(Some(0)).collect();
+
+        if unsafe { CreateDirectoryW(wide.as_ptr(), null_mut()) } == 0 {
+            let error: Error = Error::last_os_error();
+
+            if error.raw_os_error() != Some(ERROR_ALREADY_EXISTS as i32) {
+                return Err(error);
+            }
+        }
+
+        self.open_dir(name)
+    }
+
+    pub fn names(&self) -> Result<Vec<OsString>> {
+        Ok(self.entries()?.into_iter().map(|e| e.name).collect())
+    }
+
+    // Entries share the volume of their directory
+    pub fn fingerprint(&self, name: &OsStr) -> Result<Fingerprint> {
+        let entry: Entry = self.find(name)?;
+
+        Ok(Fingerprint {
+            dev: information_of(self.handle)?.dwVolumeSerialNumber as u64,
+            id: entry.id as u64,
+            size: entry.size as u64,
+            modified: entry.modified,
+        })
+    }
+
+    pub fn kind(&self, name: &OsStr) -> Result<Kind> {
+        let entry: Entry = self.find(name)?;
+
+        Ok(if entry.attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+            Kind::Link
+        } else if entry.attributes & FILE_ATTRIBUTE_DIRECTORY != 0 {
+            Kind::Dir
+        } else {
+            Kind::File
+        })
+    }
+
+    // The expected fingerprint is checked on the same handle that carries the
+    // deletion or the move
+    fn open_checked(&self, entry: &Entry, expected: Option<&Fingerprint>) -> Result<HANDLE> {
+        let access: DWORD = DELETE | FILE_READ_ATTRIBUTES | FILE_WRITE_ATTRIBUTES;
+        let share: DWORD = FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE;
+        let handle: HANDLE = self.open_by_id(entry, access, share)?;
+
+        if let Some(expected) = expected {
+            match fingerprint_of(handle) {
+                Ok(ref current) if current == expected => {}
+                _ => {
+                    unsafe { CloseHandle(handle); }
+                    return Err(Error::other("file changed since the scan"));
+                }
+            }
+        }
+
+        Ok(handle)
+    }
+
+    // A rename fails with ERROR_NOT_SAME_DEVICE when the destination is on
+    // another volume, the file is copied there instead
+    pub fn move_to(&self, name: &OsStr, expected: &Fingerprint, destination: &Path) -> Result<Moved> {
+        let entry: Entry = self.find(name)?;
+        let handle: HANDLE = self.open_checked(&entry, Some(expected))?;
+        let status: Result<()> = set_rename(handle, destination.as_os_str());
+
+        unsafe { CloseHandle(handle); }
+
+        match status {
+            Err(ref e) if e.raw_os_error() == Some(ERROR_NOT_SAME_DEVICE as i32) => {
+                self.copy_to(&entry, expected, destination)?;
+                Ok(Moved::Copied)
+            }
+            status => status.map(|_| Moved::Renamed),
+        }
+    }
+
+    // The content is read through a handle opened by file id and checked
+    // against the expected fingerprint, writers are denied meanwhile. The copy
+    // is synced to disk before the same handle deletes the file, it goes again
+    // when any step fails and the file stays.
+    fn copy_to(&self, entry: &Entry, expected: &Fingerprint, destination: &Path) -> Result<()> {
+        if entry.attributes & FILE_ATTRIBUTE_READONLY != 0 {
+            let handle: HANDLE = self.open_checked(entry, Some(expected))?;
+            let status: Result<()> = clear_readonly(handle, entry.attributes);
+
+            unsafe { CloseHandle(handle); }
+            status?;
+        }
+
+        let access: DWORD = GENERIC_READ | DELETE | FILE_READ_ATTRIBUTES;
+        let handle: HANDLE = self.open_by_id(entry, access, FILE_SHARE_READ | FILE_SHARE_DELETE)?;
+        let mut file: File = unsafe { File::from_raw_handle(handle as RawHandle) };
+
+        match fingerprint_of(file.as_raw_handle() as HANDLE) {
+            Ok(ref current) if current == expected => {}
+            _ => return Err(Error::other("file changed since the scan")),
+        }
+
+        let mut stored: File = OpenOptions::new().write(true).create_new(true).open(destination)?;
+
+        let status: Result<()> = copy(&mut file, &mut stored)
+            .and_then(|_| stored.sync_all())
+            .and_then(|_| set_delete_disposition(file.as_raw_handle() as HANDLE));
+
+        if status.is_err() {
+            drop(stored);
+            let _ = remove_file(destination);
+        }
+
+        status
+    }
+
+    // The content is overwritten through a handle opened by file id and checked
+    // against the expected fingerprint, the same handle renames the file away
+    // in its directory then deletes it
+    pub fn shred(&self, name: &OsStr, expected: &Fingerprint, shred: &Shred) -> Result<()> {
+        let entry: Entry = self.find(name)?;
+
+        if entry.attributes & FILE_ATTRIBUTE_READONLY != 0 {
+            let handle: HANDLE = self.open_checked(&entry, Some(expected))?;
+            let status: Result<()> = clear_readonly(handle, entry.attributes);
+
+            unsafe { CloseHandle(handle); }
+            status?;
+        }
+
+        // Writers are denied while the content is overwritten
+        let access: DWORD = GENERIC_WRITE | DELETE | FILE_READ_ATTRIBUTES | FILE_WRITE_ATTRIBUTES;
+        let handle: HANDLE = self.open_by_id(&entry, access, FILE_SHARE_READ | FILE_SHARE_DELETE)?;
+        let mut file: File = unsafe { File::from_raw_handle(handle as RawHandle) };
+
+        match fingerprint_of(file.as_raw_handle() as HANDLE) {
+            Ok(ref current) if current == expected => {}
+            _ => return Err(Error::other("file changed since the scan")),
+        }
+
+        overwrite(&mut file, expected.size, shred)?;
+
+        // A bare file name renames inside the current directory
+        set_rename(file.as_raw_handle() as HANDLE, &random_name())?;
+        set_delete_disposition(file.as_raw_handle() as HANDLE)
+    }
+
+    // The stored file gets its owner and attributes while it is still in the
+    // store, then the same handle renames it in place, never over an existing
+    // entry. Onto another volume the content goes to a new file instead, the
+    // store only lets go of the file once it is back.
+    pub fn restore(&self, name: &OsStr, stored: &Path, attributes: Option<&Attributes>) -> Result<()> {
+        let destination: PathBuf = self.path()?.join(name);
+        let wide: Vec<u16> = stored.as_os_str().encode_wide().chain(Some(0)).collect();
+        let access: DWORD = GENERIC_READ | DELETE | FILE_READ_ATTRIBUTES | FILE_WRITE_ATTRIBUTES | WRITE_OWNER;
+
+        let handle: HANDLE = unsafe {
+            CreateFileW(wide.as_ptr(), access, FILE_SHARE_READ, null_mut(), OPEN_EXISTING, FLAGS, null_mut())
+        };
+
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(Error::last_os_error());
+        }
+
+        let mut file: File = unsafe { File::from_raw_handle(handle as RawHandle) };
+
+        if attributes_of(handle)? & (FILE_ATTRIBUTE_DIRECTORY | FILE_ATTRIBUTE_REPARSE_POINT) != 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "not a regular file"));
+        }
+
+        if let Some(a) = attributes {
+            set_attributes(handle, a)?;
+        }
+
+        match set_rename(handle, destination.as_os_str()) {
+            Err(ref e) if e.raw_os_error() == Some(ERROR_NOT_SAME_DEVICE as i32) => {}
+            status => return status,
+        }
+
+        let target: Vec<u16> = destination.as_os_str().encode_wide().chain(Some(0)).collect();
+
+        let created: HANDLE = unsafe {
+            CreateFileW(
+                target.as_ptr(),
+                GENERIC_WRITE | DELETE | FILE_WRITE_ATTRIBUTES | WRITE_OWNER,
+                0,
+                null_mut(),
+                CREATE_NEW,
+                FILE_FLAG_OPEN_REPARSE_POINT,
+                null_mut(),
+            )
+        };
+
+        if created == INVALID_HANDLE_VALUE {
+            return Err(Error::last_os_error());
+        }
+
+        let mut restored: File = unsafe { File::from_raw_handle(created as RawHandle) };
+
+        let status: Result<()> = copy(&mut file, &mut restored)
+            .and_then(|_| restored.sync_all())
+            .and_then(|_| attributes.map_or(Ok(()), |a| set_attributes(created, a)));
+
+        if status.is_err() {
+            let _ = set_delete_disposition(created);
+            return status;
+        }
+
+        clear_readonly(handle, attributes_of(handle)?)?;
+        set_delete_disposition(handle)
+    }
+
+    // Opened with FILE_FLAG_OPEN_REPARSE_POINT, a link is deleted itself and
+    // never its target
+    pub fn remove(&self, name: &OsStr, _kind: Kind, expected: Option<&Fingerprint>) -> Result<()> {
+        let entry: Entry = self.find(name)?;
+        let handle: HANDLE = self.open_checked(&entry, expected)?;
+
+        let mut status: Result<()> = set_delete_disposition(handle);
+
+        if let Err(ref e) = status {
+            if e.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32)
+                && entry.attributes & FILE_ATTRIBUTE_READONLY != 0
+                && clear_readonly(handle, entry.attributes).is_ok() {
+                status = set_delete_disposition(handle);
+            }
+        }
+
+        unsafe { CloseHandle(handle); }
+
+        status
+    }
+}
+
+fn set_delete_disposition(handle: HANDLE) -> Result<()> {
+    let mut info = FILE_DISPOSITION_INFO { DeleteFile: 1 };
+
+    let status = unsafe {
+        SetFileInformationByHandle(
+            handle,
+            FileDispositionInfo,
+            &mut info as *mut FILE_DISPOSITION_INFO as LPVOID,
+            size_of::<FILE_DISPOSITION_INFO>() as DWORD,
+        )
+    };
+
+    if status == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn set_rename(handle: HANDLE, destination: &OsStr) -> Result<()> {
+    let name: Vec<u16> = destination.encode_wide().collect();
+    let size: usize = size_of::<FILE_RENAME_INFO>() + name.len() * size_of::<u16>();
+    let mut buffer: Vec<u64> = vec![0; size / size_of::<u64>() + 1];
+
+    let status = unsafe {
+        let info = buffer.as_mut_ptr() as *mut FILE_RENAME_INFO;
+        (*info).ReplaceIfExists = 0;
+        (*info).RootDirectory = null_mut();
+        (*info).FileNameLength = (name.len() * size_of::<u16>()) as DWORD;
+        copy_nonoverlapping(name.as_ptr(), (*info).FileName.as_mut_ptr(), name.len());
+
+        SetFileInformationByHandle(handle, FileRenameInfo, info as LPVOID, size as DWORD)
+    };
+
+    if status == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+fn clear_readonly(handle: HANDLE, attributes: DWORD) -> Result<()> {
+    set_basic_attributes(handle, attributes & !FILE_ATTRIBUTE_READONLY)
+}
+
+// The owner takes SeRestorePrivilege when it is another account
+fn set_attributes(handle: HANDLE, attributes: &Attributes) -> Result<()> {
+    let owner: Vec<u16> = OsStr::new(&attributes.owner).encode_wide().chain(Some(0)).collect();
+    let mut sid: PSID = null_mut();
+
+    if unsafe { ConvertStringSidToSidW(owner.as_ptr(), &mut sid) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let status: DWORD = unsafe {
+        SetSecurityInfo(handle, SE_FILE_OBJECT, OWNER_SECURITY_INFORMATION, sid, null_mut(), null_mut(), null_mut())
+    };
+
+    unsafe { LocalFree(sid as _); }
+
+    if status != ERROR_SUCCESS {
+        return Err(Error::from_raw_os_error(status as i32));
+    }
+
+    set_basic_attributes(handle, attributes.mode & !(FILE_ATTRIBUTE_DIRECTORY | FILE_ATTRIBUTE_REPARSE_POINT))
+}
+
+// Time stamps left at zero are not changed
+fn set_basic_attributes(handle: HANDLE, attributes: DWORD) -> Result<()> {
+    let mut info: FILE_BASIC_INFO = unsafe { zeroed() };
+    info.FileAttributes = attributes;
+
+    let status = unsafe {
+        SetFileInformationByHandle(
+            handle,
+            FileBasicInfo,
+            &mut info as *mut FILE_BASIC_INFO as LPVOID,
+            size_of::<FILE_BASIC_INFO>() as DWORD,
+        )
+    };
+
+    if status == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.handle); }
+    }
+}
@@ -0,0 +1,9 @@
+#[cfg(unix)]
+pub use self::unix::*;
+#[cfg(windows)]
+pub use self::windows::*;
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
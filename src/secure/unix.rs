@@ -0,0 +1,43 @@
+use std::fs::{create_dir_all, DirBuilder, Metadata, symlink_metadata};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::Path;
+
+// What an elevated clean reads back must come from root, or from the user
+// running the cleaner, and nobody else may have changed it. Links are refused.
+pub fn check_owner(path: &Path) -> Result<()> {
+    let metadata: Metadata = symlink_metadata(path)?;
+    let euid: u32 = unsafe { libc::geteuid() };
+
+    if metadata.file_type().is_symlink() {
+        return Err(Error::new(ErrorKind::PermissionDenied, format!("{} is a link", path.display())));
+    }
+
+    if (metadata.uid() != 0 && metadata.uid() != euid) || metadata.mode() & 0o022 != 0 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} can be changed by another user", path.display()),
+        ));
+    }
+
+    Ok(())
+}
+
+// The directory and its parent are created for their owner only, existing
+// ones have to pass check_owner
+pub fn create_dir(path: &Path) -> Result<()> {
+    let parent: Option<&Path> = path.parent().filter(|p| !p.as_os_str().is_empty());
+
+    if let Some(grand_parent) = parent.and_then(|p| p.parent()) {
+        create_dir_all(grand_parent)?;
+    }
+
+    for dir in parent.into_iter().chain(Some(path)) {
+        match DirBuilder::new().mode(0o700).create(dir) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => check_owner(dir)?,
+            result => result?,
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::fs::{create_dir_all, Metadata, symlink_metadata};
+use std::io::{Error, ErrorKind, Result};
+use std::mem::size_of;
+use std::os::windows::fs::MetadataExt;
+use std::path::Path;
+use std::ptr::null_mut;
+use std::slice::from_raw_parts;
+
+use winapi::shared::minwindef::{BOOL, DWORD};
+use winapi::shared::sddl::{ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1};
+use winapi::shared::winerror::ERROR_ALREADY_EXISTS;
+use winapi::um::fileapi::CreateDirectoryW;
+use winapi::um::minwinbase::SECURITY_ATTRIBUTES;
+use winapi::um::securitybaseapi::{GetFileSecurityW, GetSecurityDescriptorOwner, IsWellKnownSid};
+use winapi::um::winbase::LocalFree;
+use winapi::um::winnt::{
+    FILE_ATTRIBUTE_REPARSE_POINT, LPWSTR, OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, PSID, WinBuiltinAdministratorsSid,
+    WinLocalSystemSid,
+};
+
+use crate::utils::to_utf16;
+
+// Owned by administrators, nobody but them and SYSTEM gets in, nothing is
+// inherited from a parent users can write to
+const ADMIN_SDDL: &str = "O:BAD:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)";
+
+// Calls `check` with the owner of `path`, a reparse point is refused
+fn with_owner<T>(path: &Path, check: impl FnOnce(PSID) -> Result<T>) -> Result<T> {
+    let metadata: Metadata = symlink_metadata(path)?;
+
+    if metadata.file_type().is_symlink() || metadata.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+        return Err(Error::new(ErrorKind::PermissionDenied, format!("{} is a link", path.display())));
+    }
+
+    let name: Vec<u16> = to_utf16(&path.to_string_lossy());
+    let mut buffer: Vec<u64> = vec![0; 512];
+    let mut needed: DWORD = 0;
+
+    let status: BOOL = unsafe {
+        GetFileSecurityW(
+            name.as_ptr(),
+            OWNER_SECURITY_INFORMATION,
+            buffer.as_mut_ptr() as PSECURITY_DESCRIPTOR,
+            (buffer.len() * size_of::<u64>()) as DWORD,
+            &mut needed,
+        )
+    };
+
+    if status == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut owner: PSID = null_mut();
+    let mut defaulted: BOOL = 0;
+
+    if unsafe { GetSecurityDescriptorOwner(buffer.as_mut_ptr() as PSECURITY_DESCRIPTOR, &mut owner, &mut defaulted) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    if owner.is_null() {
+        return Err(Error::new(ErrorKind::PermissionDenied, format!("{} has no owner", path.display())));
+    }
+
+    check(owner)
+}
+
+// What an elevated clean reads back must be owned by SYSTEM or the
+// administrators. Reparse points are refused.
+pub fn check_owner(path: &Path) -> Result<()> {
+    with_owner(path, |owner| {
+        let trusted: bool = unsafe {
+            IsWellKnownSid(owner, WinLocalSystemSid) != 0 || IsWellKnownSid(owner, WinBuiltinAdministratorsSid) != 0
+        };
+
+        match trusted {
+            true => Ok(()),
+            false => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} is not owned by SYSTEM or the administrators", path.display()),
+            )),
+        }
+    })
+}
+
+// String SID of the owner, `S-1-5-21-...`
+pub fn owner_sid(path: &Path) -> Result<String> {
+    with_owner(path, |owner| {
+        let mut string: LPWSTR = null_mut();
+
+        if unsafe { ConvertSidToStringSidW(owner, &mut string) } == 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let sid: String = unsafe {
+            let len: usize = (0..).take_while(|i| *string.add(*i) != 0).count();
+            String::from_utf16_lossy(from_raw_parts(string, len))
+        };
+
+        unsafe { LocalFree(string as _); }
+
+        Ok(sid)
+    })
+}
+
+fn create_admin_dir(path: &Path) -> Result<()> {
+    let sddl: Vec<u16> = to_utf16(ADMIN_SDDL);
+    let mut descriptor: PSECURITY_DESCRIPTOR = null_mut();
+
+    let status: BOOL = unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(sddl.as_ptr(), SDDL_REVISION_1 as DWORD, &mut descriptor, null_mut())
+    };
+
+    if status == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: size_of::<SECURITY_ATTRIBUTES>() as DWORD,
+        lpSecurityDescriptor: descriptor,
+        bInheritHandle: 0,
+    };
+
+    let status: BOOL = unsafe { CreateDirectoryW(to_utf16(&path.to_string_lossy()).as_ptr(), &mut attributes) };
+    let error: Error = Error::last_os_error();
+
+    unsafe { LocalFree(descriptor as _); }
+
+    match status {
+        0 if error.raw_os_error() == Some(ERROR_ALREADY_EXISTS as i32) => Err(Error::from(ErrorKind::AlreadyExists)),
+        0 => Err(error),
+        _ => Ok(()),
+    }
+}
+
+// The directory and its parent are created for the administrators only,
+// existing ones have to pass check_owner. ProgramData lets any user create
+// a folder, whoever came first must not own it.
+pub fn create_dir(path: &Path) -> Result<()> {
+    let parent: Option<&Path> = path.parent().filter(|p| !p.as_os_str().is_empty());
+
+    if let Some(grand_parent) = parent.and_then(|p| p.parent()) {
+        create_dir_all(grand_parent)?;
+    }
+
+    for dir in parent.into_iter().chain(Some(path)) {
+        match create_admin_dir(dir) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => check_owner(dir)?,
+            result => result?,
+        }
+    }
+
+    Ok(())
+}
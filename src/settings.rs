@@ -0,0 +1,155 @@
+use std::env::current_exe;
+#[cfg(windows)]
+use std::env::var;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+use crate::process::OpenFiles;
+use crate::profiles::UserScope;
+use crate::shred::{Pattern, Shred};
+use crate::trash::TrashPolicy;
+
+pub const SETTINGS_FILE: &str = "kptemp.ini";
+
+// Read from kptemp.ini next to the executable, one `key = value` per line,
+// lines starting with `#` or `;` are comments
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub quarantine: bool,
+    pub quarantine_dir: PathBuf,
+    pub deferred_queue: PathBuf,
+    // The reports of the other users, out of their reach
+    pub reports_dir: PathBuf,
+    pub shred: bool,
+    pub shred_passes: u32,
+    pub shred_pattern: Pattern,
+    pub close_timeout: u64,
+    // Removals failing on a sharing violation are tried this many times more,
+    // waiting retry_backoff milliseconds and twice as long after each failure,
+    // never more than retry_max_wait milliseconds over the whole clean
+    pub retry_attempts: u32,
+    pub retry_backoff: u64,
+    pub retry_max_wait: u64,
+    pub open_files: OpenFiles,
+    pub user_scope: UserScope,
+    pub recycle_bin: TrashPolicy,
+    pub winapp2: Option<PathBuf>,
+    pub cleanerml: Option<PathBuf>,
+}
+
+// Only administrators can write there, ProgramData itself lets users create
+// folders so ours is locked down when it is created
+#[cfg(windows)]
+fn data_dir() -> PathBuf {
+    let program_data: String = var("ProgramData").unwrap_or("C:\\ProgramData".to_string());
+    PathBuf::from(program_data).join("KpTemp")
+}
+
+#[cfg(unix)]
+fn data_dir() -> PathBuf {
+    PathBuf::from("/var/lib/kptemp")
+}
+
+impl Settings {
+    pub fn default() -> Settings {
+        Settings {
+            quarantine: false,
+            quarantine_dir: data_dir().join("quarantine"),
+            // What a clean leaves for the next reboot, checked by the next one
+            deferred_queue: data_dir().join("deferred.txt"),
+            reports_dir: data_dir().join("reports"),
+            shred: false,
+            shred_passes: 1,
+            shred_pattern: Pattern::Random,
+            close_timeout: 10,
+            retry_attempts: 3,
+            retry_backoff: 100,
+            retry_max_wait: 5000,
+            open_files: OpenFiles::Ignore,
+            user_scope: UserScope::parse("interactive, services"),
+            recycle_bin: TrashPolicy::All,
+            winapp2: None,
+            cleanerml: None,
+        }
+    }
+
+    pub fn load() -> Settings {
+        let mut settings = Settings::default();
+
+        let path: PathBuf = match current_exe() {
+            Ok(p) => p.with_file_name(SETTINGS_FILE),
+            _ => return settings,
+        };
+
+        let content: String = match read_to_string(&path) {
+            Ok(c) => c,
+            _ => return settings,
+        };
+
+        for (key, value) in parse_pairs(&content) {
+            settings.set(&key, &value);
+        }
+
+        settings
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "quarantine" => self.quarantine = parse_bool(value).unwrap_or(self.quarantine),
+            "quarantine_dir" => self.quarantine_dir = PathBuf::from(value),
+            "deferred_queue" => self.deferred_queue = PathBuf::from(value),
+            "reports_dir" => self.reports_dir = PathBuf::from(value),
+            "shred" => self.shred = parse_bool(value).unwrap_or(self.shred),
+            "shred_passes" => self.shred_passes = value.parse().unwrap_or(self.shred_passes),
+            "shred_pattern" => self.shred_pattern = Pattern::parse(value).unwrap_or(self.shred_pattern),
+            "close_timeout" => self.close_timeout = value.parse().unwrap_or(self.close_timeout),
+            "retry_attempts" => self.retry_attempts = value.parse().unwrap_or(self.retry_attempts),
+            "retry_backoff" => self.retry_backoff = value.parse().unwrap_or(self.retry_backoff),
+            "retry_max_wait" => self.retry_max_wait = value.parse().unwrap_or(self.retry_max_wait),
+            "open_files" => self.open_files = OpenFiles::parse(value).unwrap_or(self.open_files),
+            "user_scope" => self.user_scope = UserScope::parse(value),
+            "winapp2" => self.winapp2 = Some(PathBuf::from(value)),
+            "cleanerml" => self.cleanerml = Some(PathBuf::from(value)),
+            "recycle_bin" => self.recycle_bin = TrashPolicy::parse(value).unwrap_or(self.recycle_bin),
+            _ => {}
+        }
+    }
+
+    // Quarantine keeps files restorable, it wins over shredding
+    pub fn shred_options(&self) -> Option<Shred> {
+        if !self.shred || self.quarantine || self.shred_passes == 0 {
+            return None;
+        }
+
+        Some(Shred { passes: self.shred_passes, pattern: self.shred_pattern })
+    }
+}
+
+pub fn parse_pairs(content: &str) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = Vec::new();
+
+    for line in content.lines() {
+        let line: &str = line.trim();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        let mut split = line.splitn(2, '=');
+
+        match (split.next(), split.next()) {
+            (Some(k), Some(v)) => pairs.push((k.trim().to_lowercase(), v.trim().to_string())),
+            _ => continue,
+        }
+    }
+
+    pairs
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "yes" | "true" | "on" => Some(true),
+        "0" | "no" | "false" | "off" => Some(false),
+        _ => None,
+    }
+}
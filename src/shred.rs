@@ -0,0 +1,114 @@
+use std::cmp::min;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{Result, Seek, SeekFrom, Write};
+use std::process::id;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SSD_WARNING: &str = "Shredding overwrites the data in place. On SSDs and flash storage, \
+    wear leveling may keep older copies of the blocks, overwriting can not guarantee they are gone.";
+
+static SEED_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    Zeros,
+    Ones,
+    Random,
+    Byte(u8),
+}
+
+impl Pattern {
+    // zeros, ones, random or a byte such as 0xAA
+    pub fn parse(value: &str) -> Option<Pattern> {
+        let value: String = value.trim().to_lowercase();
+
+        match value.as_str() {
+            "zeros" | "zero" => Some(Pattern::Zeros),
+            "ones" | "one" => Some(Pattern::Ones),
+            "random" => Some(Pattern::Random),
+            _ => {
+                let hex: &str = value.trim_start_matches("0x");
+                u8::from_str_radix(hex, 16).ok().map(Pattern::Byte)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Shred {
+    pub passes: u32,
+    pub pattern: Pattern,
+}
+
+// Not meant to be unpredictable, only to avoid writing a constant pattern
+struct XorShift(u64);
+
+impl XorShift {
+    fn seeded() -> XorShift {
+        let now: u64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        let counter: u64 = SEED_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        XorShift((now ^ (id() as u64) << 32 ^ counter.wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn fill(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(8) {
+            let bytes: [u8; 8] = self.next().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+// Overwrites the whole content `passes` times, each pass flushed to the disk,
+// then truncates the file
+pub fn overwrite(file: &mut File, size: u64, shred: &Shred) -> Result<()> {
+    let mut buffer: Vec<u8> = vec![0; 64 * 1024];
+    let mut rng: XorShift = XorShift::seeded();
+
+    match shred.pattern {
+        Pattern::Zeros | Pattern::Random => {}
+        Pattern::Ones => buffer.iter_mut().for_each(|b| *b = 0xFF),
+        Pattern::Byte(v) => buffer.iter_mut().for_each(|b| *b = v),
+    }
+
+    for _ in 0..shred.passes {
+        file.seek(SeekFrom::Start(0))?;
+        let mut remaining: u64 = size;
+
+        while remaining > 0 {
+            let n: usize = min(remaining, buffer.len() as u64) as usize;
+
+            if shred.pattern == Pattern::Random {
+                rng.fill(&mut buffer[..n]);
+            }
+
+            file.write_all(&buffer[..n])?;
+            remaining -= n as u64;
+        }
+
+        file.sync_all()?;
+    }
+
+    file.set_len(0)?;
+    file.sync_all()
+}
+
+// The name is renamed away before the unlink so it does not survive in the
+// directory entry either
+pub fn random_name() -> OsString {
+    let mut rng: XorShift = XorShift::seeded();
+    OsString::from(format!("{:016x}.kpshred", rng.next()))
+}
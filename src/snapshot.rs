@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env::{temp_dir, var_os};
+use std::env::consts::OS;
+use std::fs::{File, Metadata, read_to_string, symlink_metadata};
+use std::io::{Error, ErrorKind, Result, Write};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(windows)]
+use std::os::windows::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::id;
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::{Local, NaiveDateTime, TimeZone};
+use walkdir::WalkDir;
+
+use crate::clean::{dry_run, get_profiles, get_system_vars, Plan, read_system_vars, resolve_targets};
+use crate::globals::KPTEMP_VERSION;
+use crate::privilege::Privileges;
+use crate::profiles::{AccountKind, Profile};
+use crate::rules::{
+    glob_file_rules, kept_rules, load_cleanerml, load_winapp2, profile_rules, Rule, walk_all_rules, windows_old_rules,
+};
+use crate::settings::Settings;
+use crate::vfs::memory::MemoryFs;
+
+// The environment the paths are found from, recorded for support to read.
+// Only XDG_CACHE_HOME is replayed, the others are the variables below.
+#[cfg(windows)]
+const ENVIRONMENT: [&str; 8] = [
+    "SYSTEMDRIVE", "SYSTEMROOT", "ALLUSERSPROFILE", "ProgramFiles", "USERPROFILE", "USERNAME", "TEMP", "TMP",
+];
+#[cfg(unix)]
+const ENVIRONMENT: [&str; 4] = ["HOME", "USER", "TMPDIR", "XDG_CACHE_HOME"];
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Dir,
+    File,
+    Link,
+}
+
+// One entry of the tree, metadata only: `kind <TAB> size <TAB> mtime <TAB>
+// attributes <TAB> owner <TAB> path`
+struct Node {
+    kind: Kind,
+    size: u64,
+    modified: u64,
+    attributes: u32,
+    owner: String,
+    path: PathBuf,
+}
+
+impl Node {
+    fn to_line(&self) -> String {
+        let kind: &str = match self.kind {
+            Kind::Dir => "dir",
+            Kind::File => "file",
+            Kind::Link => "link",
+        };
+
+        format!(
+            "{}\t{}\t{}\t{:x}\t{}\t{}\r\n",
+            kind, self.size, self.modified, self.attributes, self.owner, self.path.display()
+        )
+    }
+
+    fn parse(fields: &[&str]) -> Option<Node> {
+        let kind: Kind = match *fields.first()? {
+            "dir" => Kind::Dir,
+            "file" => Kind::File,
+            "link" => Kind::Link,
+            _ => return None,
+        };
+
+        Some(Node {
+            kind,
+            size: fields.get(1)?.parse().ok()?,
+            modified: fields.get(2)?.parse().ok()?,
+            attributes: u32::from_str_radix(fields.get(3)?, 16).ok()?,
+            owner: fields.get(4)?.to_string(),
+            path: PathBuf::from(fields.get(5)?),
+        })
+    }
+}
+
+#[cfg(windows)]
+fn attributes(metadata: &Metadata) -> (u32, String) {
+    (metadata.file_attributes(), "-".to_string())
+}
+
+#[cfg(unix)]
+fn attributes(metadata: &Metadata) -> (u32, String) {
+    (metadata.mode(), metadata.uid().to_string())
+}
+
+fn node(path: &Path) -> Option<Node> {
+    let metadata: Metadata = symlink_metadata(path).ok()?;
+    let (attributes, owner) = attributes(&metadata);
+
+    let kind: Kind = match (metadata.file_type().is_symlink(), metadata.is_dir()) {
+        (true, _) => Kind::Link,
+        (false, true) => Kind::Dir,
+        (false, false) => Kind::File,
+    };
+
+    let modified: u64 = metadata.modified().ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    Some(Node { kind, size: if kind == Kind::File { metadata.len() } else { 0 }, modified, attributes, owner, path: path.to_path_buf() })
+}
+
+// The path and every directory leading to it
+fn reach(path: &Path, nodes: &mut BTreeMap<PathBuf, Node>) {
+    for ancestor in path.ancestors().filter(|a| !a.as_os_str().is_empty()) {
+        if nodes.contains_key(ancestor) {
+            break;
+        }
+
+        if let Some(n) = node(ancestor) {
+            nodes.insert(ancestor.to_path_buf(), n);
+        }
+    }
+}
+
+fn profile_line(profile: &Profile) -> String {
+    let kind: &str = match profile.kind {
+        AccountKind::User => "user",
+        AccountKind::Service => "service",
+    };
+
+    let temp: String = profile.temp.as_ref().map(|t| t.display().to_string()).unwrap_or("-".to_string());
+
+    format!("profile\t{}\t{}\t{}\t{}\t{}\r\n", profile.id, profile.name, kind, temp, profile.path.display())
+}
+
+// Everything the rules reach on this system: their targets with all they
+// hold, the directories leading to them, and where the variables and
+// profiles point. Returns the number of entries written.
+pub fn export(file: &Path, old: bool, settings: &Settings) -> Result<usize> {
+    let privileges: Privileges = Privileges::current();
+    let system_vars = get_system_vars();
+    let users_profiles: Vec<Profile> = get_profiles(&system_vars, &privileges, &settings.user_scope);
+
+    let mut walked: Vec<Rule> = walk_all_rules(&system_vars);
+    walked.extend(glob_file_rules());
+    walked.extend(profile_rules());
+
+    if old {
+        walked.extend(windows_old_rules());
+    }
+
+    // Kept targets and detected applications only need to exist
+    let mut reached: Vec<Rule> = kept_rules(&system_vars);
+
+    let imports = vec![
+        settings.winapp2.as_ref().map(|p| load_winapp2(p)),
+        settings.cleanerml.as_ref().map(|p| load_cleanerml(p)),
+    ];
+
+    for import in imports.into_iter().flatten().flatten() {
+        for entry in import.entries {
+            walked.extend(entry.rules);
+            reached.extend(entry.detect);
+        }
+    }
+
+    let mut nodes: BTreeMap<PathBuf, Node> = BTreeMap::new();
+
+    for path in system_vars.values().chain(users_profiles.iter().flat_map(|p| Some(&p.path).into_iter().chain(p.temp.as_ref()))) {
+        reach(path, &mut nodes);
+    }
+
+    for rule in &reached {
+        for (target, _) in resolve_targets(rule, &system_vars, &users_profiles) {
+            reach(&target, &mut nodes);
+        }
+    }
+
+    for rule in &walked {
+        for (target, _) in resolve_targets(rule, &system_vars, &users_profiles) {
+            reach(&target, &mut nodes);
+
+            for entry in WalkDir::new(&target).follow_links(false).min_depth(1).into_iter().filter_map(|e| e.ok()) {
+                if let Some(n) = node(entry.path()) {
+                    nodes.insert(entry.path().to_path_buf(), n);
+                }
+            }
+        }
+    }
+
+    let mut output = File::create(file)?;
+
+    output.write_all(format!("os\t{}\r\n", OS).as_bytes())?;
+    output.write_all(format!("version\t{}\r\n", KPTEMP_VERSION).as_bytes())?;
+    output.write_all(format!("date\t{}\r\n", Local::now().format(DATE_FORMAT)).as_bytes())?;
+
+    for name in ENVIRONMENT.iter() {
+        if let Some(value) = var_os(name) {
+            output.write_all(format!("env\t{}\t{}\r\n", name, value.to_string_lossy()).as_bytes())?;
+        }
+    }
+
+    for (name, path) in &system_vars {
+        output.write_all(format!("var\t{}\t{}\r\n", name, path.display()).as_bytes())?;
+    }
+
+    for profile in &users_profiles {
+        output.write_all(profile_line(profile).as_bytes())?;
+    }
+
+    for n in nodes.values() {
+        output.write_all(n.to_line().as_bytes())?;
+    }
+
+    Ok(nodes.len())
+}
+
+// An exported system, read back
+pub struct Snapshot {
+    pub os: String,
+    pub date: String,
+    env: Vec<(String, String)>,
+    vars: Vec<(String, PathBuf)>,
+    profiles: Vec<Profile>,
+    nodes: Vec<Node>,
+}
+
+fn parse_profile(fields: &[&str]) -> Option<Profile> {
+    let kind: AccountKind = match *fields.get(2)? {
+        "user" => AccountKind::User,
+        _ => AccountKind::Service,
+    };
+
+    Some(Profile {
+        id: fields.first()?.to_string(),
+        name: fields.get(1)?.to_string(),
+        kind,
+        temp: fields.get(3).filter(|t| **t != "-").map(PathBuf::from),
+        path: PathBuf::from(fields.get(4)?),
+    })
+}
+
+pub fn load(file: &Path) -> Result<Snapshot> {
+    let content: String = read_to_string(file)?;
+    let mut snapshot = Snapshot {
+        os: String::new(),
+        date: String::new(),
+        env: Vec::new(),
+        vars: Vec::new(),
+        profiles: Vec::new(),
+        nodes: Vec::new(),
+    };
+
+    for line in content.lines().filter(|l| !l.is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+
+        let valid: bool = match (fields[0], fields.len()) {
+            ("os", 2) => {
+                snapshot.os = fields[1].to_string();
+                true
+            }
+            ("date", 2) => {
+                snapshot.date = fields[1].to_string();
+                true
+            }
+            ("version", 2) => true,
+            ("env", 3) => {
+                snapshot.env.push((fields[1].to_string(), fields[2].to_string()));
+                true
+            }
+            ("var", 3) => {
+                snapshot.vars.push((fields[1].to_string(), PathBuf::from(fields[2])));
+                true
+            }
+            ("profile", _) => parse_profile(&fields[1..]).map(|p| snapshot.profiles.push(p)).is_some(),
+            _ => Node::parse(&fields).map(|n| snapshot.nodes.push(n)).is_some(),
+        };
+
+        if !valid {
+            return Err(Error::new(ErrorKind::InvalidData, format!("invalid line: {}", line)));
+        }
+    }
+
+    if snapshot.os != OS {
+        return Err(Error::new(ErrorKind::InvalidData, format!("snapshot of a {} system, replay it on {}", snapshot.os, snapshot.os)));
+    }
+
+    Ok(snapshot)
+}
+
+// Where a path of the snapshot lands below the replay directory, a drive
+// becoming a directory of its own
+fn inside(root: &Path, path: &Path) -> PathBuf {
+    let mut joined: PathBuf = root.to_path_buf();
+
+    for component in path.components() {
+        match component {
+            Component::Prefix(p) => joined.push(p.as_os_str().to_string_lossy().replace(|c: char| !c.is_alphanumeric(), "")),
+            Component::Normal(n) => joined.push(n),
+            _ => {}
+        }
+    }
+
+    joined
+}
+
+#[cfg(windows)]
+fn outside(relative: &Path) -> PathBuf {
+    let mut components = relative.components();
+
+    match components.next() {
+        Some(drive) => PathBuf::from(format!("{}:\\", drive.as_os_str().to_string_lossy())).join(components.as_path()),
+        None => relative.to_path_buf(),
+    }
+}
+
+#[cfg(unix)]
+fn outside(relative: &Path) -> PathBuf {
+    Path::new("/").join(relative)
+}
+
+// The snapshot rebuilt in memory below a directory that is never created, and
+// the dry run of a clean on it
+pub struct Replay {
+    pub root: PathBuf,
+    pub plan: Plan,
+}
+
+impl Replay {
+    // A path of the replay as it was on the exported system
+    pub fn original(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.root) {
+            Ok(r) => outside(r),
+            _ => path.to_path_buf(),
+        }
+    }
+}
+
+// The whole tree with its sizes, ages and owners, for the clean to find and
+// index. Ages are taken at `date`, the time of the export. Links point
+// nowhere, the engine never follows them.
+fn rebuild(root: &Path, nodes: &[Node], date: u64) -> MemoryFs {
+    let mut fs: MemoryFs = MemoryFs::new();
+
+    for n in nodes {
+        let name: String = inside(root, &n.path).to_string_lossy().to_string();
+
+        fs = match n.kind {
+            Kind::Dir => fs.dir(&name).aged(&name, age(date, n.modified)),
+            Kind::File => fs.file(&name, n.size, age(date, n.modified)),
+            Kind::Link => fs.link(&name),
+        };
+
+        fs = fs.owned(&name, &n.owner);
+    }
+
+    fs
+}
+
+fn age(date: u64, modified: u64) -> Duration {
+    Duration::from_secs(date.saturating_sub(modified))
+}
+
+// Seconds since the epoch of the export, in the local time it was written in
+fn export_date(snapshot: &Snapshot) -> Result<u64> {
+    NaiveDateTime::parse_from_str(&snapshot.date, DATE_FORMAT).ok()
+        .and_then(|d| Local.from_local_datetime(&d).earliest())
+        .map(|d| d.timestamp().max(0) as u64)
+        .ok_or(Error::new(ErrorKind::InvalidData, format!("invalid date: {}", snapshot.date)))
+}
+
+// A dry run of a clean on the exported system, with the rules and settings of
+// this one
+pub fn replay(snapshot: &Snapshot, old: bool, settings: &Settings) -> Result<Replay> {
+    let root: PathBuf = temp_dir().join(format!("kptemp-replay-{}", id()));
+    let fs: MemoryFs = rebuild(&root, &snapshot.nodes, export_date(snapshot)?);
+
+    let mut system_vars: HashMap<&'static str, PathBuf> = read_system_vars().into_keys()
+        .filter_map(|k| snapshot.vars.iter().find(|(n, _)| n == k).map(|(_, p)| (k, inside(&root, p))))
+        .collect();
+
+    let users_profiles: Vec<Profile> = snapshot.profiles.iter()
+        .map(|p| Profile {
+            path: inside(&root, &p.path),
+            temp: p.temp.as_ref().map(|t| inside(&root, t)),
+            ..p.clone()
+        })
+        .collect();
+
+    // The cache of the user who exported moves with the rest, whatever the
+    // environment of this one
+    system_vars.remove("xdg_cache_home");
+
+    if let Some((_, value)) = snapshot.env.iter().find(|(n, v)| n == "XDG_CACHE_HOME" && Path::new(v).is_absolute()) {
+        system_vars.insert("xdg_cache_home", inside(&root, Path::new(value)));
+    }
+
+    let plan: Plan = dry_run(old, settings, &system_vars, users_profiles, &fs);
+
+    Ok(Replay { root, plan })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::var_os;
+    use std::path::PathBuf;
+
+    use chrono::{Duration, Local};
+
+    use crate::settings::Settings;
+    use crate::testing::TempRoot;
+
+    use super::{DATE_FORMAT, load, replay, Replay, Snapshot};
+
+    #[test]
+    fn replay_at_the_export_date() {
+        // Exported a year ago: ages count from then, not from now
+        let date = Local::now() - Duration::days(365);
+        let seconds = |days: i64| (date - Duration::days(days)).timestamp();
+
+        let content: String = [
+            "os\tlinux".to_string(),
+            format!("date\t{}", date.format(DATE_FORMAT)),
+            "env\tXDG_CACHE_HOME\t/home/u/xdg".to_string(),
+            "var\troot\t/".to_string(),
+            "var\tuser_profile\t/home/u".to_string(),
+            "profile\t1000\tu\tuser\t-\t/home/u".to_string(),
+            format!("dir\t0\t{}\t41ed\t0\t/tmp", seconds(0)),
+            format!("file\t1000\t{}\t81a4\t0\t/tmp/old", seconds(20)),
+            format!("file\t500\t{}\t81a4\t0\t/tmp/older", seconds(30)),
+            format!("file\t2000\t{}\t81a4\t0\t/tmp/young", seconds(2)),
+            format!("dir\t0\t{}\t41ed\t1000\t/home/u", seconds(0)),
+            format!("file\t3000\t{}\t81a4\t1000\t/home/u/.cache/default", seconds(1)),
+            format!("file\t4000000000000\t{}\t81a4\t1000\t/home/u/xdg/moved", seconds(1)),
+            format!("file\t1\t{}\t81a4\t1000\t/home/u/xdg/other", seconds(1)),
+        ].join("\r\n");
+
+        let root: TempRoot = TempRoot::new();
+        let file: PathBuf = root.file("snapshot.txt", &content);
+        let before = var_os("XDG_CACHE_HOME");
+
+        let snapshot: Snapshot = load(&file).unwrap();
+        let replay: Replay = replay(&snapshot, false, &Settings::default()).unwrap();
+
+        let targets: Vec<(PathBuf, u64, u64)> = replay.plan.targets().into_iter()
+            .map(|(path, len, size)| (replay.original(&path), len, size))
+            .collect();
+
+        // Far bigger than the disk, and only the cache the export pointed to
+        assert!(targets.contains(&(PathBuf::from("/tmp"), 2, 1500)));
+        assert!(targets.contains(&(PathBuf::from("/home/u/xdg"), 2, 4_000_000_000_001)));
+        assert!(!targets.iter().any(|(p, _, _)| p.starts_with("/home/u/.cache")));
+        assert_eq!(var_os("XDG_CACHE_HOME"), before);
+
+        // Nothing of it was written to this system
+        assert!(!replay.root.exists());
+    }
+}
@@ -0,0 +1,54 @@
+// Helpers shared by the tests
+use std::fs::{create_dir_all, File, FileTimes, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, SystemTime};
+
+static NEXT: AtomicU32 = AtomicU32::new(0);
+
+// A directory of its own under the system temp directory, removed with
+// everything in it when dropped
+pub struct TempRoot {
+    path: PathBuf,
+}
+
+impl TempRoot {
+    pub fn new() -> TempRoot {
+        let name: String = format!("kptemp-test-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::SeqCst));
+        let path: PathBuf = std::env::temp_dir().join(name);
+
+        create_dir_all(&path).unwrap();
+
+        TempRoot { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Written with its parents, `relative` never starts with a separator
+    pub fn file(&self, relative: &str, content: &str) -> PathBuf {
+        let path: PathBuf = self.path.join(relative);
+
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(&path, content).unwrap();
+
+        path
+    }
+
+    // Last modified and accessed `age` ago, the other time stamps are the
+    // system's to set
+    pub fn aged(&self, relative: &str, age: Duration) {
+        let time: SystemTime = SystemTime::now() - age;
+        let times: FileTimes = FileTimes::new().set_modified(time).set_accessed(time);
+
+        File::options().write(true).open(self.path.join(relative)).unwrap().set_times(times).unwrap();
+    }
+}
+
+impl Drop for TempRoot {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::env::var_os;
+use std::fs::{read_dir, read_to_string, remove_file, symlink_metadata, Metadata};
+use std::io::{Error, Result};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use walkdir::WalkDir;
+
+use crate::trash::{TrashBackend, TrashItem, TrashVolume};
+
+const MOUNTS: &str = "/proc/self/mounts";
+
+// The freedesktop.org trash of one user: the home trash and the `.Trash/$uid`
+// or `.Trash-$uid` directory at the top of every mounted volume
+pub struct FreedesktopTrash {
+    home: PathBuf,
+    topdirs: Vec<PathBuf>,
+    uid: u32,
+}
+
+impl FreedesktopTrash {
+    // The trash of the user running the clean
+    pub fn new() -> FreedesktopTrash {
+        let home: PathBuf = match (var_os("XDG_DATA_HOME"), var_os("HOME")) {
+            (Some(d), _) if Path::new(&d).is_absolute() => PathBuf::from(d).join("Trash"),
+            (_, Some(h)) => PathBuf::from(h).join(".local/share/Trash"),
+            _ => PathBuf::from("/root/.local/share/Trash"),
+        };
+
+        FreedesktopTrash::at(home, mount_points(), unsafe { libc::geteuid() })
+    }
+
+    pub fn at(home: PathBuf, topdirs: Vec<PathBuf>, uid: u32) -> FreedesktopTrash {
+        FreedesktopTrash { home, topdirs, uid }
+    }
+
+    // Trash directories that exist, without duplicates
+    fn trash_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let uid: String = self.uid.to_string();
+
+        let candidates = self.topdirs.iter().flat_map(|t| {
+            let shared: PathBuf = t.join(".Trash");
+
+            // The shared .Trash is only trusted when it is sticky and not a link
+            let shared_trash: Option<PathBuf> = match symlink_metadata(&shared) {
+                Ok(m) if m.is_dir() && m.permissions().mode() & 0o1000 != 0 => Some(shared.join(&uid)),
+                _ => None,
+            };
+
+            shared_trash.into_iter().chain(Some(t.join(format!(".Trash-{}", uid))))
+        });
+
+        for dir in Some(self.home.clone()).into_iter().chain(candidates) {
+            if is_owned_dir(&dir, self.uid) && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        dirs
+    }
+}
+
+fn is_owned_dir(path: &Path, uid: u32) -> bool {
+    match symlink_metadata(path) {
+        Ok(m) => m.is_dir() && m.uid() == uid,
+        _ => false,
+    }
+}
+
+// Mount points are octal-escaped in the mount table
+fn unescape_mount(field: &str) -> String {
+    let mut unescaped: String = String::new();
+    let mut rest: &str = field;
+
+    while let Some(start) = rest.find('\\') {
+        unescaped.push_str(&rest[..start]);
+
+        match rest.get(start + 1..start + 4).and_then(|o| u8::from_str_radix(o, 8).ok()) {
+            Some(c) => {
+                unescaped.push(c as char);
+                rest = &rest[start + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+fn mount_points() -> Vec<PathBuf> {
+    let content: String = read_to_string(MOUNTS).unwrap_or_default();
+
+    content.lines()
+        .filter_map(|l| l.split(' ').nth(1))
+        .map(|m| PathBuf::from(unescape_mount(m)))
+        .collect()
+}
+
+fn percent_decode(name: &str) -> String {
+    let bytes: &[u8] = name.as_bytes();
+    let mut decoded: Vec<u8> = Vec::new();
+    let mut i: usize = 0;
+
+    while i < bytes.len() {
+        let escaped: Option<u8> = match bytes[i] {
+            b'%' => name.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()),
+            _ => None,
+        };
+
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// `size mtime name` per trashed directory, the mtime being the one of its
+// .trashinfo when the size was computed
+fn directory_sizes(trash: &Path) -> HashMap<String, (u64, i64)> {
+    let content: String = read_to_string(trash.join("directorysizes")).unwrap_or_default();
+
+    content.lines()
+        .filter_map(|l| {
+            let mut fields = l.splitn(3, ' ');
+            let size: u64 = fields.next()?.parse().ok()?;
+            let mtime: i64 = fields.next()?.parse().ok()?;
+
+            Some((percent_decode(fields.next()?), (size, mtime)))
+        })
+        .collect()
+}
+
+fn tree_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.path().symlink_metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+// Local time, as written by the file manager that trashed the item
+fn deletion_date(info: &Path) -> Option<NaiveDateTime> {
+    let content: String = read_to_string(info).ok()?;
+    let date: &str = content.lines().find_map(|l| l.trim().strip_prefix("DeletionDate="))?;
+
+    NaiveDateTime::parse_from_str(date.trim(), "%Y-%m-%dT%H:%M:%S").ok()
+}
+
+// Items described in info/, the ones whose file is already gone are skipped.
+// files/ and info/ share the name of every item.
+fn trash_items(trash: &Path) -> Result<Vec<TrashItem>> {
+    let sizes: HashMap<String, (u64, i64)> = directory_sizes(trash);
+    let mut items: Vec<TrashItem> = Vec::new();
+
+    for entry in read_dir(trash.join("info"))? {
+        let info: PathBuf = entry?.path();
+
+        let name: String = match (info.extension(), info.file_stem()) {
+            (Some(e), Some(n)) if e == "trashinfo" => n.to_string_lossy().to_string(),
+            _ => continue,
+        };
+
+        let file: PathBuf = trash.join("files").join(&name);
+
+        let metadata: Metadata = match symlink_metadata(&file) {
+            Ok(m) => m,
+            _ => continue,
+        };
+
+        let size: u64 = match (metadata.is_dir(), sizes.get(&name)) {
+            (true, Some((size, mtime))) if symlink_metadata(&info).map(|m| m.mtime()).ok() == Some(*mtime) => *size,
+            (true, _) => tree_size(&file),
+            (false, _) => metadata.len(),
+        };
+
+        items.push(TrashItem {
+            volume: trash.to_path_buf(),
+            path: file,
+            deleted: deletion_date(&info),
+            info,
+            size,
+        });
+    }
+
+    Ok(items)
+}
+
+impl TrashBackend for FreedesktopTrash {
+    fn query(&self) -> Result<Vec<TrashVolume>> {
+        let mut volumes: Vec<TrashVolume> = Vec::new();
+
+        for trash in self.trash_dirs() {
+            // A trash nothing was ever moved to has no info/ yet
+            if !trash.join("info").is_dir() {
+                continue;
+            }
+
+            let items: Vec<TrashItem> = trash_items(&trash)?;
+
+            volumes.push(TrashVolume {
+                len: items.len() as u64,
+                size: items.iter().map(|i| i.size).sum(),
+                location: trash,
+            });
+        }
+
+        Ok(volumes)
+    }
+
+    fn items(&self, volume: &TrashVolume) -> Result<Vec<TrashItem>> {
+        trash_items(&volume.location)
+    }
+
+    fn purge(&self, volume: &TrashVolume) -> Result<()> {
+        let mut failed: u64 = 0;
+
+        for item in trash_items(&volume.location)? {
+            if self.remove(&item).is_err() {
+                failed += 1;
+            }
+        }
+
+        let _ = remove_file(volume.location.join("directorysizes"));
+
+        if failed > 0 {
+            return Err(Error::other(format!("{} items could not be removed", failed)));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::fs::remove_file;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use chrono::{Duration, NaiveDateTime};
+
+use crate::safe_remove::{Fingerprint, Policy, remove_contained};
+
+#[cfg(target_os = "linux")]
+pub use self::freedesktop::FreedesktopTrash as SystemTrash;
+#[cfg(windows)]
+pub use self::windows::RecycleBin as SystemTrash;
+
+#[cfg(target_os = "linux")]
+mod freedesktop;
+#[cfg(windows)]
+mod windows;
+
+// What the trash holds on one volume, `location` is the drive on Windows and
+// the trash directory itself on Linux
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrashVolume {
+    pub location: PathBuf,
+    pub len: u64,
+    pub size: u64,
+}
+
+// One trashed file or directory: `path` is its content, `info` the file
+// describing it ($I on NTFS, .trashinfo on Linux)
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrashItem {
+    pub volume: PathBuf,
+    pub path: PathBuf,
+    pub info: PathBuf,
+    pub size: u64,
+    pub deleted: Option<NaiveDateTime>,
+}
+
+// What a clean does with the trash
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrashPolicy {
+    // Everything goes, like emptying the bin by hand
+    All,
+    // Only the items deleted more than this many days ago
+    OlderThan(i64),
+    // The oldest items go until the trash fits in this many bytes
+    Budget(u64),
+    // The trash is left alone
+    Keep,
+}
+
+impl TrashPolicy {
+    // all, none, older <days> or budget <size> such as 500M or 2G
+    pub fn parse(value: &str) -> Option<TrashPolicy> {
+        let value: String = value.trim().to_lowercase();
+        let fields: Vec<&str> = value.split_whitespace().collect();
+
+        match fields.as_slice() {
+            ["all"] => Some(TrashPolicy::All),
+            ["none"] | ["keep"] => Some(TrashPolicy::Keep),
+            ["older", days] => days.parse().ok().map(TrashPolicy::OlderThan),
+            ["budget", size] => parse_size(size).map(TrashPolicy::Budget),
+            _ => None,
+        }
+    }
+}
+
+fn parse_size(size: &str) -> Option<u64> {
+    let size: &str = size.trim_end_matches('b');
+    let (digits, unit) = size.split_at(size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len()));
+
+    let factor: u64 = match unit {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => return None,
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(factor)
+}
+
+// Everything in the trash goes, whatever its fingerprint
+struct Unconditional;
+
+impl Policy for Unconditional {
+    fn accept(&mut self, _path: &Path, _current: &Fingerprint) -> bool {
+        true
+    }
+}
+
+// The trash of the user running the clean. A failed query is an error, never
+// an empty trash.
+pub trait TrashBackend {
+    fn query(&self) -> Result<Vec<TrashVolume>>;
+
+    fn items(&self, volume: &TrashVolume) -> Result<Vec<TrashItem>>;
+
+    fn purge(&self, volume: &TrashVolume) -> Result<()>;
+
+    // The content goes first, what describes it only once it is gone
+    fn remove(&self, item: &TrashItem) -> Result<()> {
+        let parent: &Path = item.path.parent()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "trash item without a parent"))?;
+
+        remove_contained(parent, &item.path, &mut Unconditional)?;
+        remove_file(&item.info)
+    }
+}
+
+// Splits the items into the ones to purge and the ones to retain. An item
+// without a deletion date is never considered old and goes last.
+pub fn select(items: Vec<TrashItem>, policy: TrashPolicy, now: NaiveDateTime) -> (Vec<TrashItem>, Vec<TrashItem>) {
+    match policy {
+        TrashPolicy::All => (items, Vec::new()),
+        TrashPolicy::Keep => (Vec::new(), items),
+        TrashPolicy::OlderThan(days) => {
+            let limit: NaiveDateTime = now - Duration::days(days);
+            items.into_iter().partition(|i| i.deleted.map(|d| d < limit).unwrap_or(false))
+        }
+        TrashPolicy::Budget(budget) => {
+            let mut items: Vec<TrashItem> = items;
+            items.sort_by_key(|i| (i.deleted.is_none(), i.deleted));
+
+            let mut total: u64 = items.iter().map(|i| i.size).sum();
+            let mut purged: Vec<TrashItem> = Vec::new();
+            let mut retained: Vec<TrashItem> = Vec::new();
+
+            for item in items {
+                if total > budget {
+                    total -= item.size;
+                    purged.push(item);
+                } else {
+                    retained.push(item);
+                }
+            }
+
+            (purged, retained)
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::convert::TryInto;
+use std::fs::{read, read_dir, symlink_metadata};
+use std::io::{Error, Result};
+use std::mem::{size_of, zeroed};
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+use std::slice::from_raw_parts;
+
+use chrono::{Local, NaiveDateTime, TimeZone};
+use walkdir::WalkDir;
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::winerror::S_OK;
+use winapi::um::fileapi::{GetDriveTypeW, GetLogicalDriveStringsW};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+use winapi::shared::sddl::ConvertSidToStringSidW;
+use winapi::um::securitybaseapi::GetTokenInformation;
+use winapi::um::shellapi::{SHEmptyRecycleBinW, SHERB_NOCONFIRMATION, SHQUERYRBINFO, SHQueryRecycleBinW};
+use winapi::um::winbase::{LocalFree, DRIVE_FIXED, DRIVE_REMOVABLE};
+use winapi::um::winnt::{TokenUser, HANDLE, LPWSTR, TOKEN_QUERY, TOKEN_USER};
+
+use crate::trash::{TrashBackend, TrashItem, TrashVolume};
+use crate::utils::to_utf16;
+
+// Seconds between 1601-01-01 and 1970-01-01
+const EPOCH_DIFFERENCE: i64 = 11_644_473_600;
+
+// The shell recycle bin, one per drive
+pub struct RecycleBin;
+
+impl RecycleBin {
+    pub fn new() -> RecycleBin {
+        RecycleBin
+    }
+}
+
+// Fixed and removable drives, as `C:\`
+fn drives() -> Vec<(String, u32)> {
+    let mut buffer = [0u16; 512];
+    let len: DWORD = unsafe { GetLogicalDriveStringsW(buffer.len() as DWORD, buffer.as_mut_ptr()) };
+
+    if len == 0 || len as usize > buffer.len() {
+        return Vec::new();
+    }
+
+    buffer[..len as usize]
+        .split(|c| *c == 0)
+        .filter(|d| !d.is_empty())
+        .map(String::from_utf16_lossy)
+        .map(|d| {
+            let kind: u32 = unsafe { GetDriveTypeW(to_utf16(&d).as_ptr()) };
+            (d, kind)
+        })
+        .filter(|(_, kind)| *kind == DRIVE_FIXED || *kind == DRIVE_REMOVABLE)
+        .collect()
+}
+
+fn shell_error(call: &str, result: i32) -> Error {
+    Error::other(format!("{} failed with 0x{:08X}", call, result))
+}
+
+// The bin of every user lives under a directory named after its SID
+fn current_sid() -> Result<String> {
+    let mut token: HANDLE = null_mut();
+
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut buffer: Vec<u64> = vec![0; 64];
+    let mut returned: DWORD = 0;
+
+    let status: i32 = unsafe {
+        GetTokenInformation(
+            token,
+            TokenUser,
+            buffer.as_mut_ptr() as _,
+            (buffer.len() * size_of::<u64>()) as DWORD,
+            &mut returned,
+        )
+    };
+
+    unsafe { CloseHandle(token); }
+
+    if status == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let token_user: &TOKEN_USER = unsafe { &*(buffer.as_ptr() as *const TOKEN_USER) };
+    let mut sid: LPWSTR = null_mut();
+
+    if unsafe { ConvertSidToStringSidW(token_user.User.Sid, &mut sid) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let len: usize = (0..).take_while(|i| unsafe { *sid.offset(*i) } != 0).count();
+    let string: String = String::from_utf16_lossy(unsafe { from_raw_parts(sid, len) });
+
+    unsafe { LocalFree(sid as _); }
+
+    Ok(string)
+}
+
+// FILETIME in UTC to the local time, like the dates of the .trashinfo files
+fn local_time(filetime: i64) -> Option<NaiveDateTime> {
+    let seconds: i64 = filetime / 10_000_000 - EPOCH_DIFFERENCE;
+    Local.timestamp_opt(seconds, 0).single().map(|d| d.naive_local())
+}
+
+// $I header: version, size of the original and deletion date, then the
+// original path which is not needed here
+fn parse_info(content: &[u8]) -> Option<(u64, Option<NaiveDateTime>)> {
+    let version: u64 = u64::from_le_bytes(content.get(0..8)?.try_into().ok()?);
+
+    if version != 1 && version != 2 {
+        return None;
+    }
+
+    let size: u64 = u64::from_le_bytes(content.get(8..16)?.try_into().ok()?);
+    let deleted: i64 = i64::from_le_bytes(content.get(16..24)?.try_into().ok()?);
+
+    Some((size, local_time(deleted)))
+}
+
+fn tree_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.path().symlink_metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+impl TrashBackend for RecycleBin {
+    // A removable drive without a bin is skipped, a fixed one fails the query
+    fn query(&self) -> Result<Vec<TrashVolume>> {
+        let mut volumes: Vec<TrashVolume> = Vec::new();
+
+        for (drive, kind) in drives() {
+            let mut info: SHQUERYRBINFO = unsafe { zeroed() };
+            info.cbSize = size_of::<SHQUERYRBINFO>() as u32;
+
+            let result = unsafe { SHQueryRecycleBinW(to_utf16(&drive).as_ptr(), &mut info) };
+
+            match (result == S_OK, kind) {
+                (true, _) => volumes.push(TrashVolume {
+                    location: PathBuf::from(drive),
+                    len: info.i64NumItems as u64,
+                    size: info.i64Size as u64,
+                }),
+                (false, DRIVE_REMOVABLE) => continue,
+                (false, _) => return Err(shell_error("SHQueryRecycleBinW", result)),
+            }
+        }
+
+        Ok(volumes)
+    }
+
+    // Every $I file describes the $R file or directory with the same suffix,
+    // the ones whose content is already gone are skipped
+    fn items(&self, volume: &TrashVolume) -> Result<Vec<TrashItem>> {
+        let bin: PathBuf = volume.location.join("$Recycle.Bin").join(current_sid()?);
+        let mut items: Vec<TrashItem> = Vec::new();
+
+        for entry in read_dir(&bin)? {
+            let info: PathBuf = entry?.path();
+
+            let suffix: String = match info.file_name().map(|n| n.to_string_lossy().to_string()) {
+                Some(n) if n.starts_with("$I") => n[2..].to_string(),
+                _ => continue,
+            };
+
+            let path: PathBuf = bin.join(format!("$R{}", suffix));
+
+            let (size, deleted) = match (symlink_metadata(&path), read(&info).ok().as_deref().and_then(parse_info)) {
+                (Ok(m), Some((_, deleted))) if m.is_dir() => (tree_size(&path), deleted),
+                (Ok(_), Some((size, deleted))) => (size, deleted),
+                _ => continue,
+            };
+
+            items.push(TrashItem { volume: volume.location.clone(), path, info, size, deleted });
+        }
+
+        Ok(items)
+    }
+
+    fn purge(&self, volume: &TrashVolume) -> Result<()> {
+        let root: Vec<u16> = to_utf16(&volume.location.to_string_lossy());
+        let result = unsafe { SHEmptyRecycleBinW(null_mut(), root.as_ptr(), SHERB_NOCONFIRMATION) };
+
+        if result != S_OK {
+            return Err(shell_error("SHEmptyRecycleBinW", result));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use std::io::{stdin, stdout, Write};
+
+// Nothing is scheduled for a reboot on Unix, the machine is left running
+pub fn restart() {}
+
+// Without a GUI the messages go to the terminal the cleaner runs in
+pub fn message_box(content: String) {
+    println!("{}", content);
+}
+
+pub fn error_box(content: String) {
+    eprintln!("{}", content);
+}
+
+pub fn confirm_box(content: String) -> bool {
+    print!("{} [y/N] ", content);
+    let _ = stdout().flush();
+
+    let mut answer: String = String::new();
+
+    if stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+pub fn attach_console() {}
@@ -0,0 +1,190 @@
+use std::ffi::OsString;
+#[cfg(windows)]
+use std::fs::set_permissions;
+use std::fs::{copy, File, Metadata, read_dir, remove_dir, remove_file, rename, symlink_metadata};
+use std::io::{Error, ErrorKind, Result};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(windows)]
+use std::os::windows::ffi::OsStrExt;
+#[cfg(windows)]
+use std::os::windows::fs::MetadataExt;
+use std::path::Path;
+#[cfg(windows)]
+use std::ptr::null;
+use std::time::SystemTime;
+#[cfg(unix)]
+use std::time::{Duration, UNIX_EPOCH};
+
+#[cfg(windows)]
+use winapi::um::winbase::{MOVEFILE_DELAY_UNTIL_REBOOT, MoveFileExW};
+#[cfg(windows)]
+use winapi::um::winnt::FILE_ATTRIBUTE_REPARSE_POINT;
+
+use crate::safe_remove::{fingerprint, Fingerprint, Kind, Moved, Policy, remove_contained};
+#[cfg(windows)]
+use crate::secure::owner_sid;
+use crate::vfs::{Filesystem, Stat};
+
+// The real disk, removals go through the handle-relative safe_remove
+pub struct Disk;
+
+// Symlinks, junctions and mounted folders are all reparse points
+#[cfg(windows)]
+fn is_link_metadata(metadata: &Metadata) -> bool {
+    metadata.file_type().is_symlink()
+        || metadata.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0
+}
+
+#[cfg(unix)]
+fn is_link_metadata(metadata: &Metadata) -> bool {
+    metadata.file_type().is_symlink()
+}
+
+#[cfg(unix)]
+fn changed(metadata: &Metadata) -> Option<SystemTime> {
+    match metadata.ctime() {
+        s if s < 0 => None,
+        s => UNIX_EPOCH.checked_add(Duration::new(s as u64, metadata.ctime_nsec() as u32)),
+    }
+}
+
+// The change time of an entry is not in what std reads on Windows
+#[cfg(windows)]
+fn changed(_metadata: &Metadata) -> Option<SystemTime> {
+    None
+}
+
+fn check(path: &Path, expected: Option<&Fingerprint>) -> Result<()> {
+    match expected {
+        Some(e) if fingerprint(path)? != *e => Err(Error::other("file changed since the scan")),
+        _ => Ok(()),
+    }
+}
+
+impl Filesystem for Disk {
+    fn stat(&self, path: &Path) -> Result<Stat> {
+        let metadata: Metadata = symlink_metadata(path)?;
+
+        let kind: Kind = match (is_link_metadata(&metadata), metadata.is_dir()) {
+            (true, _) => Kind::Link,
+            (false, true) => Kind::Dir,
+            (false, false) => Kind::File,
+        };
+
+        Ok(Stat {
+            kind,
+            accessed: metadata.accessed().ok(),
+            created: metadata.created().ok(),
+            changed: changed(&metadata),
+            modified: metadata.modified().ok(),
+            readonly: metadata.permissions().readonly(),
+        })
+    }
+
+    fn fingerprint(&self, path: &Path) -> Result<Fingerprint> {
+        fingerprint(path)
+    }
+
+    fn names(&self, path: &Path) -> Result<Vec<OsString>> {
+        read_dir(path)?.map(|e| e.map(|e| e.file_name())).collect()
+    }
+
+    #[cfg(windows)]
+    fn owner(&self, path: &Path) -> Result<String> {
+        owner_sid(path)
+    }
+
+    #[cfg(unix)]
+    fn owner(&self, path: &Path) -> Result<String> {
+        symlink_metadata(path).map(|m| m.uid().to_string())
+    }
+
+    // A link to a directory is one on Windows
+    fn remove(&self, path: &Path, kind: Kind, expected: Option<&Fingerprint>) -> Result<()> {
+        check(path, expected)?;
+
+        match kind {
+            Kind::Dir => remove_dir(path),
+            _ => remove_file(path).or_else(|e| remove_dir(path).map_err(|_| e)),
+        }
+    }
+
+    // Another volume takes a copy, on the disk before the file goes
+    fn move_to(&self, path: &Path, expected: &Fingerprint, destination: &Path) -> Result<Moved> {
+        check(path, Some(expected))?;
+
+        match rename(path, destination) {
+            Err(ref e) if e.kind() == ErrorKind::CrossesDevices => {}
+            status => return status.map(|_| Moved::Renamed),
+        }
+
+        copy(path, destination)?;
+
+        let status: Result<()> = File::open(destination)
+            .and_then(|f| f.sync_all())
+            .and_then(|_| self.remove(path, Kind::File, Some(expected)));
+
+        if status.is_err() {
+            let _ = remove_file(destination);
+        }
+
+        status.map(|_| Moved::Copied)
+    }
+
+    // set_permissions follows links, never touch the target of a link
+    #[cfg(windows)]
+    fn clear_readonly(&self, path: &Path) -> Result<()> {
+        let metadata: Metadata = symlink_metadata(path)?;
+
+        if is_link_metadata(&metadata) {
+            return Ok(());
+        }
+
+        let mut permissions = metadata.permissions();
+        permissions.set_readonly(false);
+        set_permissions(path, permissions)
+    }
+
+    // Write permission on a file does not matter for unlinking it
+    #[cfg(unix)]
+    fn clear_readonly(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn schedule_on_reboot(&self, path: &Path, destination: Option<&Path>) -> Result<()> {
+        let existing_filename: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let new_filename: Option<Vec<u16>> = destination
+            .map(|d| d.as_os_str().encode_wide().chain(Some(0)).collect());
+
+        let status: i32 = unsafe {
+            MoveFileExW(
+                existing_filename.as_ptr(),
+                match new_filename {
+                    Some(ref n) => n.as_ptr(),
+                    None => null(),
+                },
+                MOVEFILE_DELAY_UNTIL_REBOOT,
+            )
+        };
+
+        if status == 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    // Nothing delays a removal until reboot, the deferred queue runs it at
+    // the next start. The clean only brings busy entries here, one gone since
+    // has nothing to wait for.
+    #[cfg(unix)]
+    fn schedule_on_reboot(&self, path: &Path, _destination: Option<&Path>) -> Result<()> {
+        symlink_metadata(path).map(|_| ())
+    }
+
+    fn remove_contained(&self, root: &Path, path: &Path, policy: &mut dyn Policy) -> Result<()> {
+        remove_contained(root, path, policy)
+    }
+}
@@ -0,0 +1,158 @@
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::io::{Error, Result};
+use std::path::{Path, PathBuf};
+
+#[cfg(windows)]
+use winapi::shared::winerror::{ERROR_ACCESS_DENIED, ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION};
+
+use crate::safe_remove::{Fingerprint, Kind, Moved};
+use crate::vfs::{Filesystem, Stat};
+
+// How the removal of an entry fails
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    // Refused now and at reboot
+    AccessDenied,
+    // Another process opened it without sharing the deletion
+    SharingViolation,
+    // A process holds a lock on it, or runs it
+    InUse,
+    // Refused until the read-only attribute is cleared
+    ReadOnly,
+}
+
+impl Fault {
+    // The error the system gives for it
+    #[cfg(windows)]
+    pub fn error(&self) -> Error {
+        let code: u32 = match self {
+            Fault::AccessDenied | Fault::ReadOnly => ERROR_ACCESS_DENIED,
+            Fault::SharingViolation => ERROR_SHARING_VIOLATION,
+            Fault::InUse => ERROR_LOCK_VIOLATION,
+        };
+
+        Error::from_raw_os_error(code as i32)
+    }
+
+    #[cfg(unix)]
+    pub fn error(&self) -> Error {
+        let code: i32 = match self {
+            Fault::AccessDenied | Fault::ReadOnly => libc::EACCES,
+            Fault::SharingViolation => libc::EBUSY,
+            Fault::InUse => libc::ETXTBSY,
+        };
+
+        Error::from_raw_os_error(code)
+    }
+}
+
+struct Injected {
+    path: PathBuf,
+    fault: Fault,
+    // Failed attempts left, forever when None
+    attempts: Option<u32>,
+}
+
+// Wraps another backend and makes the removal of chosen entries fail. A fault
+// can last a number of attempts, like a lock released a moment later. Every
+// failure is recorded.
+pub struct FaultyFs<F: Filesystem> {
+    inner: F,
+    faults: RefCell<Vec<Injected>>,
+    failures: RefCell<Vec<(PathBuf, Fault)>>,
+}
+
+impl<F: Filesystem> FaultyFs<F> {
+    pub fn new(inner: F) -> FaultyFs<F> {
+        FaultyFs {
+            inner,
+            faults: RefCell::new(Vec::new()),
+            failures: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn fail(self, path: &str, fault: Fault) -> FaultyFs<F> {
+        self.inject(path, fault, None)
+    }
+
+    pub fn fail_times(self, path: &str, fault: Fault, attempts: u32) -> FaultyFs<F> {
+        self.inject(path, fault, Some(attempts))
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    pub fn failures(&self) -> Vec<(PathBuf, Fault)> {
+        self.failures.borrow().clone()
+    }
+
+    fn inject(self, path: &str, fault: Fault, attempts: Option<u32>) -> FaultyFs<F> {
+        self.faults.borrow_mut().push(Injected { path: PathBuf::from(path), fault, attempts });
+        self
+    }
+
+    // Counts the attempt against the fault of the path, if it still has one
+    fn attempt(&self, path: &Path) -> Result<()> {
+        let mut faults = self.faults.borrow_mut();
+
+        let injected: &mut Injected = match faults.iter_mut().find(|i| i.path == path && i.attempts != Some(0)) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+
+        if let Some(ref mut a) = injected.attempts {
+            *a -= 1;
+        }
+
+        self.failures.borrow_mut().push((path.to_path_buf(), injected.fault));
+        Err(injected.fault.error())
+    }
+}
+
+impl<F: Filesystem> Filesystem for FaultyFs<F> {
+    fn stat(&self, path: &Path) -> Result<Stat> {
+        self.inner.stat(path)
+    }
+
+    fn fingerprint(&self, path: &Path) -> Result<Fingerprint> {
+        self.inner.fingerprint(path)
+    }
+
+    fn names(&self, path: &Path) -> Result<Vec<OsString>> {
+        self.inner.names(path)
+    }
+
+    fn owner(&self, path: &Path) -> Result<String> {
+        self.inner.owner(path)
+    }
+
+    fn remove(&self, path: &Path, kind: Kind, expected: Option<&Fingerprint>) -> Result<()> {
+        self.attempt(path)?;
+        self.inner.remove(path, kind, expected)
+    }
+
+    fn move_to(&self, path: &Path, expected: &Fingerprint, destination: &Path) -> Result<Moved> {
+        self.attempt(path)?;
+        self.inner.move_to(path, expected, destination)
+    }
+
+    fn clear_readonly(&self, path: &Path) -> Result<()> {
+        self.faults.borrow_mut().retain(|i| i.path != path || i.fault != Fault::ReadOnly);
+        self.inner.clear_readonly(path)
+    }
+
+    // Only an access denied entry cannot be scheduled, the others are free at
+    // reboot
+    fn schedule_on_reboot(&self, path: &Path, destination: Option<&Path>) -> Result<()> {
+        let denied: bool = self.faults.borrow().iter().any(|i| i.path == path && i.fault == Fault::AccessDenied);
+
+        if denied {
+            self.failures.borrow_mut().push((path.to_path_buf(), Fault::AccessDenied));
+            return Err(Fault::AccessDenied.error());
+        }
+
+        self.inner.schedule_on_reboot(path, destination)
+    }
+}
@@ -0,0 +1,285 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::safe_remove::{Fingerprint, Kind, Moved};
+use crate::vfs::{Filesystem, Stat};
+
+#[derive(Clone, Copy)]
+struct Node {
+    kind: Kind,
+    size: u64,
+    accessed: SystemTime,
+    modified: SystemTime,
+    readonly: bool,
+    id: u64,
+}
+
+// A tree held in memory, for the tests and the replay of a snapshot, so the
+// clean can run without a disk. Ages are relative to the time the entry is
+// added, parents are created as needed and what is scheduled for reboot only
+// happens on `reboot`.
+pub struct MemoryFs {
+    nodes: RefCell<BTreeMap<PathBuf, Node>>,
+    scheduled: RefCell<Vec<(PathBuf, Option<PathBuf>)>>,
+    next_id: Cell<u64>,
+    owners: BTreeMap<PathBuf, String>,
+    // Volumes other than the one of the root, by their mount point
+    mounts: Vec<PathBuf>,
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs {
+            nodes: RefCell::new(BTreeMap::new()),
+            scheduled: RefCell::new(Vec::new()),
+            next_id: Cell::new(1),
+            owners: BTreeMap::new(),
+            mounts: Vec::new(),
+        }
+    }
+
+    // Everything below `path` is on a volume of its own
+    #[cfg(test)]
+    pub fn mount(mut self, path: &str) -> MemoryFs {
+        self.mounts.push(PathBuf::from(path));
+        self
+    }
+
+    pub fn dir(self, path: &str) -> MemoryFs {
+        self.insert(Path::new(path), Kind::Dir, 0, Duration::ZERO);
+        self
+    }
+
+    pub fn file(self, path: &str, size: u64, age: Duration) -> MemoryFs {
+        self.insert(Path::new(path), Kind::File, size, age);
+        self
+    }
+
+    pub fn link(self, path: &str) -> MemoryFs {
+        self.insert(Path::new(path), Kind::Link, 0, Duration::ZERO);
+        self
+    }
+
+    // Every time stamp of an entry, directories are added brand new
+    pub fn aged(self, path: &str, age: Duration) -> MemoryFs {
+        if let Some(n) = self.nodes.borrow_mut().get_mut(Path::new(path)) {
+            n.modified = SystemTime::now() - age;
+            n.accessed = n.modified;
+        }
+
+        self
+    }
+
+    // Owned by the account `owner`, an entry nobody owns has no owner to read
+    pub fn owned(mut self, path: &str, owner: &str) -> MemoryFs {
+        self.owners.insert(PathBuf::from(path), owner.to_string());
+        self
+    }
+
+    // Read since it was last written, its other time stamps stay
+    #[cfg(test)]
+    pub fn accessed(self, path: &str, age: Duration) -> MemoryFs {
+        if let Some(n) = self.nodes.borrow_mut().get_mut(Path::new(path)) {
+            n.accessed = SystemTime::now() - age;
+        }
+
+        self
+    }
+
+    #[cfg(test)]
+    pub fn readonly(self, path: &str) -> MemoryFs {
+        if let Some(n) = self.nodes.borrow_mut().get_mut(Path::new(path)) {
+            n.readonly = true;
+        }
+
+        self
+    }
+
+    // Written again now, the entry stays the same one
+    #[cfg(test)]
+    pub fn rewrite(&self, path: &str, size: u64) {
+        if let Some(n) = self.nodes.borrow_mut().get_mut(Path::new(path)) {
+            n.size = size;
+            n.modified = SystemTime::now();
+        }
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        self.nodes.borrow().contains_key(path)
+    }
+
+    #[cfg(test)]
+    pub fn scheduled(&self) -> Vec<(PathBuf, Option<PathBuf>)> {
+        self.scheduled.borrow().clone()
+    }
+
+    // What a reboot does with the scheduled entries, in the order they were
+    // scheduled. A directory still holding something stays.
+    #[cfg(test)]
+    pub fn reboot(&self) {
+        let scheduled: Vec<(PathBuf, Option<PathBuf>)> = self.scheduled.borrow_mut().drain(..).collect();
+        let mut nodes = self.nodes.borrow_mut();
+
+        for (path, destination) in scheduled {
+            if nodes.keys().any(|k| k.parent() == Some(&path)) {
+                continue;
+            }
+
+            let node: Node = match nodes.remove(&path) {
+                Some(n) => n,
+                None => continue,
+            };
+
+            if let Some(d) = destination {
+                nodes.insert(d, node);
+            }
+        }
+    }
+
+    fn insert(&self, path: &Path, kind: Kind, size: u64, age: Duration) {
+        if let Some(parent) = path.parent().filter(|p| !self.exists(p)) {
+            self.insert(parent, Kind::Dir, 0, Duration::ZERO);
+        }
+
+        let id: u64 = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        let modified: SystemTime = SystemTime::now() - age;
+        let node: Node = Node { kind, size, accessed: modified, modified, readonly: false, id };
+
+        self.nodes.borrow_mut().insert(path.to_path_buf(), node);
+    }
+
+    // The deepest mount point holding the path, 0 for the root volume
+    fn device(&self, path: &Path) -> u64 {
+        self.mounts.iter().enumerate()
+            .filter(|(_, m)| path.starts_with(m))
+            .max_by_key(|(_, m)| m.components().count())
+            .map(|(i, _)| i as u64 + 1)
+            .unwrap_or(0)
+    }
+
+    fn node(&self, path: &Path) -> Result<Node> {
+        self.nodes.borrow().get(path).copied().ok_or(Error::from(ErrorKind::NotFound))
+    }
+
+    fn check(&self, path: &Path, expected: Option<&Fingerprint>) -> Result<()> {
+        match expected {
+            Some(e) if self.fingerprint(path)? != *e => Err(Error::other("file changed since the scan")),
+            _ => Ok(()),
+        }
+    }
+
+    // The content of a directory sorts right after it
+    fn children(&self, path: &Path) -> Vec<PathBuf> {
+        self.nodes.borrow().range(path.to_path_buf()..)
+            .map(|(k, _)| k)
+            .take_while(|k| k.starts_with(path))
+            .filter(|k| k.parent() == Some(path))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Filesystem for MemoryFs {
+    fn stat(&self, path: &Path) -> Result<Stat> {
+        let node: Node = self.node(path)?;
+
+        // Entries are written once, when they are added
+        Ok(Stat {
+            kind: node.kind,
+            accessed: Some(node.accessed),
+            created: Some(node.modified),
+            changed: Some(node.modified),
+            modified: Some(node.modified),
+            readonly: node.readonly,
+        })
+    }
+
+    fn fingerprint(&self, path: &Path) -> Result<Fingerprint> {
+        let node: Node = self.node(path)?;
+        let modified: i64 = node.modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0);
+
+        Ok(Fingerprint { dev: self.device(path), id: node.id, size: node.size, modified })
+    }
+
+    fn names(&self, path: &Path) -> Result<Vec<OsString>> {
+        if self.node(path)?.kind != Kind::Dir {
+            return Err(Error::from(ErrorKind::NotADirectory));
+        }
+
+        Ok(self.children(path).iter().filter_map(|c| c.file_name().map(|n| n.to_os_string())).collect())
+    }
+
+    fn owner(&self, path: &Path) -> Result<String> {
+        self.node(path)?;
+        self.owners.get(path).cloned().ok_or(Error::other("no owner recorded"))
+    }
+
+    fn remove(&self, path: &Path, kind: Kind, expected: Option<&Fingerprint>) -> Result<()> {
+        if self.node(path)?.kind != kind {
+            return Err(Error::other("entry changed kind since the scan"));
+        }
+
+        self.check(path, expected)?;
+
+        if !self.children(path).is_empty() {
+            return Err(Error::from(ErrorKind::DirectoryNotEmpty));
+        }
+
+        self.nodes.borrow_mut().remove(path);
+        Ok(())
+    }
+
+    // Onto another volume the file becomes a new entry
+    fn move_to(&self, path: &Path, expected: &Fingerprint, destination: &Path) -> Result<Moved> {
+        self.check(path, Some(expected))?;
+
+        if self.exists(destination) {
+            return Err(Error::from(ErrorKind::AlreadyExists));
+        }
+
+        if let Some(parent) = destination.parent().filter(|p| !self.exists(p)) {
+            self.insert(parent, Kind::Dir, 0, Duration::ZERO);
+        }
+
+        let moved: Moved = match self.device(path) == self.device(destination) {
+            true => Moved::Renamed,
+            false => Moved::Copied,
+        };
+
+        let mut nodes = self.nodes.borrow_mut();
+
+        if let Some(mut node) = nodes.remove(path) {
+            if moved == Moved::Copied {
+                node.id = self.next_id.get();
+                self.next_id.set(node.id + 1);
+            }
+
+            nodes.insert(destination.to_path_buf(), node);
+        }
+
+        Ok(moved)
+    }
+
+    fn clear_readonly(&self, path: &Path) -> Result<()> {
+        match self.nodes.borrow_mut().get_mut(path) {
+            Some(n) => {
+                n.readonly = false;
+                Ok(())
+            }
+            None => Err(Error::from(ErrorKind::NotFound)),
+        }
+    }
+
+    fn schedule_on_reboot(&self, path: &Path, destination: Option<&Path>) -> Result<()> {
+        self.node(path)?;
+        self.scheduled.borrow_mut().push((path.to_path_buf(), destination.map(|d| d.to_path_buf())));
+
+        Ok(())
+    }
+}
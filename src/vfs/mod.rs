@@ -0,0 +1,169 @@
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Component, Path};
+use std::time::SystemTime;
+
+use crate::safe_remove::{Disposal, Fingerprint, Kind, Moved, Policy};
+
+pub use self::disk::Disk;
+
+mod disk;
+#[cfg(test)]
+pub mod faults;
+pub mod memory;
+
+// What the clean needs to know about an entry, a link is never followed
+#[derive(Clone, Copy, Debug)]
+pub struct Stat {
+    pub kind: Kind,
+    pub accessed: Option<SystemTime>,
+    pub created: Option<SystemTime>,
+    pub changed: Option<SystemTime>,
+    pub modified: Option<SystemTime>,
+    pub readonly: bool,
+}
+
+// The file system a clean runs on. Removal goes through `remove_contained`,
+// built on the other operations unless the backend has a safer way.
+pub trait Filesystem {
+    fn stat(&self, path: &Path) -> Result<Stat>;
+
+    fn fingerprint(&self, path: &Path) -> Result<Fingerprint>;
+
+    fn names(&self, path: &Path) -> Result<Vec<OsString>>;
+
+    // The account owning an entry, its uid on Unix and its SID on Windows
+    fn owner(&self, path: &Path) -> Result<String>;
+
+    // `expected` is checked again just before the entry goes
+    fn remove(&self, path: &Path, kind: Kind, expected: Option<&Fingerprint>) -> Result<()>;
+
+    // Copied when the destination is on another volume
+    fn move_to(&self, path: &Path, expected: &Fingerprint, destination: &Path) -> Result<Moved>;
+
+    fn clear_readonly(&self, path: &Path) -> Result<()>;
+
+    // Removed, or moved to `destination`, at the next reboot
+    fn schedule_on_reboot(&self, path: &Path, destination: Option<&Path>) -> Result<()>;
+
+    fn remove_contained(&self, root: &Path, path: &Path, policy: &mut dyn Policy) -> Result<()> {
+        remove_tree(self, root, path, policy)
+    }
+}
+
+// The removal of safe_remove done by path: every directory between the root
+// and the entry must still be one, links are removed and never followed.
+// Shredding only means something on a disk, which has its own removal.
+fn remove_tree<F: Filesystem + ?Sized>(fs: &F, root: &Path, path: &Path, policy: &mut dyn Policy) -> Result<()> {
+    let relative: &Path = match path.strip_prefix(root) {
+        Ok(r) => r,
+        _ => return Err(Error::new(ErrorKind::InvalidInput, "path outside of the component root")),
+    };
+
+    if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Err(Error::new(ErrorKind::InvalidInput, "unexpected path component"));
+    }
+
+    // Removing the root itself (min_depth 0), only its parent has to hold
+    let first: &Path = match relative.as_os_str().is_empty() {
+        true => root.parent().ok_or(Error::new(ErrorKind::InvalidInput, "refusing to remove a volume root"))?,
+        false => root,
+    };
+
+    let parents = path.ancestors().skip(1).take_while(|a| a.starts_with(first));
+
+    for directory in parents {
+        if fs.stat(directory)?.kind != Kind::Dir {
+            return Err(Error::other("directory has been replaced by a link"));
+        }
+    }
+
+    remove_entry(fs, path, policy)
+}
+
+fn remove_entry<F: Filesystem + ?Sized>(fs: &F, path: &Path, policy: &mut dyn Policy) -> Result<()> {
+    match fs.stat(path)?.kind {
+        Kind::Link => {
+            fs.remove(path, Kind::Link, None)?;
+            policy.link_removed(path);
+        }
+        Kind::File => {
+            let current: Fingerprint = fs.fingerprint(path)?;
+
+            if !policy.accept(path, &current) {
+                return Err(Error::other("file changed since the scan"));
+            }
+
+            let disposal: Disposal = policy.disposal(path, &current);
+
+            match disposal {
+                Disposal::Delete | Disposal::Shred(_) => fs.remove(path, Kind::File, Some(&current))?,
+                Disposal::Move(ref destination) => {
+                    if fs.move_to(path, &current, destination)? == Moved::Copied {
+                        policy.copied(path);
+                    }
+                }
+            }
+
+            policy.disposed(path, &disposal, &current);
+        }
+        Kind::Dir => {
+            if !policy.enter(path) {
+                return Err(Error::other("directory excluded from the removal"));
+            }
+
+            for n in fs.names(path)? {
+                let _ = remove_entry(fs, &path.join(&n), policy);
+            }
+
+            fs.remove(path, Kind::Dir, None)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Depth first, a directory before its content, which is only read once the
+// callback is done with the directory. Links are never followed, what `enter`
+// refuses is skipped with its content.
+pub fn walk(
+    fs: &dyn Filesystem,
+    root: &Path,
+    depths: (usize, usize),
+    enter: &dyn Fn(&Path) -> bool,
+    callback: &mut dyn FnMut(&Path),
+) {
+    visit(fs, root, 0, depths, enter, callback);
+}
+
+fn visit(
+    fs: &dyn Filesystem,
+    path: &Path,
+    depth: usize,
+    (min_depth, max_depth): (usize, usize),
+    enter: &dyn Fn(&Path) -> bool,
+    callback: &mut dyn FnMut(&Path),
+) {
+    if !enter(path) {
+        return;
+    }
+
+    if depth >= min_depth {
+        callback(path);
+    }
+
+    if depth >= max_depth || !fs.stat(path).map(|s| s.kind == Kind::Dir).unwrap_or(false) {
+        return;
+    }
+
+    let mut names: Vec<OsString> = match fs.names(path) {
+        Ok(n) => n,
+        _ => return,
+    };
+
+    names.sort();
+
+    for n in names {
+        visit(fs, &path.join(n), depth + 1, (min_depth, max_depth), enter, callback);
+    }
+}
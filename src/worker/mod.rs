@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+use crate::clean::{Confirm, execute, Plan, probe_system_vars, scan, Summary};
+use crate::settings::{parse_bool, Settings};
+
+#[cfg(unix)]
+pub use self::unix::{authorized, client_process, connect, Connection, Listener};
+#[cfg(windows)]
+pub use self::windows::{authorized, client_process, connect, Connection, launch, Listener};
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod windows;
+
+// The privileged side of KpTemp. A front end sends one tab-separated request
+// per line and reads tab-separated answer lines until `end`:
+//
+//   status                                  -> status <idle|busy> [what]
+//   scan <old>                              -> target <root> <files> <bytes>, skipped <rule> <reason>, note <text>
+//   clean <old> <quarantine> [<root> ...]   -> done <files> <bytes> <action> <reboot> <report>
+//
+// Requests never carry a path to delete, only roots of a fresh scan are
+// accepted, so the worker cleans nothing the rule set and safety checks refuse.
+// Anyone allowed to connect may ask for the status, scan and clean are only
+// served to the callers `authorized` accepts. `quarantine` can only turn the
+// quarantine on, never off when the settings ask for it. `reboot` is 1 when
+// files were left for the next start of the system.
+#[derive(Default)]
+struct State {
+    busy: Option<String>,
+}
+
+// A job that panicked must neither keep the worker busy nor lock out status
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Marks the worker busy until dropped, however the job ends
+struct Busy<'a> {
+    state: &'a Mutex<State>,
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        lock(self.state).busy = None;
+    }
+}
+
+// Serves until stopped. With `launcher`, the worker was started elevated for
+// that process alone: its connection is served without further check, then
+// the worker exits, connections of other processes are dropped.
+pub fn serve(launcher: Option<u32>) -> Result<()> {
+    let mut listener: Listener = Listener::bind()?;
+    let state: Arc<Mutex<State>> = Arc::new(Mutex::new(State::default()));
+
+    loop {
+        let connection: Connection = listener.accept()?;
+
+        if let Some(pid) = launcher {
+            match client_process(&connection) {
+                Ok(p) if p == pid => return handle_connection(connection, &state, true),
+                _ => continue,
+            }
+        }
+
+        let state: Arc<Mutex<State>> = state.clone();
+
+        thread::spawn(move || {
+            let _ = handle_connection(connection, &state, false);
+        });
+    }
+}
+
+fn handle_connection(connection: Connection, state: &Mutex<State>, trusted: bool) -> Result<()> {
+    let mut output: Connection = connection.try_clone()?;
+    let reader = BufReader::new(connection);
+
+    for line in reader.lines() {
+        let line: String = line?;
+        let request: Vec<&str> = line.split('\t').collect();
+
+        // Checked again for every request, after it was read
+        let allowed: Result<bool> = match request.first() {
+            Some(&"status") => Ok(true),
+            _ if trusted => Ok(true),
+            _ => authorized(&output),
+        };
+
+        let answer: Result<Vec<Vec<String>>> = match allowed {
+            Ok(true) => handle_request(&request, state),
+            Ok(false) => Err(Error::new(ErrorKind::PermissionDenied, "not allowed to drive the worker")),
+            Err(e) => Err(e),
+        };
+
+        match answer {
+            Ok(answer) => {
+                for a in answer {
+                    output.write_all(format!("{}\n", a.join("\t")).as_bytes())?;
+                }
+            }
+            Err(e) => output.write_all(format!("error\t{}\n", e).as_bytes())?,
+        }
+
+        output.write_all(b"end\n")?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn parse_old(value: Option<&&str>) -> Result<bool> {
+    value.and_then(|v| parse_bool(v)).ok_or_else(|| invalid("expected 0 or 1 for windows.old"))
+}
+
+fn handle_request(request: &[&str], state: &Mutex<State>) -> Result<Vec<Vec<String>>> {
+    match request.first() {
+        Some(&"status") => {
+            let state = lock(state);
+
+            Ok(vec![match state.busy {
+                Some(ref what) => vec!["status".to_string(), "busy".to_string(), what.clone()],
+                None => vec!["status".to_string(), "idle".to_string()],
+            }])
+        }
+        Some(&"scan") => {
+            let old: bool = parse_old(request.get(1))?;
+            let plan: Plan = exclusive(state, "scan", || probe_scan(old, &Settings::load(), None))?;
+
+            let mut answer: Vec<Vec<String>> = Vec::new();
+
+            for (root, len, size) in plan.targets() {
+                answer.push(vec!["target".to_string(), root.display().to_string(), len.to_string(), size.to_string()]);
+            }
+
+            for (rule, reason) in plan.skipped() {
+                answer.push(vec!["skipped".to_string(), rule, reason]);
+            }
+
+            for note in plan.notes() {
+                answer.push(vec!["note".to_string(), note.clone()]);
+            }
+
+            Ok(answer)
+        }
+        Some(&"clean") => {
+            let old: bool = parse_old(request.get(1))?;
+            let quarantine: bool = request.get(2)
+                .and_then(|v| parse_bool(v))
+                .ok_or_else(|| invalid("expected 0 or 1 for the quarantine"))?;
+            let roots: Vec<PathBuf> = request[3..].iter().map(PathBuf::from).collect();
+            let summary: Summary = exclusive(state, "clean", || clean_roots(old, quarantine, &roots))??;
+
+            Ok(vec![vec![
+                "done".to_string(),
+                summary.len.to_string(),
+                summary.size.to_string(),
+                summary.action.to_string(),
+                (summary.reboot as u8).to_string(),
+                summary.report.display().to_string(),
+            ]])
+        }
+        _ => Err(invalid("unknown request")),
+    }
+}
+
+// Only one scan or clean runs at a time, status stays available meanwhile
+fn exclusive<T, F: FnOnce() -> T>(state: &Mutex<State>, what: &str, job: F) -> Result<T> {
+    let _busy: Busy = {
+        let mut locked = lock(state);
+
+        if let Some(ref current) = locked.busy {
+            return Err(Error::new(ErrorKind::WouldBlock, format!("busy with {}", current)));
+        }
+
+        locked.busy = Some(what.to_string());
+
+        Busy { state }
+    };
+
+    Ok(job())
+}
+
+// Nobody sits in front of the worker to be warned about a bad layout, the
+// variables that are not a directory are left out with a note instead
+fn probe_scan(old: bool, settings: &Settings, confirm: Option<Confirm>) -> Plan {
+    let mut notes: Vec<String> = Vec::new();
+    let system_vars: HashMap<&'static str, PathBuf> = probe_system_vars(&mut notes);
+
+    let mut plan: Plan = scan(old, settings, &system_vars, confirm);
+    plan.add_notes(notes);
+
+    plan
+}
+
+// Applications are never forced to close from here, the rules they hold are
+// skipped instead. Roots must all come from the scan made for this request.
+fn clean_roots(old: bool, quarantine: bool, roots: &[PathBuf]) -> Result<Summary> {
+    let mut settings: Settings = Settings::load();
+    settings.quarantine |= quarantine;
+
+    let mut plan: Plan = probe_scan(old, &settings, Some(&mut |_: &[String], _| false));
+    let targets: Vec<PathBuf> = plan.targets().into_iter().map(|t| t.0).collect();
+
+    if let Some(root) = roots.iter().find(|r| !targets.contains(r)) {
+        return Err(invalid(&format!("{} is not a cleaning target", root.display())));
+    }
+
+    if !roots.is_empty() {
+        plan.retain(roots);
+    }
+
+    execute(plan, &settings)
+}
+
+// Sends one request to the worker and returns its answer lines
+pub fn request(fields: &[&str]) -> Result<Vec<Vec<String>>> {
+    exchange(connect()?, fields)
+}
+
+// The same, a worker is started elevated for it when none is running
+#[cfg(windows)]
+pub fn request_or_launch(fields: &[&str]) -> Result<Vec<Vec<String>>> {
+    match connect() {
+        Ok(connection) => exchange(connection, fields),
+        Err(_) => exchange(launch()?, fields),
+    }
+}
+
+fn exchange(mut connection: Connection, fields: &[&str]) -> Result<Vec<Vec<String>>> {
+    connection.write_all(format!("{}\n", fields.join("\t")).as_bytes())?;
+    connection.flush()?;
+
+    let mut answer: Vec<Vec<String>> = Vec::new();
+
+    for line in BufReader::new(connection).lines() {
+        let line: String = line?;
+
+        if line == "end" {
+            break;
+        }
+
+        answer.push(line.split('\t').map(|f| f.to_string()).collect());
+    }
+
+    Ok(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    use super::*;
+
+    #[test]
+    fn a_panicking_job_leaves_the_worker_idle() {
+        let state: Mutex<State> = Mutex::new(State::default());
+
+        let result = catch_unwind(AssertUnwindSafe(|| exclusive(&state, "clean", || panic!("job failed"))));
+        assert!(result.is_err());
+        assert!(lock(&state).busy.is_none());
+
+        assert_eq!(exclusive(&state, "scan", || 1).unwrap(), 1);
+    }
+}
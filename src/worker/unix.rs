@@ -0,0 +1,140 @@
+use std::ffi::{CStr, CString};
+use std::fs::{Permissions, remove_file, set_permissions, symlink_metadata};
+use std::io::{Error, ErrorKind, Result};
+use std::mem::{size_of, zeroed};
+use std::os::unix::fs::{chown, FileTypeExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::ptr::null_mut;
+
+const SOCKET_PATH: &str = "/run/kptemp.sock";
+
+// Members of this group may drive the worker besides root
+const GROUP: &str = "kptemp";
+
+pub type Connection = UnixStream;
+
+pub struct Listener {
+    listener: UnixListener,
+}
+
+fn group_id() -> Option<libc::gid_t> {
+    let name: CString = CString::new(GROUP).ok()?;
+    let group: *mut libc::group = unsafe { libc::getgrnam(name.as_ptr()) };
+
+    match group.is_null() {
+        true => None,
+        false => Some(unsafe { (*group).gr_gid }),
+    }
+}
+
+impl Listener {
+    pub fn bind() -> Result<Listener> {
+        // A socket left by a previous worker is replaced, anything else is kept
+        if let Ok(metadata) = symlink_metadata(SOCKET_PATH) {
+            if !metadata.file_type().is_socket() {
+                return Err(Error::new(ErrorKind::AlreadyExists, SOCKET_PATH));
+            }
+
+            remove_file(SOCKET_PATH)?;
+        }
+
+        let listener: UnixListener = UnixListener::bind(SOCKET_PATH)?;
+
+        // Only root and the group may connect, every request is still checked
+        // against the credentials of the caller
+        let mode: u32 = match group_id() {
+            Some(gid) => {
+                chown(SOCKET_PATH, Some(0), Some(gid))?;
+                0o660
+            }
+            None => 0o600,
+        };
+
+        set_permissions(SOCKET_PATH, Permissions::from_mode(mode))?;
+
+        Ok(Listener { listener })
+    }
+
+    pub fn accept(&mut self) -> Result<Connection> {
+        self.listener.accept().map(|(stream, _)| stream)
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = remove_file(SOCKET_PATH);
+    }
+}
+
+pub fn connect() -> Result<Connection> {
+    UnixStream::connect(SOCKET_PATH)
+}
+
+fn peer_credentials(connection: &Connection) -> Result<libc::ucred> {
+    let mut credentials: libc::ucred = unsafe { zeroed() };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+
+    let status: i32 = unsafe {
+        libc::getsockopt(
+            connection.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if status != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(credentials)
+}
+
+// The process at the other end of the connection
+pub fn client_process(connection: &Connection) -> Result<u32> {
+    peer_credentials(connection).map(|c| c.pid as u32)
+}
+
+// Every group of the account, its primary one included
+fn groups_of(uid: libc::uid_t) -> Result<Vec<libc::gid_t>> {
+    let mut passwd: libc::passwd = unsafe { zeroed() };
+    let mut buffer: Vec<libc::c_char> = vec![0; 16384];
+    let mut result: *mut libc::passwd = null_mut();
+
+    let status: i32 = unsafe { libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+
+    if status != 0 || result.is_null() {
+        return Err(Error::new(ErrorKind::NotFound, format!("no account for uid {}", uid)));
+    }
+
+    let name: &CStr = unsafe { CStr::from_ptr(passwd.pw_name) };
+    let mut groups: Vec<libc::gid_t> = vec![0; 256];
+    let mut count: libc::c_int = groups.len() as libc::c_int;
+
+    if unsafe { libc::getgrouplist(name.as_ptr(), passwd.pw_gid, groups.as_mut_ptr(), &mut count) } < 0 {
+        return Err(Error::other(format!("too many groups for uid {}", uid)));
+    }
+
+    groups.truncate(count as usize);
+
+    Ok(groups)
+}
+
+// Root, the account running the worker and the members of the group may scan
+// and clean, the socket permissions aside
+pub fn authorized(connection: &Connection) -> Result<bool> {
+    let credentials: libc::ucred = peer_credentials(connection)?;
+
+    if credentials.uid == 0 || credentials.uid == unsafe { libc::geteuid() } {
+        return Ok(true);
+    }
+
+    let gid: libc::gid_t = match group_id() {
+        Some(g) => g,
+        None => return Ok(false),
+    };
+
+    Ok(credentials.gid == gid || groups_of(credentials.uid)?.contains(&gid))
+}
@@ -0,0 +1,274 @@
+use std::env::current_exe;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, Result};
+use std::mem::{size_of, zeroed};
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::process;
+use std::ptr::null_mut;
+use std::slice::from_raw_parts;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use winapi::shared::minwindef::{BOOL, DWORD};
+use winapi::shared::sddl::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1};
+use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::minwinbase::{SECURITY_ATTRIBUTES, STILL_ACTIVE};
+use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW, ImpersonateNamedPipeClient};
+use winapi::um::processthreadsapi::{GetCurrentThread, GetExitCodeProcess, OpenThreadToken};
+use winapi::um::securitybaseapi::{GetTokenInformation, IsWellKnownSid, RevertToSelf};
+use winapi::um::shellapi::{SEE_MASK_NOASYNC, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW, ShellExecuteExW};
+use winapi::um::winbase::{
+    FILE_FLAG_FIRST_PIPE_INSTANCE, GetNamedPipeClientProcessId, LocalFree, PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use winapi::um::winnt::{
+    HANDLE, PSECURITY_DESCRIPTOR, SE_GROUP_ENABLED, SE_GROUP_USE_FOR_DENY_ONLY, SID_AND_ATTRIBUTES, TOKEN_ELEVATION, TOKEN_GROUPS,
+    TOKEN_INFORMATION_CLASS, TOKEN_QUERY, TOKEN_USER, TokenElevation, TokenGroups, TokenUser, WinBuiltinAdministratorsSid,
+    WinLocalSystemSid,
+};
+use winapi::um::winuser::SW_HIDE;
+
+use crate::utils::to_utf16;
+
+const PIPE_NAME: &str = "\\\\.\\pipe\\kptemp";
+
+// How long a launched worker gets to serve the pipe once the user consented
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Full access for SYSTEM and administrators, read and write for the
+// interactive users driving the worker from an unprivileged front end, never
+// for network or anonymous logons. Requests are authorized one by one.
+const PIPE_SDDL: &str = "D:P(D;;GA;;;NU)(D;;GA;;;AN)(A;;GA;;;SY)(A;;GA;;;BA)(A;;GRGW;;;IU)";
+
+pub type Connection = File;
+
+// The next instance waits for its client before the connected one is handed
+// out, the name never goes free for another process to take
+pub struct Listener {
+    descriptor: PSECURITY_DESCRIPTOR,
+    next: HANDLE,
+}
+
+impl Listener {
+    pub fn bind() -> Result<Listener> {
+        let sddl: Vec<u16> = to_utf16(PIPE_SDDL);
+        let mut descriptor: PSECURITY_DESCRIPTOR = null_mut();
+
+        let status: i32 = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1 as DWORD,
+                &mut descriptor,
+                null_mut(),
+            )
+        };
+
+        if status == 0 {
+            return Err(Error::last_os_error());
+        }
+
+        // Fails when another process already serves the name
+        let next: HANDLE = match create_instance(descriptor, true) {
+            Ok(h) => h,
+            Err(e) => {
+                unsafe { LocalFree(descriptor as _); }
+                return Err(e);
+            }
+        };
+
+        Ok(Listener { descriptor, next })
+    }
+
+    // Every client gets its own instance of the pipe
+    pub fn accept(&mut self) -> Result<Connection> {
+        loop {
+            let status: i32 = unsafe { ConnectNamedPipe(self.next, null_mut()) };
+            let error: Error = Error::last_os_error();
+            let handle: HANDLE = self.next;
+
+            self.next = create_instance(self.descriptor, false)?;
+
+            // A client connecting between the two calls is already there, one
+            // that left already is dropped
+            if status != 0 || error.raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32) {
+                return Ok(unsafe { File::from_raw_handle(handle as _) });
+            }
+
+            unsafe { CloseHandle(handle); }
+        }
+    }
+}
+
+fn create_instance(descriptor: PSECURITY_DESCRIPTOR, first: bool) -> Result<HANDLE> {
+    let name: Vec<u16> = to_utf16(PIPE_NAME);
+
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: size_of::<SECURITY_ATTRIBUTES>() as DWORD,
+        lpSecurityDescriptor: descriptor,
+        bInheritHandle: 0,
+    };
+
+    let open_mode: DWORD = match first {
+        true => PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+        false => PIPE_ACCESS_DUPLEX,
+    };
+
+    let handle: HANDLE = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            open_mode,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            4096,
+            4096,
+            0,
+            &mut attributes,
+        )
+    };
+
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(handle)
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.next);
+            LocalFree(self.descriptor as _);
+        }
+    }
+}
+
+pub fn connect() -> Result<Connection> {
+    OpenOptions::new().read(true).write(true).open(PIPE_NAME)
+}
+
+// Starts this program as a worker serving this process alone, elevated once the
+// user consented, and connects to it
+pub fn launch() -> Result<Connection> {
+    let program: Vec<u16> = to_utf16(&current_exe()?.to_string_lossy());
+    let verb: Vec<u16> = to_utf16("runas");
+    let parameters: Vec<u16> = to_utf16(&format!("worker --once {}", process::id()));
+
+    let mut info: SHELLEXECUTEINFOW = unsafe { zeroed() };
+    info.cbSize = size_of::<SHELLEXECUTEINFOW>() as DWORD;
+    info.fMask = SEE_MASK_NOCLOSEPROCESS | SEE_MASK_NOASYNC;
+    info.lpVerb = verb.as_ptr();
+    info.lpFile = program.as_ptr();
+    info.lpParameters = parameters.as_ptr();
+    info.nShow = SW_HIDE;
+
+    // Fails when the user declines
+    if unsafe { ShellExecuteExW(&mut info) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let start: Instant = Instant::now();
+
+    let connection: Result<Connection> = loop {
+        let error: Error = match connect() {
+            Ok(c) => break Ok(c),
+            Err(e) => e,
+        };
+
+        let mut code: DWORD = STILL_ACTIVE;
+
+        if !info.hProcess.is_null() {
+            unsafe { GetExitCodeProcess(info.hProcess, &mut code); }
+        }
+
+        if code != STILL_ACTIVE || start.elapsed() > LAUNCH_TIMEOUT {
+            break Err(error);
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    if !info.hProcess.is_null() {
+        unsafe { CloseHandle(info.hProcess); }
+    }
+
+    connection
+}
+
+// The process at the other end of the connection
+pub fn client_process(connection: &Connection) -> Result<u32> {
+    let mut pid: DWORD = 0;
+
+    if unsafe { GetNamedPipeClientProcessId(connection.as_raw_handle() as HANDLE, &mut pid) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(pid)
+}
+
+fn token_information(token: HANDLE, class: TOKEN_INFORMATION_CLASS) -> Result<Vec<u64>> {
+    let mut size: DWORD = 0;
+
+    unsafe { GetTokenInformation(token, class, null_mut(), 0, &mut size); }
+
+    let mut buffer: Vec<u64> = vec![0; (size as usize).div_ceil(size_of::<u64>())];
+
+    if unsafe { GetTokenInformation(token, class, buffer.as_mut_ptr() as _, size, &mut size) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(buffer)
+}
+
+// SYSTEM or an administrator holding the rights of one: an elevated token or
+// the administrators group enabled. A token UAC filtered only keeps the group
+// for deny and is refused.
+fn is_administrator(token: HANDLE) -> Result<bool> {
+    let user: Vec<u64> = token_information(token, TokenUser)?;
+    let user: &TOKEN_USER = unsafe { &*(user.as_ptr() as *const TOKEN_USER) };
+
+    if unsafe { IsWellKnownSid(user.User.Sid, WinLocalSystemSid) } != 0 {
+        return Ok(true);
+    }
+
+    let elevation: Vec<u64> = token_information(token, TokenElevation)?;
+    let elevation: &TOKEN_ELEVATION = unsafe { &*(elevation.as_ptr() as *const TOKEN_ELEVATION) };
+
+    if elevation.TokenIsElevated != 0 {
+        return Ok(true);
+    }
+
+    let groups: Vec<u64> = token_information(token, TokenGroups)?;
+    let groups: &TOKEN_GROUPS = unsafe { &*(groups.as_ptr() as *const TOKEN_GROUPS) };
+    let groups: &[SID_AND_ATTRIBUTES] = unsafe { from_raw_parts(groups.Groups.as_ptr(), groups.GroupCount as usize) };
+
+    Ok(groups.iter().any(|g| {
+        let enabled: bool = g.Attributes & SE_GROUP_ENABLED != 0 && g.Attributes & SE_GROUP_USE_FOR_DENY_ONLY == 0;
+
+        enabled && unsafe { IsWellKnownSid(g.Sid, WinBuiltinAdministratorsSid) } != 0
+    }))
+}
+
+// Only administrators may scan and clean. The client is impersonated to read
+// its token, which needs a request read from the pipe first.
+pub fn authorized(connection: &Connection) -> Result<bool> {
+    if unsafe { ImpersonateNamedPipeClient(connection.as_raw_handle() as HANDLE) } == 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut token: HANDLE = null_mut();
+    let status: BOOL = unsafe { OpenThreadToken(GetCurrentThread(), TOKEN_QUERY, 1, &mut token) };
+    let error: Error = Error::last_os_error();
+
+    unsafe { RevertToSelf(); }
+
+    if status == 0 {
+        return Err(error);
+    }
+
+    let administrator: Result<bool> = is_administrator(token);
+
+    unsafe { CloseHandle(token); }
+
+    administrator
+}